@@ -0,0 +1,2 @@
+-- NIP-42: 認証済みかつsafelistに登録されたpubkeyにのみREQを許可する
+ALTER TABLE relay_info ADD COLUMN auth_restricted_reads INTEGER NOT NULL DEFAULT 0;  -- Boolean
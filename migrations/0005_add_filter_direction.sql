@@ -0,0 +1,7 @@
+-- フィルタルールの適用方向
+-- inbound: クライアント→バックエンドのEVENT, outbound: バックエンド→クライアントのEVENT, both: 両方
+ALTER TABLE filter_rules ADD COLUMN direction TEXT NOT NULL DEFAULT 'both'
+  CHECK (direction IN ('inbound', 'outbound', 'both'));
+
+-- 拒否ログに方向を記録（既存の行はNULL）
+ALTER TABLE event_rejection_logs ADD COLUMN direction TEXT;
@@ -0,0 +1,12 @@
+-- レート制限設定（シングルトン）
+-- 各値はNULLで無制限
+CREATE TABLE IF NOT EXISTS rate_limits (
+  id INTEGER PRIMARY KEY CHECK (id = 1),
+  events_per_minute_per_ip INTEGER,      -- IPごとのEVENT数/分
+  events_per_minute_per_pubkey INTEGER,  -- pubkeyごとのEVENT数/分
+  reqs_per_minute_per_ip INTEGER,        -- IPごとのREQ数/分
+  max_connections_per_ip INTEGER,        -- IPごとの同時接続数
+  updated_at TEXT NOT NULL DEFAULT (datetime('now'))
+);
+
+INSERT OR IGNORE INTO rate_limits (id) VALUES (1);
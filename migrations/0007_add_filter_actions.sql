@@ -0,0 +1,9 @@
+-- フィルタルールにマッチした時のアクション
+-- drop: 破棄, shadow: クライアントにはOK trueを返すが転送しない, flag: 転送してログに記録,
+-- delay: delay_secs秒保留してから転送
+ALTER TABLE filter_rules ADD COLUMN action TEXT NOT NULL DEFAULT 'drop'
+  CHECK (action IN ('drop', 'shadow', 'flag', 'delay'));
+ALTER TABLE filter_rules ADD COLUMN delay_secs INTEGER;
+
+-- ログに適用したアクションを記録（既存の行はNULL = drop）
+ALTER TABLE event_rejection_logs ADD COLUMN action TEXT;
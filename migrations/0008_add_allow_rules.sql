@@ -0,0 +1,4 @@
+-- フィルタルールの種類
+-- block: マッチしたらactionを適用, allow: マッチしたら以降のルールを評価せず受理（先にマッチしたルールが優先）
+ALTER TABLE filter_rules ADD COLUMN rule_type TEXT NOT NULL DEFAULT 'block'
+  CHECK (rule_type IN ('block', 'allow'));
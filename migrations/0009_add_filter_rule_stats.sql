@@ -0,0 +1,6 @@
+-- フィルタルールごとの評価統計（メモリ上で集計し定期的に加算される）
+ALTER TABLE filter_rules ADD COLUMN hit_count INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE filter_rules ADD COLUMN eval_count INTEGER NOT NULL DEFAULT 0;
+-- 評価時間の合計（ナノ秒）
+ALTER TABLE filter_rules ADD COLUMN eval_time_ns INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE filter_rules ADD COLUMN last_matched_at TEXT;
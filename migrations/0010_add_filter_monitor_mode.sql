@@ -0,0 +1,21 @@
+-- フィルタルールの動作モード
+-- off: 評価しない, monitor: 評価してログに残すだけ（ブロックしない）, enforce: 適用する
+ALTER TABLE filter_rules ADD COLUMN mode TEXT NOT NULL DEFAULT 'enforce'
+  CHECK (mode IN ('off', 'monitor', 'enforce'));
+UPDATE filter_rules SET mode = CASE WHEN enabled = 1 THEN 'enforce' ELSE 'off' END;
+
+-- monitorモードのルールがマッチしたイベント
+CREATE TABLE IF NOT EXISTS filter_monitor_logs (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  rule_id INTEGER NOT NULL,
+  event_id TEXT NOT NULL,
+  pubkey_hex TEXT NOT NULL,
+  npub TEXT NOT NULL,
+  kind INTEGER NOT NULL,
+  content_preview TEXT NOT NULL,
+  direction TEXT NOT NULL,
+  reason TEXT NOT NULL,  -- 'would_block:<id>' / 'would_allow:<id>'
+  created_at TEXT NOT NULL DEFAULT (datetime('now'))
+);
+
+CREATE INDEX IF NOT EXISTS idx_monitor_rule_created ON filter_monitor_logs(rule_id, created_at);
@@ -0,0 +1,10 @@
+-- フィルタのバックテスト用に、プロキシを通過したイベントを直近N件だけ保持する
+CREATE TABLE IF NOT EXISTS event_samples (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  event_id TEXT NOT NULL UNIQUE,
+  pubkey_hex TEXT NOT NULL,
+  kind INTEGER NOT NULL,
+  direction TEXT NOT NULL,  -- 'inbound' / 'outbound'
+  event_json TEXT NOT NULL,
+  created_at TEXT NOT NULL DEFAULT (datetime('now'))
+);
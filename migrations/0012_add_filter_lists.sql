@@ -0,0 +1,11 @@
+-- フィルタクエリから @name で参照する名前付きリスト
+-- items_json: JSON配列（pubkeysはnpub、kindsは数値、words/domainsは文字列）
+CREATE TABLE IF NOT EXISTS filter_lists (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  name TEXT NOT NULL UNIQUE,
+  list_type TEXT NOT NULL CHECK (list_type IN ('pubkeys', 'words', 'domains', 'kinds')),
+  description TEXT NOT NULL DEFAULT '',
+  items_json TEXT NOT NULL DEFAULT '[]',
+  created_at TEXT NOT NULL DEFAULT (datetime('now')),
+  updated_at TEXT NOT NULL DEFAULT (datetime('now'))
+);
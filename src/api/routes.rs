@@ -1,14 +1,34 @@
 use axum::{
-    extract::{Path, State},
+    extract::{FromRef, Path, State},
     routing::{delete, get, post, put},
     Json, Router,
 };
 use serde::{Deserialize, Serialize};
 use sqlx::SqlitePool;
 
-use crate::{auth, parser::filter_query};
+use crate::{auth, nostr::event::Event, filter::{engine::{Direction, Explanation, FilterEngine}, event_samples::{self, BacktestResult}, kind1_cache::Kind1CacheStats, lists}, parser::filter_query};
 
-pub fn router(pool: SqlitePool) -> Router {
+/// State shared by the admin API handlers
+#[derive(Clone)]
+pub struct AppState {
+    pub pool: SqlitePool,
+    /// The proxy's process-wide engine, reloaded when filter rules change
+    pub filter_engine: FilterEngine,
+}
+
+impl FromRef<AppState> for SqlitePool {
+    fn from_ref(state: &AppState) -> Self {
+        state.pool.clone()
+    }
+}
+
+impl FromRef<AppState> for FilterEngine {
+    fn from_ref(state: &AppState) -> Self {
+        state.filter_engine.clone()
+    }
+}
+
+pub fn router(state: AppState) -> Router {
     Router::new()
         .route("/relay", get(get_relays).put(put_relays))
         .route("/safelist", get(list_safelist).post(upsert_safelist))
@@ -18,6 +38,12 @@ pub fn router(pool: SqlitePool) -> Router {
         .route("/filters", get(list_filters).post(create_filter))
         .route("/filters/:id", put(update_filter).delete(delete_filter))
         .route("/filters/validate", post(validate_filter))
+        .route("/filters/format", post(format_filter))
+        .route("/filters/backtest", post(backtest_filter))
+        .route("/filters/explain", post(explain_filter))
+        .route("/filters/:id/would-block", get(get_filter_would_block))
+        .route("/lists", get(list_filter_lists).post(create_filter_list))
+        .route("/lists/:id", put(update_filter_list).delete(delete_filter_list))
         .route("/ip-access-control", get(list_ip_access_control).post(create_ip_access_control))
         .route("/ip-access-control/:id", put(update_ip_access_control).delete(delete_ip_access_control))
         .route("/req-kind-blacklist", get(list_req_kind_blacklist).post(create_req_kind_blacklist))
@@ -25,9 +51,11 @@ pub fn router(pool: SqlitePool) -> Router {
         .route("/connection-logs", get(get_connection_logs))
         .route("/event-rejection-logs", get(get_event_rejection_logs))
         .route("/stats", get(get_stats))
+        .route("/cache/kind1", get(get_kind1_cache_stats))
         .route("/relay-info", get(get_relay_info).put(put_relay_info))
-        .with_state(pool.clone())
-        .layer(axum::middleware::from_fn_with_state(pool, auth::basic_auth))
+        .route("/rate-limits", get(get_rate_limits).put(put_rate_limits))
+        .with_state(state.clone())
+        .layer(axum::middleware::from_fn_with_state(state.pool, auth::basic_auth))
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -128,25 +156,73 @@ pub struct FilterRow {
     pub nl_text: String,
     pub parsed_json: String,
     pub enabled: bool,
+    /// off / monitor / enforce
+    pub mode: String,
     pub rule_order: i64,
+    /// inbound / outbound / both
+    pub direction: String,
+    /// drop / shadow / flag / delay
+    pub action: String,
+    /// Hold time for `delay`
+    pub delay_secs: Option<i64>,
+    /// block / allow
+    pub rule_type: String,
+    /// Events this rule matched (it was the first matching rule)
+    pub hit_count: i64,
+    /// Events this rule was evaluated against
+    pub eval_count: i64,
+    /// Mean evaluation time in microseconds
+    pub avg_eval_us: f64,
+    pub last_matched_at: Option<String>,
+}
+
+/// Mean evaluation time in microseconds from the stored totals
+fn avg_eval_us(eval_time_ns: i64, eval_count: i64) -> f64 {
+    if eval_count == 0 {
+        0.0
+    } else {
+        eval_time_ns as f64 / eval_count as f64 / 1000.0
+    }
+}
+
+/// メモリ上のルール統計をDBへ書き出してから読む
+async fn flush_rule_stats(engine: &FilterEngine, pool: &SqlitePool) {
+    if let Err(e) = engine.flush_rule_stats(pool).await {
+        tracing::error!(error = %e, "Failed to flush filter rule stats");
+    }
 }
 
-async fn list_filters(State(pool): State<SqlitePool>) -> Json<Vec<FilterRow>> {
-    let rows = sqlx::query_as::<_, (i64, String, String, String, i64, i64)>(
-        "SELECT id, name, nl_text, parsed_json, enabled, rule_order FROM filter_rules ORDER BY rule_order ASC, id ASC",
+async fn list_filters(
+    State(pool): State<SqlitePool>,
+    State(engine): State<FilterEngine>,
+) -> Json<Vec<FilterRow>> {
+    flush_rule_stats(&engine, &pool).await;
+    let rows = sqlx::query_as::<_, (i64, String, String, String, String, i64, String, String, Option<i64>, String, i64, i64, i64, Option<String>)>(
+        "SELECT id, name, nl_text, parsed_json, mode, rule_order, direction, action, delay_secs, rule_type,
+                hit_count, eval_count, eval_time_ns, last_matched_at
+         FROM filter_rules ORDER BY rule_order ASC, id ASC",
     )
     .fetch_all(&pool)
     .await
     .unwrap_or_default();
     Json(
         rows.into_iter()
-            .map(|(id, name, nl_text, parsed_json, enabled, rule_order)| FilterRow {
+            .map(|(id, name, nl_text, parsed_json, mode, rule_order, direction, action, delay_secs, rule_type, hit_count, eval_count, eval_time_ns, last_matched_at)| FilterRow {
                 id,
                 name,
                 nl_text,
                 parsed_json,
-                enabled: enabled != 0,
+                enabled: mode != "off",
+                mode,
                 rule_order,
+                direction,
+                action,
+                delay_secs,
+                rule_type,
+                hit_count,
+                eval_count,
+                avg_eval_us: avg_eval_us(eval_time_ns, eval_count),
+                last_matched_at,
             })
             .collect(),
     )
@@ -156,6 +232,77 @@ async fn list_filters(State(pool): State<SqlitePool>) -> Json<Vec<FilterRow>> {
 pub struct CreateFilterBody {
     pub name: String,
     pub nl_text: String,
+    #[serde(default = "default_filter_direction")]
+    pub direction: String,
+    #[serde(default = "default_filter_action")]
+    pub action: String,
+    #[serde(default)]
+    pub delay_secs: Option<i64>,
+    #[serde(default = "default_filter_rule_type")]
+    pub rule_type: String,
+    #[serde(default = "default_filter_mode")]
+    pub mode: String,
+}
+
+fn default_filter_direction() -> String {
+    "both".to_string()
+}
+
+fn default_filter_action() -> String {
+    "drop".to_string()
+}
+
+fn default_filter_rule_type() -> String {
+    "block".to_string()
+}
+
+fn default_filter_mode() -> String {
+    "enforce".to_string()
+}
+
+/// Reject unknown `mode` values before they hit the CHECK constraint
+fn validate_filter_mode(mode: &str) -> Result<(), String> {
+    match mode {
+        "off" | "monitor" | "enforce" => Ok(()),
+        other => Err(format!("Invalid mode: {} (expected off, monitor or enforce)", other)),
+    }
+}
+
+/// Longest hold allowed for the `delay` action
+const MAX_FILTER_DELAY_SECS: i64 = 3600;
+
+/// Reject unknown `direction` values before they hit the CHECK constraint
+fn validate_filter_direction(direction: &str) -> Result<(), String> {
+    match direction {
+        "inbound" | "outbound" | "both" => Ok(()),
+        other => Err(format!("Invalid direction: {} (expected inbound, outbound or both)", other)),
+    }
+}
+
+/// Check `rule_type`, `action` and `delay_secs` before they hit the CHECK constraints.
+///
+/// Returns the `action` and `delay_secs` to store (`delay_secs` is only kept
+/// for `delay`). Allow rules have no action and are stored as `drop`.
+fn validate_filter_action(rule_type: &str, action: &str, delay_secs: Option<i64>) -> Result<(String, Option<i64>), String> {
+    match rule_type {
+        "allow" => return Ok(("drop".to_string(), None)),
+        "block" => {}
+        other => return Err(format!("Invalid rule_type: {} (expected block or allow)", other)),
+    }
+    let delay_secs = match action {
+        "drop" | "shadow" | "flag" => None,
+        "delay" => match delay_secs {
+            Some(secs) if (1..=MAX_FILTER_DELAY_SECS).contains(&secs) => Some(secs),
+            _ => return Err(format!("delay requires delay_secs between 1 and {}", MAX_FILTER_DELAY_SECS)),
+        },
+        other => {
+            return Err(format!(
+                "Invalid action: {} (expected drop, shadow, flag or delay)",
+                other
+            ))
+        }
+    };
+    Ok((action.to_string(), delay_secs))
 }
 
 /// Response for filter creation/update operations
@@ -168,9 +315,28 @@ pub struct FilterResponse {
     pub id: Option<i64>,
 }
 
-async fn create_filter(State(pool): State<SqlitePool>, Json(body): Json<CreateFilterBody>) -> Json<FilterResponse> {
+/// `@name` で参照できるリスト（読み込めなければ空）
+async fn load_filter_lists(pool: &SqlitePool) -> filter_query::FilterLists {
+    lists::load(pool).await.unwrap_or_else(|e| {
+        tracing::error!(error = %e, "Failed to load filter lists");
+        Default::default()
+    })
+}
+
+/// ルール変更をプロキシのフィルタエンジンへ即時反映する
+async fn reload_filter_engine(engine: &FilterEngine, pool: &SqlitePool) {
+    if let Err(e) = engine.reload_rules(pool).await {
+        tracing::error!(error = %e, "Failed to reload filter rules");
+    }
+}
+
+async fn create_filter(
+    State(pool): State<SqlitePool>,
+    State(engine): State<FilterEngine>,
+    Json(body): Json<CreateFilterBody>,
+) -> Json<FilterResponse> {
     // Validate DSL query
-    let validation = filter_query::validate(&body.nl_text);
+    let validation = filter_query::validate_with_lists(&body.nl_text, &load_filter_lists(&pool).await);
     if !validation.valid {
         return Json(FilterResponse {
             success: false,
@@ -178,19 +344,43 @@ async fn create_filter(State(pool): State<SqlitePool>, Json(body): Json<CreateFi
             id: None,
         });
     }
+    if let Err(e) = validate_filter_direction(&body.direction).and_then(|_| validate_filter_mode(&body.mode)) {
+        return Json(FilterResponse {
+            success: false,
+            error: Some(e),
+            id: None,
+        });
+    }
+    let (action, delay_secs) = match validate_filter_action(&body.rule_type, &body.action, body.delay_secs) {
+        Ok(stored) => stored,
+        Err(e) => {
+            return Json(FilterResponse {
+                success: false,
+                error: Some(e),
+                id: None,
+            });
+        }
+    };
     
     // Store DSL query directly (nl_text contains the DSL query, parsed_json also stores it for filtering)
     match sqlx::query(
-        "INSERT INTO filter_rules (name, nl_text, parsed_json, enabled, rule_order) VALUES (?, ?, ?, 1, 0)",
+        "INSERT INTO filter_rules (name, nl_text, parsed_json, enabled, mode, rule_order, direction, action, delay_secs, rule_type) VALUES (?, ?, ?, ?, ?, 0, ?, ?, ?, ?)",
     )
     .bind(&body.name)
     .bind(&body.nl_text)  // DSL query
     .bind(&body.nl_text)  // Store same DSL query in parsed_json for FilterEngine
+    .bind(body.mode != "off")
+    .bind(&body.mode)
+    .bind(&body.direction)
+    .bind(&action)
+    .bind(delay_secs)
+    .bind(&body.rule_type)
     .execute(&pool)
     .await {
         Ok(result) => {
             let id = result.last_insert_rowid();
             tracing::info!(name = %body.name, id = id, "Created filter rule");
+            reload_filter_engine(&engine, &pool).await;
             Json(FilterResponse {
                 success: true,
                 error: None,
@@ -213,16 +403,29 @@ pub struct UpdateFilterBody {
     pub name: String,
     pub nl_text: String,
     pub enabled: bool,
+    /// off / monitor / enforce; takes precedence over `enabled` when given
+    #[serde(default)]
+    pub mode: Option<String>,
     pub rule_order: i64,
+    /// Omitted fields keep their stored value
+    #[serde(default)]
+    pub direction: Option<String>,
+    #[serde(default)]
+    pub action: Option<String>,
+    #[serde(default)]
+    pub delay_secs: Option<i64>,
+    #[serde(default)]
+    pub rule_type: Option<String>,
 }
 
 async fn update_filter(
     State(pool): State<SqlitePool>,
+    State(engine): State<FilterEngine>,
     Path(id): Path<i64>,
     Json(body): Json<UpdateFilterBody>,
 ) -> Json<FilterResponse> {
     // Validate DSL query
-    let validation = filter_query::validate(&body.nl_text);
+    let validation = filter_query::validate_with_lists(&body.nl_text, &load_filter_lists(&pool).await);
     if !validation.valid {
         return Json(FilterResponse {
             success: false,
@@ -230,21 +433,78 @@ async fn update_filter(
             id: Some(id),
         });
     }
+    // 省略されたdirection/action/rule_typeは保存済みの値を引き継ぐ
+    let stored = sqlx::query_as::<_, (String, String, Option<i64>, String)>(
+        "SELECT direction, action, delay_secs, rule_type FROM filter_rules WHERE id = ?",
+    )
+    .bind(id)
+    .fetch_optional(&pool)
+    .await;
+    let (stored_direction, stored_action, stored_delay_secs, stored_rule_type) = match stored {
+        Ok(Some(row)) => row,
+        Ok(None) => {
+            return Json(FilterResponse {
+                success: false,
+                error: Some("Filter not found".to_string()),
+                id: Some(id),
+            });
+        }
+        Err(e) => {
+            tracing::error!(error = %e, id = id, "Failed to load filter rule");
+            return Json(FilterResponse {
+                success: false,
+                error: Some(format!("Database error: {}", e)),
+                id: Some(id),
+            });
+        }
+    };
+    let direction = body.direction.clone().unwrap_or(stored_direction);
+    let rule_type = body.rule_type.clone().unwrap_or(stored_rule_type);
+    let requested_action = body.action.clone().unwrap_or(stored_action);
+    let requested_delay_secs = body.delay_secs.or(stored_delay_secs);
+    // modeの指定がなければenabledから決める
+    let mode = body
+        .mode
+        .clone()
+        .unwrap_or_else(|| if body.enabled { "enforce" } else { "off" }.to_string());
+    if let Err(e) = validate_filter_direction(&direction).and_then(|_| validate_filter_mode(&mode)) {
+        return Json(FilterResponse {
+            success: false,
+            error: Some(e),
+            id: Some(id),
+        });
+    }
+    let (action, delay_secs) = match validate_filter_action(&rule_type, &requested_action, requested_delay_secs) {
+        Ok(stored) => stored,
+        Err(e) => {
+            return Json(FilterResponse {
+                success: false,
+                error: Some(e),
+                id: Some(id),
+            });
+        }
+    };
     
-    let enabled = if body.enabled { 1i64 } else { 0i64 };
+    let enabled = if mode != "off" { 1i64 } else { 0i64 };
     match sqlx::query(
-        "UPDATE filter_rules SET name = ?, nl_text = ?, parsed_json = ?, enabled = ?, rule_order = ?, updated_at = datetime('now') WHERE id = ?",
+        "UPDATE filter_rules SET name = ?, nl_text = ?, parsed_json = ?, enabled = ?, mode = ?, rule_order = ?, direction = ?, action = ?, delay_secs = ?, rule_type = ?, updated_at = datetime('now') WHERE id = ?",
     )
     .bind(&body.name)
     .bind(&body.nl_text)  // DSL query
     .bind(&body.nl_text)  // Store same DSL query in parsed_json
     .bind(enabled)
+    .bind(&mode)
     .bind(body.rule_order)
+    .bind(&direction)
+    .bind(&action)
+    .bind(delay_secs)
+    .bind(&rule_type)
     .bind(id)
     .execute(&pool)
     .await {
         Ok(_) => {
             tracing::info!(name = %body.name, id = id, "Updated filter rule");
+            reload_filter_engine(&engine, &pool).await;
             Json(FilterResponse {
                 success: true,
                 error: None,
@@ -262,11 +522,16 @@ async fn update_filter(
     }
 }
 
-async fn delete_filter(State(pool): State<SqlitePool>, Path(id): Path<i64>) -> Json<()> {
+async fn delete_filter(
+    State(pool): State<SqlitePool>,
+    State(engine): State<FilterEngine>,
+    Path(id): Path<i64>,
+) -> Json<()> {
     let _ = sqlx::query("DELETE FROM filter_rules WHERE id = ?")
         .bind(id)
         .execute(&pool)
         .await;
+    reload_filter_engine(&engine, &pool).await;
     Json(())
 }
 
@@ -277,8 +542,218 @@ pub struct ValidateFilterBody {
     pub query: String,
 }
 
-async fn validate_filter(Json(body): Json<ValidateFilterBody>) -> Json<filter_query::ValidationResult> {
-    Json(filter_query::validate(&body.query))
+async fn validate_filter(
+    State(pool): State<SqlitePool>,
+    Json(body): Json<ValidateFilterBody>,
+) -> Json<filter_query::ValidationResult> {
+    Json(filter_query::validate_with_lists(&body.query, &load_filter_lists(&pool).await))
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FormatResponse {
+    pub valid: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub formatted: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub position: Option<usize>,
+}
+
+/// クエリを正規形に整形する（括弧は必要なものだけ残し、コメントは落とす）
+async fn format_filter(Json(body): Json<ValidateFilterBody>) -> Json<FormatResponse> {
+    Json(match filter_query::format(&body.query) {
+        Ok(formatted) => FormatResponse { valid: true, formatted: Some(formatted), error: None, position: None },
+        Err(e) => FormatResponse { valid: false, formatted: None, error: Some(e.message), position: Some(e.position) },
+    })
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BacktestFilterBody {
+    pub query: String,
+    /// inbound / outbound / both (default both)
+    #[serde(default = "default_filter_direction")]
+    pub direction: String,
+    /// Maximum matched events returned per list (default 20)
+    #[serde(default)]
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BacktestResponse {
+    pub valid: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub position: Option<usize>,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    pub result: Option<BacktestResult>,
+}
+
+impl BacktestResponse {
+    fn error(error: String, position: Option<usize>) -> Self {
+        Self { valid: false, error: Some(error), position, result: None }
+    }
+}
+
+/// 直近に通過したイベントのサンプルに対してクエリを試す
+async fn backtest_filter(
+    State(pool): State<SqlitePool>,
+    State(engine): State<FilterEngine>,
+    Json(body): Json<BacktestFilterBody>,
+) -> Json<BacktestResponse> {
+    let direction = match body.direction.as_str() {
+        "inbound" => Some(Direction::Inbound),
+        "outbound" => Some(Direction::Outbound),
+        "both" => None,
+        other => {
+            return Json(BacktestResponse::error(
+                format!("Invalid direction: {} (expected inbound, outbound or both)", other),
+                None,
+            ))
+        }
+    };
+    let filter = match filter_query::compile_with_lists(&body.query, &load_filter_lists(&pool).await) {
+        Ok(filter) => filter,
+        Err(e) => return Json(BacktestResponse::error(e.message, Some(e.position))),
+    };
+    // バッファ中のサンプルも対象にする
+    if let Err(e) = engine.flush_event_samples(&pool).await {
+        tracing::error!(error = %e, "Failed to flush event samples");
+    }
+    match event_samples::backtest(&pool, &filter, direction, body.limit.unwrap_or(20)).await {
+        Ok(result) => Json(BacktestResponse { valid: true, error: None, position: None, result: Some(result) }),
+        Err(e) => Json(BacktestResponse::error(e.to_string(), None)),
+    }
+}
+
+fn default_explain_direction() -> String {
+    "inbound".to_string()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExplainFilterBody {
+    pub event: Event,
+    /// inbound (client -> relay, default) / outbound (relay -> client)
+    #[serde(default = "default_explain_direction")]
+    pub direction: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ExplainFilterResponse {
+    pub success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    pub explanation: Option<Explanation>,
+}
+
+/// イベント1件がどのチェック・ルールでどう判定されるかを返す
+async fn explain_filter(
+    State(pool): State<SqlitePool>,
+    State(engine): State<FilterEngine>,
+    Json(body): Json<ExplainFilterBody>,
+) -> Json<ExplainFilterResponse> {
+    let direction = match body.direction.as_str() {
+        "inbound" => Direction::Inbound,
+        "outbound" => Direction::Outbound,
+        other => {
+            return Json(ExplainFilterResponse {
+                success: false,
+                error: Some(format!("Invalid direction: {} (expected inbound or outbound)", other)),
+                explanation: None,
+            })
+        }
+    };
+    match engine.explain_event(&pool, &body.event, direction).await {
+        Ok(explanation) => Json(ExplainFilterResponse { success: true, error: None, explanation: Some(explanation) }),
+        Err(e) => Json(ExplainFilterResponse { success: false, error: Some(e.to_string()), explanation: None }),
+    }
+}
+
+// Monitor mode
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WouldBlockQuery {
+    /// Look-back window in hours (default 24, max 720)
+    #[serde(default)]
+    pub hours: Option<i64>,
+    #[serde(default)]
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MonitorLogRow {
+    pub id: i64,
+    pub event_id: String,
+    pub pubkey_hex: String,
+    pub npub: String,
+    pub kind: i64,
+    pub content_preview: String,
+    pub direction: String,
+    /// would_block:<id> / would_allow:<id>
+    pub reason: String,
+    pub created_at: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WouldBlockResponse {
+    pub rule_id: i64,
+    pub hours: i64,
+    /// Matches in the window (`events` is capped by `limit`)
+    pub total: i64,
+    pub events: Vec<MonitorLogRow>,
+}
+
+/// monitorモードのルールが直近N時間にブロックしていたはずのイベント
+async fn get_filter_would_block(
+    State(pool): State<SqlitePool>,
+    Path(id): Path<i64>,
+    axum::extract::Query(params): axum::extract::Query<WouldBlockQuery>,
+) -> Json<WouldBlockResponse> {
+    let hours = params.hours.unwrap_or(24).clamp(1, 720);
+    let limit = params.limit.unwrap_or(100).clamp(1, 1000);
+    let since = format!("-{} hours", hours);
+    let total: (i64,) = sqlx::query_as(
+        "SELECT COUNT(*) FROM filter_monitor_logs WHERE rule_id = ? AND created_at >= datetime('now', ?)",
+    )
+    .bind(id)
+    .bind(&since)
+    .fetch_one(&pool)
+    .await
+    .unwrap_or((0,));
+    let rows = sqlx::query_as::<_, (i64, String, String, String, i64, String, String, String, String)>(
+        "SELECT id, event_id, pubkey_hex, npub, kind, content_preview, direction, reason, created_at
+         FROM filter_monitor_logs
+         WHERE rule_id = ? AND created_at >= datetime('now', ?)
+         ORDER BY created_at DESC, id DESC
+         LIMIT ?",
+    )
+    .bind(id)
+    .bind(&since)
+    .bind(limit)
+    .fetch_all(&pool)
+    .await
+    .unwrap_or_default();
+    Json(WouldBlockResponse {
+        rule_id: id,
+        hours,
+        total: total.0,
+        events: rows
+            .into_iter()
+            .map(|(id, event_id, pubkey_hex, npub, kind, content_preview, direction, reason, created_at)| MonitorLogRow {
+                id,
+                event_id,
+                pubkey_hex,
+                npub,
+                kind,
+                content_preview,
+                direction,
+                reason,
+                created_at,
+            })
+            .collect(),
+    })
 }
 
 // IP管理エンドポイント
@@ -393,6 +868,173 @@ async fn unban_npub(State(pool): State<SqlitePool>, Path(npub): Path<String>) ->
     Json(())
 }
 
+// 名前付きリストエンドポイント
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FilterListRow {
+    pub id: i64,
+    pub name: String,
+    pub list_type: String,
+    pub description: String,
+    pub items: Vec<filter_query::Value>,
+    pub updated_at: String,
+}
+
+async fn list_filter_lists(State(pool): State<SqlitePool>) -> Json<Vec<FilterListRow>> {
+    let rows = sqlx::query_as::<_, (i64, String, String, String, String, String)>(
+        "SELECT id, name, list_type, description, items_json, updated_at FROM filter_lists ORDER BY name",
+    )
+    .fetch_all(&pool)
+    .await
+    .unwrap_or_default();
+    Json(
+        rows.into_iter()
+            .map(|(id, name, list_type, description, items_json, updated_at)| FilterListRow {
+                id,
+                name,
+                list_type,
+                description,
+                items: serde_json::from_str(&items_json).unwrap_or_default(),
+                updated_at,
+            })
+            .collect(),
+    )
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FilterListBody {
+    pub name: String,
+    /// pubkeys / words / domains / kinds
+    pub list_type: String,
+    #[serde(default)]
+    pub description: String,
+    /// Strings, or numbers for kinds
+    #[serde(default)]
+    pub items: Vec<serde_json::Value>,
+}
+
+/// Validated name and normalized `items_json` of a list body
+fn validate_filter_list(body: &FilterListBody) -> Result<String, String> {
+    lists::validate_name(&body.name)?;
+    let items = lists::normalize_items(&body.list_type, &body.items)?;
+    serde_json::to_string(&items).map_err(|e| e.to_string())
+}
+
+async fn create_filter_list(
+    State(pool): State<SqlitePool>,
+    State(engine): State<FilterEngine>,
+    Json(body): Json<FilterListBody>,
+) -> Json<FilterResponse> {
+    let items_json = match validate_filter_list(&body) {
+        Ok(items_json) => items_json,
+        Err(e) => return Json(FilterResponse { success: false, error: Some(e), id: None }),
+    };
+    match sqlx::query("INSERT INTO filter_lists (name, list_type, description, items_json) VALUES (?, ?, ?, ?)")
+        .bind(&body.name)
+        .bind(&body.list_type)
+        .bind(&body.description)
+        .bind(&items_json)
+        .execute(&pool)
+        .await
+    {
+        Ok(result) => {
+            let id = result.last_insert_rowid();
+            tracing::info!(name = %body.name, id = id, "Created filter list");
+            // 未定義のリストを参照していたルールが有効になる
+            reload_filter_engine(&engine, &pool).await;
+            Json(FilterResponse { success: true, error: None, id: Some(id) })
+        }
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to create filter list");
+            Json(FilterResponse { success: false, error: Some(format!("Database error: {}", e)), id: None })
+        }
+    }
+}
+
+/// Error if filter rules still reference `@name`
+async fn ensure_list_unreferenced(pool: &SqlitePool, name: &str) -> Result<(), String> {
+    let rules = lists::referencing_rules(pool, name).await.map_err(|e| format!("Database error: {}", e))?;
+    if rules.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("List @{} is used by filter rules: {}", name, rules.join(", ")))
+    }
+}
+
+async fn filter_list_name(pool: &SqlitePool, id: i64) -> Option<String> {
+    sqlx::query_as::<_, (String,)>("SELECT name FROM filter_lists WHERE id = ?")
+        .bind(id)
+        .fetch_optional(pool)
+        .await
+        .ok()
+        .flatten()
+        .map(|(name,)| name)
+}
+
+async fn update_filter_list(
+    State(pool): State<SqlitePool>,
+    State(engine): State<FilterEngine>,
+    Path(id): Path<i64>,
+    Json(body): Json<FilterListBody>,
+) -> Json<FilterResponse> {
+    let items_json = match validate_filter_list(&body) {
+        Ok(items_json) => items_json,
+        Err(e) => return Json(FilterResponse { success: false, error: Some(e), id: Some(id) }),
+    };
+    let Some(current_name) = filter_list_name(&pool, id).await else {
+        return Json(FilterResponse { success: false, error: Some("List not found".to_string()), id: Some(id) });
+    };
+    // 参照中のリストは名前を変えられない
+    if current_name != body.name {
+        if let Err(e) = ensure_list_unreferenced(&pool, &current_name).await {
+            return Json(FilterResponse { success: false, error: Some(e), id: Some(id) });
+        }
+    }
+    match sqlx::query(
+        "UPDATE filter_lists SET name = ?, list_type = ?, description = ?, items_json = ?, updated_at = datetime('now') WHERE id = ?",
+    )
+    .bind(&body.name)
+    .bind(&body.list_type)
+    .bind(&body.description)
+    .bind(&items_json)
+    .bind(id)
+    .execute(&pool)
+    .await
+    {
+        Ok(_) => {
+            tracing::info!(name = %body.name, id = id, "Updated filter list");
+            // 参照しているルールを新しい内容で再コンパイルする
+            reload_filter_engine(&engine, &pool).await;
+            Json(FilterResponse { success: true, error: None, id: Some(id) })
+        }
+        Err(e) => {
+            tracing::error!(error = %e, id = id, "Failed to update filter list");
+            Json(FilterResponse { success: false, error: Some(format!("Database error: {}", e)), id: Some(id) })
+        }
+    }
+}
+
+async fn delete_filter_list(
+    State(pool): State<SqlitePool>,
+    State(engine): State<FilterEngine>,
+    Path(id): Path<i64>,
+) -> Json<FilterResponse> {
+    let Some(name) = filter_list_name(&pool, id).await else {
+        return Json(FilterResponse { success: false, error: Some("List not found".to_string()), id: Some(id) });
+    };
+    if let Err(e) = ensure_list_unreferenced(&pool, &name).await {
+        return Json(FilterResponse { success: false, error: Some(e), id: Some(id) });
+    }
+    match sqlx::query("DELETE FROM filter_lists WHERE id = ?").bind(id).execute(&pool).await {
+        Ok(_) => {
+            tracing::info!(name = %name, id = id, "Deleted filter list");
+            reload_filter_engine(&engine, &pool).await;
+            Json(FilterResponse { success: true, error: None, id: Some(id) })
+        }
+        Err(e) => Json(FilterResponse { success: false, error: Some(format!("Database error: {}", e)), id: Some(id) }),
+    }
+}
+
 // REQ Kindブラックリストエンドポイント
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -547,6 +1189,10 @@ pub struct EventRejectionLogRow {
     pub kind: i64,
     pub reason: String,
     pub created_at: String,
+    /// inbound / outbound（方向記録前のログはNULL）
+    pub direction: Option<String>,
+    /// 適用したアクション（記録前のログはNULL = drop）
+    pub action: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -563,8 +1209,8 @@ async fn get_event_rejection_logs(
 ) -> Json<Vec<EventRejectionLogRow>> {
     let limit = params.limit.unwrap_or(100);
     let offset = params.offset.unwrap_or(0);
-    let rows = sqlx::query_as::<_, (i64, String, String, String, Option<String>, i64, String, String)>(
-        "SELECT id, event_id, pubkey_hex, npub, ip_address, kind, reason, created_at 
+    let rows = sqlx::query_as::<_, (i64, String, String, String, Option<String>, i64, String, String, Option<String>, Option<String>)>(
+        "SELECT id, event_id, pubkey_hex, npub, ip_address, kind, reason, created_at, direction, action 
          FROM event_rejection_logs 
          ORDER BY created_at DESC 
          LIMIT ? OFFSET ?",
@@ -576,7 +1222,7 @@ async fn get_event_rejection_logs(
     .unwrap_or_default();
     Json(
         rows.into_iter()
-            .map(|(id, event_id, pubkey_hex, npub, ip_address, kind, reason, created_at)| {
+            .map(|(id, event_id, pubkey_hex, npub, ip_address, kind, reason, created_at, direction, action)| {
                 EventRejectionLogRow {
                     id,
                     event_id,
@@ -586,6 +1232,8 @@ async fn get_event_rejection_logs(
                     kind,
                     reason,
                     created_at,
+                    direction,
+                    action,
                 }
             })
             .collect(),
@@ -600,6 +1248,19 @@ pub struct StatsResponse {
     pub rejections_by_reason: Vec<RejectionReasonCount>,
     pub top_npubs_by_rejections: Vec<NpubRejectionCount>,
     pub top_ips_by_rejections: Vec<IpRejectionCount>,
+    /// Every filter rule with its hit counters, most hits first
+    pub filter_rule_hits: Vec<FilterRuleHitCount>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FilterRuleHitCount {
+    pub id: i64,
+    pub name: String,
+    pub enabled: bool,
+    pub hit_count: i64,
+    pub eval_count: i64,
+    pub avg_eval_us: f64,
+    pub last_matched_at: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -620,7 +1281,10 @@ pub struct IpRejectionCount {
     pub count: i64,
 }
 
-async fn get_stats(State(pool): State<SqlitePool>) -> Json<StatsResponse> {
+async fn get_stats(
+    State(pool): State<SqlitePool>,
+    State(engine): State<FilterEngine>,
+) -> Json<StatsResponse> {
     // 総接続数
     let total_connections: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM connection_logs")
         .fetch_one(&pool)
@@ -675,6 +1339,27 @@ async fn get_stats(State(pool): State<SqlitePool>) -> Json<StatsResponse> {
         .map(|(ip_address, count)| IpRejectionCount { ip_address, count })
         .collect();
 
+    // フィルタルールごとのヒット数（未使用のルールも含む）
+    flush_rule_stats(&engine, &pool).await;
+    let filter_rule_rows = sqlx::query_as::<_, (i64, String, i64, i64, i64, i64, Option<String>)>(
+        "SELECT id, name, enabled, hit_count, eval_count, eval_time_ns, last_matched_at FROM filter_rules ORDER BY hit_count DESC, id ASC",
+    )
+    .fetch_all(&pool)
+    .await
+    .unwrap_or_default();
+    let filter_rule_hits: Vec<FilterRuleHitCount> = filter_rule_rows
+        .into_iter()
+        .map(|(id, name, enabled, hit_count, eval_count, eval_time_ns, last_matched_at)| FilterRuleHitCount {
+            id,
+            name,
+            enabled: enabled != 0,
+            hit_count,
+            eval_count,
+            avg_eval_us: avg_eval_us(eval_time_ns, eval_count),
+            last_matched_at,
+        })
+        .collect();
+
     Json(StatsResponse {
         total_connections: total_connections.0,
         active_connections: active_connections.0,
@@ -682,9 +1367,15 @@ async fn get_stats(State(pool): State<SqlitePool>) -> Json<StatsResponse> {
         rejections_by_reason,
         top_npubs_by_rejections,
         top_ips_by_rejections,
+        filter_rule_hits,
     })
 }
 
+/// 参照イベントキャッシュ（kind1）のサイズとヒット率
+async fn get_kind1_cache_stats(State(engine): State<FilterEngine>) -> Json<Kind1CacheStats> {
+    Json(engine.kind1_cache_stats())
+}
+
 // NIP-11 Relay Information
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -704,18 +1395,21 @@ pub struct RelayInfoRow {
     pub limitation_auth_required: bool,
     pub limitation_payment_required: bool,
     pub icon: Option<String>,
+    /// NIP-42認証済みのsafelistユーザーにのみ読み取りを許可する（NIP-11には出力しない）
+    #[serde(default)]
+    pub auth_restricted_reads: bool,
 }
 
 async fn get_relay_info(State(pool): State<SqlitePool>) -> Json<RelayInfoRow> {
     let row = sqlx::query_as::<_, (
         Option<String>, Option<String>, Option<String>, Option<String>, Option<String>,
         Option<String>, Option<String>, Option<i64>, Option<i64>, Option<i64>,
-        Option<i64>, Option<i64>, i64, i64, Option<String>,
+        Option<i64>, Option<i64>, i64, i64, Option<String>, i64,
     )>(
         "SELECT name, description, pubkey, contact, supported_nips, software, version, 
          limitation_max_message_length, limitation_max_subscriptions, limitation_max_filters,
          limitation_max_event_tags, limitation_max_content_length, limitation_auth_required,
-         limitation_payment_required, icon
+         limitation_payment_required, icon, auth_restricted_reads
          FROM relay_info WHERE id = 1",
     )
     .fetch_optional(&pool)
@@ -727,6 +1421,7 @@ async fn get_relay_info(State(pool): State<SqlitePool>) -> Json<RelayInfoRow> {
             name, description, pubkey, contact, supported_nips,
             software, version, max_msg_len, max_subs, max_filters,
             max_event_tags, max_content_len, auth_required, payment_required, icon,
+            restricted_reads,
         )) => Json(RelayInfoRow {
             name,
             description,
@@ -743,6 +1438,7 @@ async fn get_relay_info(State(pool): State<SqlitePool>) -> Json<RelayInfoRow> {
             limitation_auth_required: auth_required != 0,
             limitation_payment_required: payment_required != 0,
             icon,
+            auth_restricted_reads: restricted_reads != 0,
         }),
         None => Json(RelayInfoRow {
             name: Some("Proxy Nostr Relay".to_string()),
@@ -760,6 +1456,7 @@ async fn get_relay_info(State(pool): State<SqlitePool>) -> Json<RelayInfoRow> {
             limitation_auth_required: false,
             limitation_payment_required: false,
             icon: None,
+            auth_restricted_reads: false,
         }),
     }
 }
@@ -767,13 +1464,14 @@ async fn get_relay_info(State(pool): State<SqlitePool>) -> Json<RelayInfoRow> {
 async fn put_relay_info(State(pool): State<SqlitePool>, Json(body): Json<RelayInfoRow>) -> Json<()> {
     let auth_required = if body.limitation_auth_required { 1i64 } else { 0i64 };
     let payment_required = if body.limitation_payment_required { 1i64 } else { 0i64 };
+    let restricted_reads = if body.auth_restricted_reads { 1i64 } else { 0i64 };
     
     let _ = sqlx::query(
         "INSERT INTO relay_info (id, name, description, pubkey, contact, supported_nips, software, version,
          limitation_max_message_length, limitation_max_subscriptions, limitation_max_filters,
          limitation_max_event_tags, limitation_max_content_length, limitation_auth_required,
-         limitation_payment_required, icon)
-         VALUES (1, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+         limitation_payment_required, icon, auth_restricted_reads)
+         VALUES (1, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
          ON CONFLICT(id) DO UPDATE SET
          name = excluded.name, description = excluded.description, pubkey = excluded.pubkey,
          contact = excluded.contact, supported_nips = excluded.supported_nips, software = excluded.software,
@@ -785,6 +1483,7 @@ async fn put_relay_info(State(pool): State<SqlitePool>, Json(body): Json<RelayIn
          limitation_auth_required = excluded.limitation_auth_required,
          limitation_payment_required = excluded.limitation_payment_required,
          icon = excluded.icon,
+         auth_restricted_reads = excluded.auth_restricted_reads,
          updated_at = datetime('now')",
     )
     .bind(&body.name)
@@ -802,8 +1501,59 @@ async fn put_relay_info(State(pool): State<SqlitePool>, Json(body): Json<RelayIn
     .bind(auth_required)
     .bind(payment_required)
     .bind(&body.icon)
+    .bind(restricted_reads)
     .execute(&pool)
     .await;
     
     Json(())
 }
+
+// Rate Limits
+
+/// 各値はNULLで無制限
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RateLimitsRow {
+    pub events_per_minute_per_ip: Option<i64>,
+    pub events_per_minute_per_pubkey: Option<i64>,
+    pub reqs_per_minute_per_ip: Option<i64>,
+    pub max_connections_per_ip: Option<i64>,
+}
+
+async fn get_rate_limits(State(pool): State<SqlitePool>) -> Json<RateLimitsRow> {
+    let row = sqlx::query_as::<_, (Option<i64>, Option<i64>, Option<i64>, Option<i64>)>(
+        "SELECT events_per_minute_per_ip, events_per_minute_per_pubkey, reqs_per_minute_per_ip, max_connections_per_ip
+         FROM rate_limits WHERE id = 1",
+    )
+    .fetch_optional(&pool)
+    .await
+    .unwrap_or(None)
+    .unwrap_or((None, None, None, None));
+    Json(RateLimitsRow {
+        events_per_minute_per_ip: row.0,
+        events_per_minute_per_pubkey: row.1,
+        reqs_per_minute_per_ip: row.2,
+        max_connections_per_ip: row.3,
+    })
+}
+
+async fn put_rate_limits(State(pool): State<SqlitePool>, Json(body): Json<RateLimitsRow>) -> Json<()> {
+    // 0以下は無制限として扱う
+    let limit = |v: Option<i64>| v.filter(|v| *v > 0);
+    let _ = sqlx::query(
+        "INSERT INTO rate_limits (id, events_per_minute_per_ip, events_per_minute_per_pubkey, reqs_per_minute_per_ip, max_connections_per_ip)
+         VALUES (1, ?, ?, ?, ?)
+         ON CONFLICT(id) DO UPDATE SET
+         events_per_minute_per_ip = excluded.events_per_minute_per_ip,
+         events_per_minute_per_pubkey = excluded.events_per_minute_per_pubkey,
+         reqs_per_minute_per_ip = excluded.reqs_per_minute_per_ip,
+         max_connections_per_ip = excluded.max_connections_per_ip,
+         updated_at = datetime('now')",
+    )
+    .bind(limit(body.events_per_minute_per_ip))
+    .bind(limit(body.events_per_minute_per_pubkey))
+    .bind(limit(body.reqs_per_minute_per_ip))
+    .bind(limit(body.max_connections_per_ip))
+    .execute(&pool)
+    .await;
+    Json(())
+}
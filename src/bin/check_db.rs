@@ -1,11 +1,13 @@
 use sqlx::SqlitePool;
 
+type RejectionLogRow = (i64, String, String, String, Option<String>, i64, String, String);
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let db_url = std::env::var("DATABASE_URL")
         .unwrap_or_else(|_| "sqlite:data/app.sqlite".to_string());
     
-    let pool = sqlx::SqlitePool::connect(&db_url).await?;
+    let pool = SqlitePool::connect(&db_url).await?;
     
     println!("=== Safelist ===");
     let safelist_rows: Vec<(String, i64, String)> = sqlx::query_as(
@@ -27,7 +29,7 @@ async fn main() -> anyhow::Result<()> {
     }
     
     println!("\n=== Event Rejection Logs (last 20) ===");
-    let rejection_rows: Vec<(i64, String, String, String, Option<String>, i64, String, String)> = sqlx::query_as(
+    let rejection_rows: Vec<RejectionLogRow> = sqlx::query_as(
         "SELECT id, event_id, pubkey_hex, npub, ip_address, kind, reason, created_at 
          FROM event_rejection_logs 
          ORDER BY created_at DESC 
@@ -727,8 +727,8 @@ async fn serve_doc(name: &str) -> impl IntoResponse {
 fn extract_title(markdown: &str) -> Option<String> {
     for line in markdown.lines() {
         let trimmed = line.trim();
-        if trimmed.starts_with("# ") {
-            return Some(trimmed[2..].to_string());
+        if let Some(title) = trimmed.strip_prefix("# ") {
+            return Some(title.to_string());
         }
     }
     None
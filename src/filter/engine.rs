@@ -1,26 +1,254 @@
 use anyhow::Context;
+use futures_util::future::BoxFuture;
+use serde::Serialize;
 use sqlx::SqlitePool;
 use std::collections::HashMap;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use tokio::sync::RwLock;
 
 use crate::nostr::event::Event;
-use crate::parser::filter_query::{self, CompiledFilter};
+use crate::parser::filter_query::{self, CompiledFilter, EvalContext, TraceNode};
+use crate::parser::filter_query_ast::extract_fields;
+use super::event_samples::{self, EventSampler};
+use super::kind1_cache::{Kind1Cache, Kind1CacheStats, Kind1Peek};
+use super::rule_stats::RuleStats;
+
+/// Looks up a kind1 by id on the backends when it is not cached
+pub type Kind1Fetcher = Arc<dyn Fn(String) -> BoxFuture<'static, Option<Event>> + Send + Sync>;
+
+/// Which way an event is travelling through the proxy
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Client -> backend EVENT
+    Inbound,
+    /// Backend -> client EVENT
+    Outbound,
+}
+
+impl Direction {
+    /// Value stored in `event_rejection_logs.direction`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Direction::Inbound => "inbound",
+            Direction::Outbound => "outbound",
+        }
+    }
+}
+
+/// What happens to an event a filter rule matched (`filter_rules.action`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RuleAction {
+    /// Discard the event (clients get OK false)
+    Drop,
+    /// Tell the publishing client OK true but never forward the event
+    Shadow,
+    /// Forward the event and record a log row
+    Flag,
+    /// Hold the event for the given time before forwarding it
+    Delay(Duration),
+}
+
+impl RuleAction {
+    /// Value stored in `filter_rules.action` and `event_rejection_logs.action`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RuleAction::Drop => "drop",
+            RuleAction::Shadow => "shadow",
+            RuleAction::Flag => "flag",
+            RuleAction::Delay(_) => "delay",
+        }
+    }
+
+    /// Build the action of a rule row
+    fn from_row(action: &str, delay_secs: Option<i64>) -> Option<Self> {
+        Some(match action {
+            "drop" => RuleAction::Drop,
+            "shadow" => RuleAction::Shadow,
+            "flag" => RuleAction::Flag,
+            "delay" => RuleAction::Delay(Duration::from_secs(delay_secs.filter(|s| *s > 0)? as u64)),
+            _ => return None,
+        })
+    }
+
+    /// Whether the event is kept from its receivers altogether
+    pub fn withholds(&self) -> bool {
+        matches!(self, RuleAction::Drop | RuleAction::Shadow)
+    }
+}
+
+/// Why an event matched and what to do with it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Verdict {
+    /// Logged reason, e.g. `banned_npub` or `filter_rule:3`
+    pub reason: String,
+    pub action: RuleAction,
+}
+
+impl Verdict {
+    fn drop(reason: &str) -> Self {
+        Self { reason: reason.to_string(), action: RuleAction::Drop }
+    }
+}
+
+/// What the proxy does with a backend frame after filtering
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BackendOutcome {
+    /// Send the frame as is
+    Forward,
+    /// Do not send the frame
+    Drop,
+    /// Send the frame after the given time
+    Delay(Duration),
+}
+
+/// How one loaded rule treats an event (`POST /api/filters/explain`)
+#[derive(Debug, Clone, Serialize)]
+pub struct RuleExplanation {
+    pub id: i64,
+    pub name: String,
+    /// block / allow
+    pub rule_type: &'static str,
+    /// monitor / enforce
+    pub mode: &'static str,
+    pub action: &'static str,
+    pub direction: String,
+    /// The rule is evaluated for this direction
+    pub applies: bool,
+    /// No earlier rule decided, so the proxy evaluates this one
+    pub reached: bool,
+    pub matched: bool,
+    /// This rule decided the outcome
+    pub decisive: bool,
+    /// Result of every condition (absent when the rule does not apply)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trace: Option<TraceNode>,
+}
+
+/// The legacy kind6/7 bot filter's view of an event
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct BotFilterExplanation {
+    /// kind 6/7 and no earlier step decided
+    pub applies: bool,
+    /// Author has the safelist filter bypass flag
+    pub filter_bypass: bool,
+    pub referenced_event_id: Option<String>,
+    /// created_at of the referenced kind1, if cached
+    pub referenced_created_at: Option<i64>,
+    pub matched: bool,
+}
+
+/// Every check an event goes through and which one decided
+#[derive(Debug, Clone, Serialize)]
+pub struct Explanation {
+    pub direction: &'static str,
+    pub npub: String,
+    /// Why the id or signature does not verify
+    pub signature_error: Option<String>,
+    /// Checked for backend events only when VERIFY_BACKEND_EVENTS is set
+    pub signature_checked: bool,
+    /// Inbound only: the author may post (safelist `post_allowed`)
+    pub post_allowed: Option<bool>,
+    pub banned_npub: bool,
+    pub kind_blacklisted: bool,
+    pub rules: Vec<RuleExplanation>,
+    pub bot_filter: BotFilterExplanation,
+    /// accept, drop, shadow, flag or delay
+    pub decision: &'static str,
+    /// Logged reason of the decision, e.g. `filter_rule:3`
+    pub reason: Option<String>,
+}
 
 /// Cached compiled filter rule
 struct CachedRule {
     id: i64,
     name: String,
     filter: CompiledFilter,
+    /// `filter_rules.direction`: inbound, outbound or both
+    direction: String,
+    /// Rule reads `referenced_created_at`
+    uses_referenced: bool,
+    /// `rule_type = 'allow'`: a match accepts the event and stops evaluation
+    allow: bool,
+    /// `mode = 'monitor'`: matches are only logged as would_block / would_allow
+    monitor: bool,
+    action: RuleAction,
+}
+
+/// Result of walking the rule list
+enum RuleOutcome {
+    /// No rule matched
+    NoMatch,
+    /// An allow rule matched first
+    Allow,
+    /// A block rule matched first
+    Block(Verdict),
+}
+
+impl CachedRule {
+    fn applies_to(&self, direction: Direction) -> bool {
+        self.direction == "both" || self.direction == direction.as_str()
+    }
+}
+
+/// Compiled rules in evaluation order, indexed by the kinds they can match
+#[derive(Default)]
+struct RuleSet {
+    rules: Vec<CachedRule>,
+    // kind -> positions of the rules limited to a set of kinds that includes it
+    by_kind: HashMap<i64, Vec<usize>>,
+    // positions of the rules that can match any kind
+    any_kind: Vec<usize>,
+}
+
+impl RuleSet {
+    fn new(rules: Vec<CachedRule>) -> Self {
+        let mut by_kind: HashMap<i64, Vec<usize>> = HashMap::new();
+        let mut any_kind = Vec::new();
+        for (position, rule) in rules.iter().enumerate() {
+            match rule.filter.kinds() {
+                Some(kinds) => {
+                    for kind in kinds {
+                        by_kind.entry(*kind).or_default().push(position);
+                    }
+                }
+                None => any_kind.push(position),
+            }
+        }
+        Self { rules, by_kind, any_kind }
+    }
+
+    fn iter(&self) -> std::slice::Iter<'_, CachedRule> {
+        self.rules.iter()
+    }
+
+    /// Rules that can match an event of `kind`, in evaluation order.
+    /// Rules limited to other kinds are skipped without being evaluated.
+    fn candidates(&self, kind: i64) -> Vec<&CachedRule> {
+        let limited = self.by_kind.get(&kind).map(Vec::as_slice).unwrap_or_default();
+        let mut positions: Vec<usize> = limited.iter().chain(&self.any_kind).copied().collect();
+        positions.sort_unstable();
+        positions.into_iter().map(|position| &self.rules[position]).collect()
+    }
 }
 
+/// Filter engine shared by every connection (clones share caches and rules)
+#[derive(Clone)]
 pub struct FilterEngine {
-    // Minimal cache: kind1 event_id -> created_at
-    kind1_created_at_by_id: HashMap<String, i64>,
+    // kind1 event_id -> created_at, filled by every connection
+    kind1_cache: Arc<Mutex<Kind1Cache>>,
+    // Optional backend lookup for referenced events missing from the cache
+    kind1_fetcher: Option<Kind1Fetcher>,
     // Cached compiled filter rules
-    compiled_rules: Arc<RwLock<Vec<CachedRule>>>,
+    compiled_rules: Arc<RwLock<RuleSet>>,
     // Last time rules were loaded
     rules_loaded_at: Arc<RwLock<Option<std::time::Instant>>>,
+    // Verify id and signature of backend events before forwarding
+    verify_signatures: bool,
+    // Per-rule hit/evaluation counts not yet written to the database
+    rule_stats: Arc<Mutex<RuleStats>>,
+    // Recently seen events kept for backtesting, if enabled
+    event_sampler: Option<Arc<Mutex<EventSampler>>>,
 }
 
 /// 拒否ログを記録する
@@ -29,6 +257,8 @@ async fn log_rejection(
     event: &Event,
     reason: &str,
     ip_address: Option<&str>,
+    direction: Direction,
+    action: &RuleAction,
 ) -> anyhow::Result<()> {
     let npub = match pubkey_hex_to_npub(&event.pubkey) {
         Ok(n) => n,
@@ -38,7 +268,7 @@ async fn log_rejection(
         }
     };
     match sqlx::query(
-        "INSERT INTO event_rejection_logs (event_id, pubkey_hex, npub, ip_address, kind, reason) VALUES (?, ?, ?, ?, ?, ?)"
+        "INSERT INTO event_rejection_logs (event_id, pubkey_hex, npub, ip_address, kind, reason, direction, action) VALUES (?, ?, ?, ?, ?, ?, ?, ?)"
     )
     .bind(&event.id)
     .bind(&event.pubkey)
@@ -46,6 +276,8 @@ async fn log_rejection(
     .bind(ip_address)
     .bind(event.kind)
     .bind(reason)
+    .bind(direction.as_str())
+    .bind(action.as_str())
     .execute(pool)
     .await {
         Ok(_) => {
@@ -59,13 +291,149 @@ async fn log_rejection(
     }
 }
 
+/// monitorモードのルールのマッチを記録する（イベントはブロックしない）
+async fn log_monitor_match(
+    pool: &SqlitePool,
+    event: &Event,
+    rule_id: i64,
+    allow: bool,
+    direction: Direction,
+) -> anyhow::Result<()> {
+    const PREVIEW_CHARS: usize = 200;
+    let npub = pubkey_hex_to_npub(&event.pubkey).unwrap_or_else(|_| "unknown".to_string());
+    let reason = if allow {
+        format!("would_allow:{}", rule_id)
+    } else {
+        format!("would_block:{}", rule_id)
+    };
+    let preview: String = event.content.chars().take(PREVIEW_CHARS).collect();
+    sqlx::query(
+        "INSERT INTO filter_monitor_logs (rule_id, event_id, pubkey_hex, npub, kind, content_preview, direction, reason) VALUES (?, ?, ?, ?, ?, ?, ?, ?)"
+    )
+    .bind(rule_id)
+    .bind(&event.id)
+    .bind(&event.pubkey)
+    .bind(&npub)
+    .bind(event.kind)
+    .bind(&preview)
+    .bind(direction.as_str())
+    .bind(&reason)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+impl Default for FilterEngine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl FilterEngine {
     pub fn new() -> Self {
         Self {
-            kind1_created_at_by_id: HashMap::new(),
-            compiled_rules: Arc::new(RwLock::new(Vec::new())),
+            kind1_cache: Arc::new(Mutex::new(Kind1Cache::default())),
+            kind1_fetcher: None,
+            compiled_rules: Arc::new(RwLock::new(RuleSet::default())),
             rules_loaded_at: Arc::new(RwLock::new(None)),
+            verify_signatures: false,
+            rule_stats: Arc::new(Mutex::new(RuleStats::default())),
+            event_sampler: None,
+        }
+    }
+
+    /// Drop backend events whose id or signature does not verify
+    pub fn with_signature_verification(mut self, enabled: bool) -> Self {
+        self.verify_signatures = enabled;
+        self
+    }
+
+    /// Size and age limits of the referenced-event cache (replaces its contents)
+    pub fn with_kind1_cache(mut self, max_entries: usize, ttl: Duration) -> Self {
+        self.kind1_cache = Arc::new(Mutex::new(Kind1Cache::new(max_entries, ttl)));
+        self
+    }
+
+    /// Fetch referenced events missing from the cache instead of letting the
+    /// reaction pass unchecked
+    pub fn with_kind1_fetcher(mut self, fetcher: Kind1Fetcher) -> Self {
+        self.kind1_fetcher = Some(fetcher);
+        self
+    }
+
+    /// Keep every `every`-th checked event in `event_samples` (newest `max_rows`)
+    /// so queries can be backtested. `max_rows = 0` disables sampling.
+    pub fn with_event_sampling(mut self, max_rows: usize, every: u64) -> Self {
+        self.event_sampler = (max_rows > 0).then(|| Arc::new(Mutex::new(EventSampler::new(max_rows, every))));
+        self
+    }
+
+    /// Size and hit counters of the referenced-event cache
+    pub fn kind1_cache_stats(&self) -> Kind1CacheStats {
+        self.kind1_cache.lock().unwrap().stats()
+    }
+
+    /// Add the per-rule counters gathered since the last flush to `filter_rules`
+    pub async fn flush_rule_stats(&self, pool: &SqlitePool) -> anyhow::Result<()> {
+        let pending = self.rule_stats.lock().unwrap().take();
+        for (rule_id, counter) in pending {
+            sqlx::query(
+                "UPDATE filter_rules SET hit_count = hit_count + ?, eval_count = eval_count + ?, eval_time_ns = eval_time_ns + ?, last_matched_at = COALESCE(?, last_matched_at) WHERE id = ?"
+            )
+            .bind(counter.hits as i64)
+            .bind(counter.evaluations as i64)
+            .bind(i64::try_from(counter.eval_time.as_nanos()).unwrap_or(i64::MAX))
+            .bind(counter.last_matched_at.map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string()))
+            .bind(rule_id)
+            .execute(pool)
+            .await?;
+        }
+        Ok(())
+    }
+
+    /// Flush the per-rule counters every `period` in the background
+    pub fn spawn_rule_stats_flush(&self, pool: SqlitePool, period: Duration) {
+        let engine = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(period);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                ticker.tick().await;
+                if let Err(e) = engine.flush_rule_stats(&pool).await {
+                    tracing::error!(error = %e, "Failed to flush filter rule stats");
+                }
+            }
+        });
+    }
+
+    /// Write the sampled events gathered since the last flush to `event_samples`
+    pub async fn flush_event_samples(&self, pool: &SqlitePool) -> anyhow::Result<()> {
+        let Some(sampler) = &self.event_sampler else {
+            return Ok(());
+        };
+        let (events, max_rows) = {
+            let mut sampler = sampler.lock().unwrap();
+            (sampler.take(), sampler.max_rows())
+        };
+        event_samples::store(pool, events, max_rows).await
+    }
+
+    /// Flush the sampled events every `period` in the background
+    pub fn spawn_event_sample_flush(&self, pool: SqlitePool, period: Duration) {
+        if self.event_sampler.is_none() {
+            return;
         }
+        let engine = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(period);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                ticker.tick().await;
+                if let Err(e) = engine.flush_event_samples(&pool).await {
+                    tracing::error!(error = %e, "Failed to flush event samples");
+                }
+            }
+        });
     }
 
     /// Reload filter rules from database if needed (cached for 30 seconds)
@@ -84,22 +452,34 @@ impl FilterEngine {
         Ok(())
     }
 
-    /// Force reload filter rules from database
-    async fn reload_rules(&self, pool: &SqlitePool) -> anyhow::Result<()> {
-        let rows: Vec<(i64, String, String)> = sqlx::query_as(
-            "SELECT id, name, parsed_json FROM filter_rules WHERE enabled = 1 ORDER BY rule_order ASC, id ASC"
+    /// Force reload filter rules from database.
+    ///
+    /// Called by the filter and list APIs after every change so edits apply immediately.
+    pub async fn reload_rules(&self, pool: &SqlitePool) -> anyhow::Result<()> {
+        let rows = sqlx::query_as::<_, (i64, String, String, String, String, Option<i64>, String, String)>(
+            "SELECT id, name, parsed_json, direction, action, delay_secs, rule_type, mode FROM filter_rules WHERE mode != 'off' ORDER BY rule_order ASC, id ASC"
         )
         .fetch_all(pool)
         .await?;
+        let lists = super::lists::load(pool).await?;
         
         let mut new_rules = Vec::new();
         
-        for (id, name, parsed_json) in rows {
+        for (id, name, parsed_json, direction, action, delay_secs, rule_type, mode) in rows {
             // Try to compile as DSL query first, then fall back to legacy format
-            match filter_query::compile(&parsed_json) {
+            match filter_query::compile_with_lists(&parsed_json, &lists) {
                 Ok(filter) => {
                     // ログ削除: ルール読み込みは静かに行う
-                    new_rules.push(CachedRule { id, name, filter });
+                    let uses_referenced = extract_fields(filter.ast())
+                        .iter()
+                        .any(|f| f == "referenced_created_at");
+                    let action = RuleAction::from_row(&action, delay_secs).unwrap_or_else(|| {
+                        tracing::warn!(rule_id = id, action = %action, delay_secs = ?delay_secs, "Invalid filter rule action, using drop");
+                        RuleAction::Drop
+                    });
+                    let allow = rule_type == "allow";
+                    let monitor = mode == "monitor";
+                    new_rules.push(CachedRule { id, name, filter, direction, uses_referenced, allow, monitor, action });
                 }
                 Err(e) => {
                     // エラー時のみログ出力
@@ -110,7 +490,7 @@ impl FilterEngine {
         
         {
             let mut rules = self.compiled_rules.write().await;
-            *rules = new_rules;
+            *rules = RuleSet::new(new_rules);
         }
         
         {
@@ -122,115 +502,371 @@ impl FilterEngine {
         Ok(())
     }
 
-    /// Check event against compiled filter rules
+    /// Check event against compiled filter rules for the given direction.
+    ///
+    /// Rules are tried in `rule_order` and the first match decides, like a
+    /// firewall: an allow rule accepts the event, a block rule returns its verdict.
+    /// Monitored rules never decide; their matches are written to
+    /// `filter_monitor_logs` and evaluation carries on.
     async fn check_filter_rules(
         &self,
         pool: &SqlitePool,
         event: &Event,
-        ip_address: Option<&str>,
-    ) -> anyhow::Result<bool> {
+        direction: Direction,
+    ) -> anyhow::Result<RuleOutcome> {
         // Reload rules if needed
         self.reload_rules_if_needed(pool).await?;
         
-        // Check if user has filter bypass
-        if is_filter_bypass(pool, &event.pubkey).await? {
-            return Ok(false);
-        }
-        
         // Check against all compiled rules
-        let rules = self.compiled_rules.read().await;
-        for rule in rules.iter() {
-            if rule.filter.matches(event, &self.kind1_created_at_by_id) {
-                let reason = format!("filter_rule:{}", rule.id);
-                // ブロック時のみログ出力（重要）
-                let npub = pubkey_hex_to_npub(&event.pubkey).unwrap_or_else(|_| "unknown".to_string());
-                tracing::info!(
-                    event_id = %event.id,
-                    npub = %npub,
-                    rule_id = rule.id,
-                    rule_name = %rule.name,
-                    kind = event.kind,
-                    "Event blocked by filter rule"
-                );
-                log_rejection(pool, event, &reason, ip_address).await?;
-                return Ok(true);
+        let (outcome, monitored) = {
+            let rules = self.compiled_rules.read().await;
+            let mut evaluated = Vec::new();
+            let mut hits = Vec::new();
+            let mut monitored = Vec::new();
+            let mut matched = None;
+            // npub などイベントから導く値はルール間で共有する
+            let cx = EvalContext::new(event, &*self.kind1_cache);
+            for rule in rules.candidates(event.kind).into_iter().filter(|r| r.applies_to(direction)) {
+                let started = Instant::now();
+                let hit = rule.filter.matches_in(&cx);
+                evaluated.push((rule.id, started.elapsed()));
+                if !hit {
+                    continue;
+                }
+                hits.push(rule.id);
+                if rule.monitor {
+                    monitored.push((rule.id, rule.allow));
+                    continue;
+                }
+                matched = Some(rule);
+                break;
+            }
+            self.rule_stats
+                .lock()
+                .unwrap()
+                .record(&evaluated, &hits, chrono::Utc::now());
+
+            let outcome = match matched {
+                None => RuleOutcome::NoMatch,
+                Some(rule) if rule.allow => {
+                    tracing::debug!(event_id = %event.id, rule_id = rule.id, "Event accepted by allow rule");
+                    RuleOutcome::Allow
+                }
+                Some(rule) => {
+                    // ブロック時のみログ出力（重要）
+                    let npub = pubkey_hex_to_npub(&event.pubkey).unwrap_or_else(|_| "unknown".to_string());
+                    tracing::info!(
+                        event_id = %event.id,
+                        npub = %npub,
+                        rule_id = rule.id,
+                        rule_name = %rule.name,
+                        kind = event.kind,
+                        direction = direction.as_str(),
+                        action = rule.action.as_str(),
+                        "Event matched filter rule"
+                    );
+                    RuleOutcome::Block(Verdict {
+                        reason: format!("filter_rule:{}", rule.id),
+                        action: rule.action.clone(),
+                    })
+                }
+            };
+            (outcome, monitored)
+        };
+
+        for (rule_id, allow) in monitored {
+            if let Err(e) = log_monitor_match(pool, event, rule_id, allow, direction).await {
+                tracing::error!(rule_id, error = %e, "Failed to log monitored rule match");
             }
         }
-        
-        Ok(false)
+        Ok(outcome)
     }
 
     pub async fn should_drop_backend_text(
-        &mut self,
+        &self,
         pool: &SqlitePool,
         text: &str,
     ) -> anyhow::Result<bool> {
         self.should_drop_backend_text_with_ip(pool, text, None).await
     }
 
+    /// Whether the frame is withheld entirely (delay still forwards)
     pub async fn should_drop_backend_text_with_ip(
-        &mut self,
+        &self,
         pool: &SqlitePool,
         text: &str,
         ip_address: Option<&str>,
     ) -> anyhow::Result<bool> {
+        Ok(self.filter_backend_text_with_ip(pool, text, ip_address).await? == BackendOutcome::Drop)
+    }
+
+    /// Run a backend frame through the outbound filters and log any match
+    pub async fn filter_backend_text_with_ip(
+        &self,
+        pool: &SqlitePool,
+        text: &str,
+        ip_address: Option<&str>,
+    ) -> anyhow::Result<BackendOutcome> {
         let v: serde_json::Value = match serde_json::from_str(text) {
             Ok(v) => v,
-            Err(_) => return Ok(false), // non-json: ignore
+            Err(_) => return Ok(BackendOutcome::Forward), // non-json: ignore
         };
         let Some(arr) = v.as_array() else {
-            return Ok(false);
+            return Ok(BackendOutcome::Forward);
         };
         if arr.first().and_then(|v| v.as_str()) != Some("EVENT") {
-            return Ok(false);
+            return Ok(BackendOutcome::Forward);
         }
 
         // ["EVENT", <sub_id>, <event>]
         let ev_v = arr.get(2).context("EVENT missing event")?;
         let event: Event = serde_json::from_value(ev_v.clone()).context("parse event")?;
 
+        // 署名検証（有効時のみ）
+        if self.verify_signatures {
+            if let Err(e) = event.verify() {
+                log_rejection(pool, &event, e.reason(), ip_address, Direction::Outbound, &RuleAction::Drop).await?;
+                return Ok(BackendOutcome::Drop);
+            }
+        }
+
+        let Some(verdict) = self.check_event(pool, &event, Direction::Outbound).await? else {
+            return Ok(BackendOutcome::Forward);
+        };
+        log_rejection(pool, &event, &verdict.reason, ip_address, Direction::Outbound, &verdict.action).await?;
+        // 受信側にはOKを返さないため、shadowはdropと同じ扱い
+        Ok(match verdict.action {
+            RuleAction::Drop | RuleAction::Shadow => BackendOutcome::Drop,
+            RuleAction::Flag => BackendOutcome::Forward,
+            RuleAction::Delay(delay) => BackendOutcome::Delay(delay),
+        })
+    }
+
+    /// Explain how the proxy would treat `event` travelling in `direction`.
+    ///
+    /// Runs the same checks as the proxy (signature, safelist for inbound
+    /// events, npub ban, kind blacklist, every loaded rule and the legacy bot
+    /// filter) without logging, counting or caching anything. Connection
+    /// state such as NIP-42 AUTH and rate limits is not considered.
+    pub async fn explain_event(
+        &self,
+        pool: &SqlitePool,
+        event: &Event,
+        direction: Direction,
+    ) -> anyhow::Result<Explanation> {
+        self.reload_rules_if_needed(pool).await?;
+        let kind1 = Kind1Peek(&self.kind1_cache);
+
+        let npub = pubkey_hex_to_npub(&event.pubkey).unwrap_or_else(|_| "unknown".to_string());
+        let signature_checked = direction == Direction::Inbound || self.verify_signatures;
+        let signature_error = event.verify().err();
+        let post_allowed = match direction {
+            Direction::Inbound => Some(is_post_allowed(pool, &event.pubkey).await?),
+            Direction::Outbound => None,
+        };
+        let banned_npub = is_npub_banned(pool, &event.pubkey).await?;
+        let kind_blacklisted = is_kind_blacklisted(pool, event.kind).await?;
+
+        let mut decision: Option<(&'static str, String)> = None;
+        if let Some(e) = signature_error.as_ref().filter(|_| signature_checked) {
+            decision = Some(("drop", e.reason().to_string()));
+        } else if post_allowed == Some(false) {
+            decision = Some(("drop", if banned_npub { "banned_npub" } else { "not_in_safelist" }.to_string()));
+        } else if banned_npub {
+            decision = Some(("drop", "banned_npub".to_string()));
+        } else if kind_blacklisted {
+            decision = Some(("drop", "kind_blacklist".to_string()));
+        }
+
+        // safelistのfilter_bypassはoutboundのルールとBot検出を丸ごと飛ばす
+        let filter_bypass = is_filter_bypass(pool, &event.pubkey).await?;
+        let mut allowed = decision.is_none() && direction == Direction::Outbound && filter_bypass;
+        let mut rules = Vec::new();
+        for rule in self.compiled_rules.read().await.iter() {
+            let applies = rule.applies_to(direction);
+            let reached = applies && decision.is_none() && !allowed;
+            let trace = applies.then(|| rule.filter.explain(event, &kind1));
+            let matched = trace.as_ref().is_some_and(|t| t.result);
+            let decisive = reached && matched && !rule.monitor;
+            if decisive {
+                if rule.allow {
+                    allowed = true;
+                } else {
+                    decision = Some((rule.action.as_str(), format!("filter_rule:{}", rule.id)));
+                }
+            }
+            rules.push(RuleExplanation {
+                id: rule.id,
+                name: rule.name.clone(),
+                rule_type: if rule.allow { "allow" } else { "block" },
+                mode: if rule.monitor { "monitor" } else { "enforce" },
+                action: rule.action.as_str(),
+                direction: rule.direction.clone(),
+                applies,
+                reached,
+                matched,
+                decisive,
+                trace,
+            });
+        }
+
+        let mut bot_filter = BotFilterExplanation::default();
+        if event.kind == 6 || event.kind == 7 {
+            bot_filter.applies = decision.is_none() && !allowed;
+            bot_filter.filter_bypass = filter_bypass;
+            bot_filter.referenced_event_id = event.first_e_tag_event_id().map(str::to_string);
+            bot_filter.referenced_created_at = bot_filter
+                .referenced_event_id
+                .as_deref()
+                .and_then(|id| filter_query::Kind1Lookup::created_at(&kind1, id));
+            bot_filter.matched = !bot_filter.filter_bypass
+                && bot_filter.referenced_created_at == Some(event.created_at);
+            if bot_filter.applies && bot_filter.matched {
+                decision = Some(("drop", "bot_filter".to_string()));
+            }
+        }
+
+        let (decision, reason) = match decision {
+            Some((decision, reason)) => (decision, Some(reason)),
+            None => ("accept", None),
+        };
+        Ok(Explanation {
+            direction: direction.as_str(),
+            npub,
+            signature_error: signature_error.map(|e| e.to_string()),
+            signature_checked,
+            post_allowed,
+            banned_npub,
+            kind_blacklisted,
+            rules,
+            bot_filter,
+            decision,
+            reason,
+        })
+    }
+
+    /// Check a client EVENT before it is forwarded to the backends.
+    ///
+    /// Returns the matching verdict; unlike the backend path the caller logs it,
+    /// since it also has to answer the client with an OK.
+    pub async fn check_client_event(
+        &self,
+        pool: &SqlitePool,
+        event: &Event,
+    ) -> anyhow::Result<Option<Verdict>> {
+        self.check_event(pool, event, Direction::Inbound).await
+    }
+
+    /// Ban, kind blacklist, filter rules and the legacy bot filter
+    async fn check_event(
+        &self,
+        pool: &SqlitePool,
+        event: &Event,
+        direction: Direction,
+    ) -> anyhow::Result<Option<Verdict>> {
+        // バックテスト用のサンプル（拒否されるイベントも含める）
+        if let Some(sampler) = &self.event_sampler {
+            sampler.lock().unwrap().offer(event, direction);
+        }
+
         // Npub BANチェック
         if is_npub_banned(pool, &event.pubkey).await? {
-            log_rejection(pool, &event, "banned_npub", ip_address).await?;
-            return Ok(true);
+            return Ok(Some(Verdict::drop("banned_npub")));
         }
 
         // Kindブラックリストチェック
         if is_kind_blacklisted(pool, event.kind).await? {
-            log_rejection(pool, &event, "kind_blacklist", ip_address).await?;
-            return Ok(true);
+            return Ok(Some(Verdict::drop("kind_blacklist")));
         }
 
         // cache kind1
         if event.kind == 1 {
-            self.kind1_created_at_by_id
-                .insert(event.id.clone(), event.created_at);
+            self.kind1_cache
+                .lock()
+                .unwrap()
+                .insert(&event.id, event.created_at, Instant::now());
+        }
+
+        // 参照先kind1がキャッシュにない場合はバックエンドに問い合わせる（有効時のみ）
+        if let Some(fetcher) = &self.kind1_fetcher {
+            self.fetch_referenced_if_needed(pool, fetcher, event, direction).await?;
+        }
+
+        // safelistのfilter_bypassは従来通りoutboundのルールとBot検出を対象外にする
+        if direction == Direction::Outbound && is_filter_bypass(pool, &event.pubkey).await? {
+            return Ok(None);
         }
 
         // Check custom filter rules from database
-        if self.check_filter_rules(pool, &event, ip_address).await? {
-            return Ok(true);
+        match self.check_filter_rules(pool, event, direction).await? {
+            RuleOutcome::Block(verdict) => return Ok(Some(verdict)),
+            // allowルールは従来のbotフィルタも含めて評価を打ち切る
+            RuleOutcome::Allow => return Ok(None),
+            RuleOutcome::NoMatch => {}
         }
 
         // Legacy bot filter rule (kind6/7) with whitelist bypass
-        // This is kept for backward compatibility
+        // This is kept for backward compatibility; DSL rules are exempted with allow rules instead
         if event.kind == 6 || event.kind == 7 {
             if is_filter_bypass(pool, &event.pubkey).await? {
-                return Ok(false);
+                return Ok(None);
             }
             let Some(target_id) = event.first_e_tag_event_id() else {
-                return Ok(false);
+                return Ok(None);
             };
-            let Some(target_created_at) = self.kind1_created_at_by_id.get(target_id) else {
-                return Ok(false); // cache miss => pass
+            let target_created_at = self.kind1_cache.lock().unwrap().get(target_id, Instant::now());
+            let Some(target_created_at) = target_created_at else {
+                return Ok(None); // cache miss => pass
             };
-            if *target_created_at == event.created_at {
-                log_rejection(pool, &event, "bot_filter", ip_address).await?;
-                return Ok(true); // drop
+            if target_created_at == event.created_at {
+                return Ok(Some(Verdict::drop("bot_filter")));
             }
         }
 
-        Ok(false)
+        Ok(None)
+    }
+
+    /// Fetch the e-tag target of a reaction/repost (or of any event a
+    /// `referenced_created_at` rule will look at) when it is not cached
+    async fn fetch_referenced_if_needed(
+        &self,
+        pool: &SqlitePool,
+        fetcher: &Kind1Fetcher,
+        event: &Event,
+        direction: Direction,
+    ) -> anyhow::Result<()> {
+        let Some(target_id) = event.first_e_tag_event_id() else {
+            return Ok(());
+        };
+        {
+            let cache = self.kind1_cache.lock().unwrap();
+            let now = Instant::now();
+            if cache.contains(target_id, now) || cache.is_recently_missing(target_id, now) {
+                return Ok(());
+            }
+        }
+        let needed = event.kind == 6 || event.kind == 7 || {
+            self.reload_rules_if_needed(pool).await?;
+            self.compiled_rules
+                .read()
+                .await
+                .iter()
+                .any(|r| r.uses_referenced && r.applies_to(direction))
+        };
+        if !needed {
+            return Ok(());
+        }
+
+        let fetched = fetcher(target_id.to_string()).await.filter(|ev| {
+            ev.kind == 1 && ev.id == target_id && (!self.verify_signatures || ev.verify().is_ok())
+        });
+        let mut cache = self.kind1_cache.lock().unwrap();
+        cache.record_fetch(fetched.is_some());
+        match fetched {
+            Some(ev) => cache.insert(&ev.id, ev.created_at, Instant::now()),
+            None => cache.mark_missing(target_id, Instant::now()),
+        }
+        Ok(())
     }
 }
 
@@ -243,6 +879,16 @@ async fn is_filter_bypass(pool: &SqlitePool, pubkey_hex: &str) -> anyhow::Result
     Ok(row.map(|(flags,)| (flags & 2) == 2).unwrap_or(false))
 }
 
+/// 投稿が許可されているか確認（safelistの post_allowed かつ未BAN）
+async fn is_post_allowed(pool: &SqlitePool, pubkey_hex: &str) -> anyhow::Result<bool> {
+    let npub = pubkey_hex_to_npub(pubkey_hex)?;
+    let row: Option<(i64, i64)> = sqlx::query_as("SELECT flags, banned FROM safelist WHERE npub = ?")
+        .bind(npub)
+        .fetch_optional(pool)
+        .await?;
+    Ok(row.is_some_and(|(flags, banned)| banned == 0 && (flags & 1) == 1))
+}
+
 /// NpubがBANされているか確認
 async fn is_npub_banned(pool: &SqlitePool, pubkey_hex: &str) -> anyhow::Result<bool> {
     let npub = pubkey_hex_to_npub(pubkey_hex)?;
@@ -276,7 +922,7 @@ async fn is_kind_blacklisted(pool: &SqlitePool, kind: i64) -> anyhow::Result<boo
     Ok(range.is_some())
 }
 
-fn pubkey_hex_to_npub(pubkey_hex: &str) -> anyhow::Result<String> {
+pub(crate) fn pubkey_hex_to_npub(pubkey_hex: &str) -> anyhow::Result<String> {
     let bytes = hex::decode(pubkey_hex).context("pubkey hex decode")?;
     let hrp = bech32::Hrp::parse("npub").context("invalid bech32 hrp")?;
     Ok(bech32::encode::<bech32::Bech32>(hrp, &bytes)?)
@@ -0,0 +1,196 @@
+//! Rolling sample of events seen by the proxy, used to backtest filter queries.
+//!
+//! The engine offers every event it checks to an `EventSampler`; the kept ones
+//! are buffered in memory and written to `event_samples` by a periodic flush,
+//! which also trims the table to the newest `max_rows` events.
+
+use serde::Serialize;
+use sqlx::SqlitePool;
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use crate::nostr::event::Event;
+use crate::parser::filter_query::CompiledFilter;
+use super::engine::Direction;
+
+/// Default number of events kept in `event_samples`
+pub const DEFAULT_MAX_ROWS: usize = 10_000;
+/// Default sampling interval (1 = keep every event)
+pub const DEFAULT_SAMPLE_EVERY: u64 = 1;
+
+pub struct EventSampler {
+    max_rows: usize,
+    every: u64,
+    seen: u64,
+    // events waiting for the next flush, newest last
+    pending: VecDeque<(Event, Direction)>,
+    pending_ids: HashSet<String>,
+}
+
+impl EventSampler {
+    pub fn new(max_rows: usize, every: u64) -> Self {
+        Self {
+            max_rows,
+            every: every.max(1),
+            seen: 0,
+            pending: VecDeque::new(),
+            pending_ids: HashSet::new(),
+        }
+    }
+
+    pub fn max_rows(&self) -> usize {
+        self.max_rows
+    }
+
+    /// Keep every `every`-th event. The buffer never grows past `max_rows`.
+    pub fn offer(&mut self, event: &Event, direction: Direction) {
+        if self.max_rows == 0 || self.pending_ids.contains(&event.id) {
+            return;
+        }
+        self.seen += 1;
+        if !self.seen.is_multiple_of(self.every) {
+            return;
+        }
+        if self.pending.len() >= self.max_rows {
+            if let Some((oldest, _)) = self.pending.pop_front() {
+                self.pending_ids.remove(&oldest.id);
+            }
+        }
+        self.pending_ids.insert(event.id.clone());
+        self.pending.push_back((event.clone(), direction));
+    }
+
+    /// Take the events kept since the previous call
+    pub fn take(&mut self) -> Vec<(Event, Direction)> {
+        self.pending_ids.clear();
+        std::mem::take(&mut self.pending).into()
+    }
+}
+
+/// Write sampled events and drop all but the newest `max_rows`
+pub async fn store(pool: &SqlitePool, events: Vec<(Event, Direction)>, max_rows: usize) -> anyhow::Result<()> {
+    if events.is_empty() {
+        return Ok(());
+    }
+    let mut tx = pool.begin().await?;
+    for (event, direction) in &events {
+        sqlx::query(
+            "INSERT OR IGNORE INTO event_samples (event_id, pubkey_hex, kind, direction, event_json) VALUES (?, ?, ?, ?, ?)"
+        )
+        .bind(&event.id)
+        .bind(&event.pubkey)
+        .bind(event.kind)
+        .bind(direction.as_str())
+        .bind(serde_json::to_string(event)?)
+        .execute(&mut *tx)
+        .await?;
+    }
+    sqlx::query("DELETE FROM event_samples WHERE id <= (SELECT MAX(id) FROM event_samples) - ?")
+        .bind(max_rows as i64)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// A sampled event a backtested query matched
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct BacktestMatch {
+    pub npub: String,
+    pub direction: String,
+    pub sampled_at: String,
+    pub event: Event,
+}
+
+#[derive(Debug, Clone, Default, Serialize, PartialEq)]
+pub struct BacktestResult {
+    /// Sampled events the query was run against
+    pub scanned: usize,
+    pub match_count: usize,
+    /// Newest matches, up to the requested limit
+    pub samples: Vec<BacktestMatch>,
+    /// Matches published by safelisted (not banned) npubs: likely false positives
+    pub safelisted_match_count: usize,
+    pub safelisted_samples: Vec<BacktestMatch>,
+}
+
+/// Run `filter` over the sampled events travelling in `direction` (None = both)
+pub async fn backtest(
+    pool: &SqlitePool,
+    filter: &CompiledFilter,
+    direction: Option<Direction>,
+    limit: usize,
+) -> anyhow::Result<BacktestResult> {
+    let rows = sqlx::query_as::<_, (String, String, String)>(
+        "SELECT event_json, direction, created_at FROM event_samples ORDER BY id DESC",
+    )
+    .fetch_all(pool)
+    .await?;
+    let safelisted: HashSet<String> = sqlx::query_as::<_, (String,)>("SELECT npub FROM safelist WHERE banned = 0")
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .map(|(npub,)| npub)
+        .collect();
+
+    let samples: Vec<(Event, String, String)> = rows
+        .into_iter()
+        .filter(|(_, dir, _)| direction.is_none_or(|d| d.as_str() == dir))
+        .filter_map(|(json, dir, at)| Some((serde_json::from_str(&json).ok()?, dir, at)))
+        .collect();
+    // referenced_created_at は同じサンプル内のkind1で解決する
+    let kind1_created_at: HashMap<String, i64> = samples
+        .iter()
+        .filter(|(ev, _, _)| ev.kind == 1)
+        .map(|(ev, _, _)| (ev.id.clone(), ev.created_at))
+        .collect();
+
+    let mut result = BacktestResult { scanned: samples.len(), ..Default::default() };
+    for (event, direction, sampled_at) in samples {
+        if !filter.matches(&event, &kind1_created_at) {
+            continue;
+        }
+        result.match_count += 1;
+        let npub = super::engine::pubkey_hex_to_npub(&event.pubkey).unwrap_or_else(|_| "unknown".to_string());
+        let safelisted = safelisted.contains(&npub);
+        if safelisted {
+            result.safelisted_match_count += 1;
+        }
+        let matched = BacktestMatch { npub, direction, sampled_at, event };
+        if safelisted && result.safelisted_samples.len() < limit {
+            result.safelisted_samples.push(matched.clone());
+        }
+        if result.samples.len() < limit {
+            result.samples.push(matched);
+        }
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(id: &str) -> Event {
+        serde_json::from_value(serde_json::json!({
+            "id": id, "pubkey": "00", "created_at": 1, "kind": 1, "tags": [], "content": "", "sig": ""
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_offer_samples_every_nth_and_stays_bounded() {
+        let mut sampler = EventSampler::new(2, 2);
+        for id in ["a", "b", "c", "d", "e", "f"] {
+            sampler.offer(&event(id), Direction::Outbound);
+        }
+        let kept: Vec<String> = sampler.take().into_iter().map(|(ev, _)| ev.id).collect();
+        assert_eq!(kept, vec!["d", "f"]);
+        assert!(sampler.take().is_empty());
+
+        // 同じイベントが複数の接続から届いても一度だけ残す
+        let mut sampler = EventSampler::new(10, 1);
+        sampler.offer(&event("a"), Direction::Outbound);
+        sampler.offer(&event("a"), Direction::Outbound);
+        assert_eq!(sampler.take().len(), 1);
+    }
+}
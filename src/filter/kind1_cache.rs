@@ -0,0 +1,317 @@
+//! Bounded LRU cache of kind1 `created_at` values for bot detection.
+//!
+//! Shared by every connection through the process-wide `FilterEngine`, so a
+//! reaction can be matched against a kind1 another client received. Entries
+//! are evicted least-recently-used first once `capacity` is reached, and
+//! expire `ttl` after they were inserted regardless of use.
+
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use crate::parser::filter_query::Kind1Lookup;
+
+/// Default maximum number of cached kind1 events
+pub const DEFAULT_CAPACITY: usize = 100_000;
+/// Default time a cached kind1 stays usable
+pub const DEFAULT_TTL: Duration = Duration::from_secs(60 * 60);
+/// How long an id that could not be fetched is not asked for again
+const MISSING_TTL: Duration = Duration::from_secs(60);
+/// Maximum number of remembered missing ids
+const MISSING_CAPACITY: usize = 10_000;
+
+struct Entry {
+    created_at: i64,
+    inserted_at: Instant,
+    // matches the newest `order` entry for this id
+    stamp: u64,
+}
+
+/// Counters exposed through `GET /api/cache/kind1`
+#[derive(Debug, Clone, Default, Serialize, PartialEq)]
+pub struct Kind1CacheStats {
+    pub entries: usize,
+    pub max_entries: usize,
+    pub ttl_secs: u64,
+    pub hits: u64,
+    pub misses: u64,
+    pub hit_rate: f64,
+    /// Entries dropped to stay under `max_entries`
+    pub evictions: u64,
+    /// Entries dropped because they outlived `ttl_secs`
+    pub expirations: u64,
+    /// Backend lookups made for a missing referenced event
+    pub fetches: u64,
+    /// Backend lookups that returned the event
+    pub fetch_hits: u64,
+}
+
+pub struct Kind1Cache {
+    entries: HashMap<String, Entry>,
+    // use order, least recently used first. Entries whose stamp no longer
+    // matches are stale and skipped (a hit pushes a fresh copy to the back).
+    order: VecDeque<(String, u64)>,
+    next_stamp: u64,
+    // ids the backends did not return, with the time they were asked for
+    missing: HashMap<String, Instant>,
+    missing_order: VecDeque<String>,
+    capacity: usize,
+    ttl: Duration,
+    stats: Kind1CacheStats,
+}
+
+impl Default for Kind1Cache {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY, DEFAULT_TTL)
+    }
+}
+
+impl Kind1Cache {
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            entries: HashMap::new(),
+            order: VecDeque::new(),
+            next_stamp: 0,
+            missing: HashMap::new(),
+            missing_order: VecDeque::new(),
+            capacity,
+            ttl,
+            stats: Kind1CacheStats::default(),
+        }
+    }
+
+    /// Remember a kind1. An id already cached keeps its original entry
+    /// unless that entry has expired, in which case it is replaced.
+    pub fn insert(&mut self, event_id: &str, created_at: i64, now: Instant) {
+        if self.capacity == 0 || self.contains(event_id, now) {
+            return;
+        }
+        // 期限切れのエントリは無いものとして扱う（古いorderの記録はstamp不一致で読み飛ばされる）
+        if self.entries.remove(event_id).is_some() {
+            self.stats.expirations += 1;
+        }
+        self.missing.remove(event_id);
+        while self.entries.len() >= self.capacity {
+            if !self.evict_lru(now) {
+                break;
+            }
+        }
+        let stamp = self.bump_stamp();
+        self.entries.insert(
+            event_id.to_string(),
+            Entry { created_at, inserted_at: now, stamp },
+        );
+        self.order.push_back((event_id.to_string(), stamp));
+        self.compact();
+    }
+
+    /// Look up a kind1, marking it as recently used. Counts a hit or a miss.
+    pub fn get(&mut self, event_id: &str, now: Instant) -> Option<i64> {
+        let ttl = self.ttl;
+        let Some(entry) = self.entries.get(event_id) else {
+            self.stats.misses += 1;
+            return None;
+        };
+        if now.saturating_duration_since(entry.inserted_at) >= ttl {
+            self.entries.remove(event_id);
+            self.stats.expirations += 1;
+            self.stats.misses += 1;
+            return None;
+        }
+        let stamp = self.bump_stamp();
+        let entry = self.entries.get_mut(event_id).expect("checked above");
+        entry.stamp = stamp;
+        let created_at = entry.created_at;
+        self.order.push_back((event_id.to_string(), stamp));
+        self.compact();
+        self.stats.hits += 1;
+        Some(created_at)
+    }
+
+    /// Whether a usable entry exists, without touching the LRU order or counters
+    pub fn contains(&self, event_id: &str, now: Instant) -> bool {
+        self.entries
+            .get(event_id)
+            .is_some_and(|e| now.saturating_duration_since(e.inserted_at) < self.ttl)
+    }
+
+    /// Look up a kind1 without touching the LRU order or counters
+    pub fn peek(&self, event_id: &str, now: Instant) -> Option<i64> {
+        self.entries
+            .get(event_id)
+            .filter(|e| now.saturating_duration_since(e.inserted_at) < self.ttl)
+            .map(|e| e.created_at)
+    }
+
+    /// Record that the backends did not return `event_id`
+    pub fn mark_missing(&mut self, event_id: &str, now: Instant) {
+        if self.missing.insert(event_id.to_string(), now).is_none() {
+            self.missing_order.push_back(event_id.to_string());
+        }
+        while self.missing_order.len() > MISSING_CAPACITY {
+            if let Some(oldest) = self.missing_order.pop_front() {
+                self.missing.remove(&oldest);
+            }
+        }
+    }
+
+    /// Whether `event_id` was looked up recently without success
+    pub fn is_recently_missing(&self, event_id: &str, now: Instant) -> bool {
+        self.missing
+            .get(event_id)
+            .is_some_and(|at| now.saturating_duration_since(*at) < MISSING_TTL)
+    }
+
+    /// Count a backend lookup for a missing referenced event
+    pub fn record_fetch(&mut self, found: bool) {
+        self.stats.fetches += 1;
+        if found {
+            self.stats.fetch_hits += 1;
+        }
+    }
+
+    pub fn stats(&self) -> Kind1CacheStats {
+        let lookups = self.stats.hits + self.stats.misses;
+        Kind1CacheStats {
+            entries: self.entries.len(),
+            max_entries: self.capacity,
+            ttl_secs: self.ttl.as_secs(),
+            hit_rate: if lookups == 0 {
+                0.0
+            } else {
+                self.stats.hits as f64 / lookups as f64
+            },
+            ..self.stats.clone()
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn bump_stamp(&mut self) -> u64 {
+        self.next_stamp += 1;
+        self.next_stamp
+    }
+
+    /// Drop the least recently used entry. Returns false if nothing was left.
+    fn evict_lru(&mut self, now: Instant) -> bool {
+        while let Some((id, stamp)) = self.order.pop_front() {
+            let current = self.entries.get(&id).is_some_and(|e| e.stamp == stamp);
+            if !current {
+                continue;
+            }
+            if let Some(entry) = self.entries.remove(&id) {
+                if now.saturating_duration_since(entry.inserted_at) >= self.ttl {
+                    self.stats.expirations += 1;
+                } else {
+                    self.stats.evictions += 1;
+                }
+            }
+            return true;
+        }
+        false
+    }
+
+    /// Drop stale queue entries once hits have grown the queue well past the cache
+    fn compact(&mut self) {
+        if self.order.len() <= self.capacity.saturating_mul(2).max(64) {
+            return;
+        }
+        let entries = &self.entries;
+        self.order
+            .retain(|(id, stamp)| entries.get(id).is_some_and(|e| e.stamp == *stamp));
+    }
+}
+
+impl Kind1Lookup for std::sync::Mutex<Kind1Cache> {
+    fn created_at(&self, event_id: &str) -> Option<i64> {
+        self.lock().unwrap().get(event_id, Instant::now())
+    }
+}
+
+/// Read-only view for explaining decisions without counting lookups
+pub struct Kind1Peek<'a>(pub &'a std::sync::Mutex<Kind1Cache>);
+
+impl Kind1Lookup for Kind1Peek<'_> {
+    fn created_at(&self, event_id: &str) -> Option<i64> {
+        self.0.lock().unwrap().peek(event_id, Instant::now())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capacity_evicts_least_recently_used() {
+        let now = Instant::now();
+        let mut cache = Kind1Cache::new(2, DEFAULT_TTL);
+        cache.insert("a", 1, now);
+        cache.insert("b", 2, now);
+        cache.insert("a", 99, now);
+        // "a" is used again, so "b" is the one to go
+        assert_eq!(cache.get("a", now), Some(1));
+        cache.insert("c", 3, now);
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get("b", now), None);
+        assert_eq!(cache.get("a", now), Some(1));
+        assert_eq!(cache.get("c", now), Some(3));
+
+        let stats = cache.stats();
+        assert_eq!(stats.evictions, 1);
+        assert_eq!(stats.hits, 3);
+        assert_eq!(stats.misses, 1);
+        assert_eq!(stats.hit_rate, 0.75);
+    }
+
+    #[test]
+    fn test_ttl_expiry() {
+        let start = Instant::now();
+        let mut cache = Kind1Cache::new(10, Duration::from_secs(60));
+        cache.insert("a", 1, start);
+        assert_eq!(cache.get("a", start + Duration::from_secs(59)), Some(1));
+        assert_eq!(cache.get("a", start + Duration::from_secs(60)), None);
+        assert_eq!(cache.stats().expirations, 1);
+        assert!(cache.is_empty());
+
+        cache.mark_missing("b", start);
+        assert!(cache.is_recently_missing("b", start + Duration::from_secs(1)));
+        assert!(!cache.is_recently_missing("b", start + MISSING_TTL));
+        cache.insert("b", 2, start);
+        assert!(!cache.is_recently_missing("b", start));
+    }
+
+    #[test]
+    fn test_insert_replaces_expired_entry() {
+        let start = Instant::now();
+        let mut cache = Kind1Cache::new(2, Duration::from_secs(60));
+        cache.insert("a", 1, start);
+        cache.insert("b", 2, start);
+        // "a" is seen again after it expired
+        let later = start + Duration::from_secs(61);
+        cache.insert("a", 1, later);
+        assert_eq!(cache.get("a", later), Some(1));
+        assert_eq!(cache.stats().expirations, 1);
+        // the refreshed "a" is the most recently used entry, so "b" goes first
+        cache.insert("c", 3, later);
+        assert_eq!(cache.get("a", later), Some(1));
+        assert_eq!(cache.get("b", later), None);
+    }
+
+    #[test]
+    fn test_queue_stays_bounded_under_hits() {
+        let now = Instant::now();
+        let mut cache = Kind1Cache::new(4, DEFAULT_TTL);
+        cache.insert("a", 1, now);
+        for _ in 0..1_000 {
+            cache.get("a", now);
+        }
+        assert!(cache.order.len() <= 64);
+        assert_eq!(cache.get("a", now), Some(1));
+    }
+}
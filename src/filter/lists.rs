@@ -0,0 +1,124 @@
+//! Named lists referenced from filter queries as `@name`.
+//!
+//! Lists live in `filter_lists` and are copied into each `CompiledFilter`
+//! when rules are (re)loaded, so the API reloads the engine after every
+//! change to a list.
+
+use sqlx::SqlitePool;
+
+use crate::parser::filter_query::{self, FilterLists, Value};
+
+/// Accepted `filter_lists.list_type` values
+pub const LIST_TYPES: [&str; 4] = ["pubkeys", "words", "domains", "kinds"];
+
+/// List names are usable after `@` in a query: letters, digits and `_`
+pub fn validate_name(name: &str) -> Result<(), String> {
+    if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+        return Err(format!("Invalid list name: '{}' (use letters, digits and _)", name));
+    }
+    Ok(())
+}
+
+/// Check and normalize list items for `list_type`.
+///
+/// pubkeys are stored as npub (hex is converted), kinds as numbers, domains
+/// in lowercase. Blank and duplicate items are dropped.
+pub fn normalize_items(list_type: &str, items: &[serde_json::Value]) -> Result<Vec<Value>, String> {
+    if !LIST_TYPES.contains(&list_type) {
+        return Err(format!("Invalid list_type: {} (expected pubkeys, words, domains or kinds)", list_type));
+    }
+    let mut normalized: Vec<Value> = Vec::new();
+    for item in items {
+        let value = match (list_type, item) {
+            ("kinds", serde_json::Value::Number(n)) => {
+                Value::Number(n.as_i64().ok_or_else(|| format!("Invalid kind: {}", n))?)
+            }
+            ("kinds", serde_json::Value::String(s)) if s.trim().is_empty() => continue,
+            ("kinds", serde_json::Value::String(s)) => {
+                Value::Number(s.trim().parse().map_err(|_| format!("Invalid kind: {}", s))?)
+            }
+            (_, serde_json::Value::String(s)) if s.trim().is_empty() => continue,
+            ("pubkeys", serde_json::Value::String(s)) => Value::String(normalize_pubkey(s.trim())?),
+            ("words", serde_json::Value::String(s)) => Value::String(s.trim().to_string()),
+            ("domains", serde_json::Value::String(s)) => Value::String(s.trim().to_lowercase()),
+            (t, other) => return Err(format!("Invalid {} item: {}", t, other)),
+        };
+        if !normalized.contains(&value) {
+            normalized.push(value);
+        }
+    }
+    Ok(normalized)
+}
+
+/// npub1... as is, 64 hex characters converted to npub
+fn normalize_pubkey(s: &str) -> Result<String, String> {
+    if s.len() == 64 && s.chars().all(|c| c.is_ascii_hexdigit()) {
+        return super::engine::pubkey_hex_to_npub(&s.to_lowercase()).map_err(|e| e.to_string());
+    }
+    match bech32::decode(s) {
+        Ok((hrp, data)) if hrp.as_str() == "npub" && data.len() == 32 => Ok(s.to_lowercase()),
+        _ => Err(format!("Invalid pubkey: {} (expected npub or 64 hex characters)", s)),
+    }
+}
+
+/// Every list by name, for compiling rules
+pub async fn load(pool: &SqlitePool) -> anyhow::Result<FilterLists> {
+    let rows = sqlx::query_as::<_, (String, String)>("SELECT name, items_json FROM filter_lists")
+        .fetch_all(pool)
+        .await?;
+    let mut lists = FilterLists::new();
+    for (name, items_json) in rows {
+        match serde_json::from_str::<Vec<Value>>(&items_json) {
+            Ok(items) => {
+                lists.insert(name, items);
+            }
+            Err(e) => tracing::warn!(list = %name, error = %e, "Skipping filter list with invalid items"),
+        }
+    }
+    Ok(lists)
+}
+
+/// Names of the filter rules whose query references `@name`
+pub async fn referencing_rules(pool: &SqlitePool, name: &str) -> anyhow::Result<Vec<String>> {
+    let rows = sqlx::query_as::<_, (String, String)>("SELECT name, parsed_json FROM filter_rules ORDER BY id")
+        .fetch_all(pool)
+        .await?;
+    Ok(rows
+        .into_iter()
+        .filter(|(_, query)| {
+            filter_query::parse(query)
+                .map(|ast| filter_query::extract_list_refs(&ast).iter().any(|r| r == name))
+                .unwrap_or(false)
+        })
+        .map(|(rule, _)| rule)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_normalize_items() {
+        let hex = "7e7e9c42a91bfef19fa929e5fda1b72e0ebc1a4c1141673e2794234d86addf4e";
+        let npub = crate::filter::engine::pubkey_hex_to_npub(hex).unwrap();
+        assert_eq!(
+            normalize_items("pubkeys", &[json!(hex), json!(npub.clone()), json!(" ")]).unwrap(),
+            vec![Value::String(npub)]
+        );
+        assert!(normalize_items("pubkeys", &[json!("npub1nope")]).is_err());
+        assert_eq!(
+            normalize_items("kinds", &[json!(6), json!("7"), json!(6)]).unwrap(),
+            vec![Value::Number(6), Value::Number(7)]
+        );
+        assert!(normalize_items("kinds", &[json!("six")]).is_err());
+        assert_eq!(
+            normalize_items("domains", &[json!(" Spam.Example ")]).unwrap(),
+            vec![Value::String("spam.example".to_string())]
+        );
+        assert!(normalize_items("colors", &[json!("red")]).is_err());
+        assert!(validate_name("known_bots").is_ok());
+        assert!(validate_name("known-bots").is_err());
+    }
+}
@@ -1,2 +1,6 @@
 pub mod engine;
-
+pub mod event_samples;
+pub mod kind1_cache;
+pub mod lists;
+pub mod req_kinds;
+pub mod rule_stats;
@@ -0,0 +1,152 @@
+//! REQ filter rewriting for `req_kind_blacklist`.
+//!
+//! Blacklisted kinds are removed from each filter's `kinds` before the REQ is
+//! sent upstream, so the backends never send events we would drop anyway.
+
+use serde_json::Value;
+use sqlx::SqlitePool;
+use std::collections::HashSet;
+
+/// Enabled entries of `req_kind_blacklist`
+#[derive(Debug, Clone, Default)]
+pub struct KindBlacklist {
+    singles: HashSet<i64>,
+    ranges: Vec<(i64, i64)>,
+}
+
+/// Why a REQ cannot be sent upstream
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum ReqRejection {
+    #[error("all requested kinds are blacklisted")]
+    AllKindsBlacklisted,
+    #[error("filter must specify kinds")]
+    KindsRequired,
+}
+
+impl KindBlacklist {
+    pub async fn load(pool: &SqlitePool) -> anyhow::Result<Self> {
+        let rows: Vec<(Option<i64>, Option<i64>, Option<i64>)> = sqlx::query_as(
+            "SELECT kind_value, kind_min, kind_max FROM req_kind_blacklist WHERE enabled = 1",
+        )
+        .fetch_all(pool)
+        .await?;
+        let mut blacklist = Self::default();
+        for row in rows {
+            match row {
+                (Some(kind), _, _) => {
+                    blacklist.singles.insert(kind);
+                }
+                (None, Some(min), Some(max)) => blacklist.ranges.push((min, max)),
+                _ => {}
+            }
+        }
+        Ok(blacklist)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.singles.is_empty() && self.ranges.is_empty()
+    }
+
+    pub fn contains(&self, kind: i64) -> bool {
+        self.singles.contains(&kind) || self.ranges.iter().any(|&(min, max)| (min..=max).contains(&kind))
+    }
+
+    /// Strip blacklisted kinds from REQ filters.
+    ///
+    /// - Filters whose `kinds` become empty are dropped; if none remain the REQ is rejected.
+    /// - Filters without `kinds` get `default_kinds` (minus the blacklist) injected.
+    ///   Without defaults they are rejected, unless they ask for specific `ids`.
+    pub fn rewrite_filters(
+        &self,
+        filters: Vec<Value>,
+        default_kinds: Option<&[i64]>,
+    ) -> Result<Vec<Value>, ReqRejection> {
+        if self.is_empty() {
+            return Ok(filters);
+        }
+        let requested = filters.len();
+        let mut rewritten = Vec::with_capacity(requested);
+        for mut filter in filters {
+            let Some(obj) = filter.as_object_mut() else {
+                // 不正なフィルタの扱いはバックエンドに任せる
+                rewritten.push(filter);
+                continue;
+            };
+            match obj.get("kinds").and_then(|k| k.as_array()) {
+                Some(kinds) => {
+                    let allowed: Vec<Value> = kinds
+                        .iter()
+                        .filter(|k| k.as_i64().map(|k| !self.contains(k)).unwrap_or(true))
+                        .cloned()
+                        .collect();
+                    if allowed.is_empty() && !kinds.is_empty() {
+                        continue;
+                    }
+                    obj.insert("kinds".to_string(), Value::Array(allowed));
+                }
+                None if obj.contains_key("ids") => {}
+                None => match default_kinds {
+                    Some(defaults) => {
+                        let allowed: Vec<Value> = defaults
+                            .iter()
+                            .filter(|&&k| !self.contains(k))
+                            .map(|&k| Value::from(k))
+                            .collect();
+                        if allowed.is_empty() {
+                            continue;
+                        }
+                        obj.insert("kinds".to_string(), Value::Array(allowed));
+                    }
+                    None => return Err(ReqRejection::KindsRequired),
+                },
+            }
+            rewritten.push(filter);
+        }
+        if rewritten.is_empty() && requested > 0 {
+            return Err(ReqRejection::AllKindsBlacklisted);
+        }
+        Ok(rewritten)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn blacklist() -> KindBlacklist {
+        KindBlacklist {
+            singles: [7].into_iter().collect(),
+            ranges: vec![(20000, 29999)],
+        }
+    }
+
+    #[test]
+    fn test_strip_blacklisted_kinds() {
+        let filters = vec![json!({"kinds": [1, 7, 20001], "limit": 10}), json!({"kinds": [7]})];
+        let rewritten = blacklist().rewrite_filters(filters, None).unwrap();
+        assert_eq!(rewritten, vec![json!({"kinds": [1], "limit": 10})]);
+
+        assert_eq!(
+            blacklist().rewrite_filters(vec![json!({"kinds": [7, 25000]})], None),
+            Err(ReqRejection::AllKindsBlacklisted)
+        );
+    }
+
+    #[test]
+    fn test_filters_without_kinds() {
+        let filters = vec![json!({"authors": ["abc"]})];
+        assert_eq!(
+            blacklist().rewrite_filters(filters.clone(), None),
+            Err(ReqRejection::KindsRequired)
+        );
+        assert_eq!(
+            blacklist().rewrite_filters(filters, Some(&[0, 1, 7])).unwrap(),
+            vec![json!({"authors": ["abc"], "kinds": [0, 1]})]
+        );
+
+        let by_id = vec![json!({"ids": ["abc"]})];
+        assert_eq!(blacklist().rewrite_filters(by_id.clone(), None).unwrap(), by_id);
+        assert_eq!(KindBlacklist::default().rewrite_filters(vec![json!({})], None).unwrap(), vec![json!({})]);
+    }
+}
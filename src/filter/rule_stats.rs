@@ -0,0 +1,67 @@
+//! In-memory per-rule evaluation counters.
+//!
+//! Counting happens on every event, so the engine only adds to these
+//! counters; `take` hands the accumulated deltas to a periodic flush that adds
+//! them to the `filter_rules` columns.
+
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Counts accumulated for one rule since the last flush
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RuleCounter {
+    pub hits: u64,
+    pub evaluations: u64,
+    pub eval_time: Duration,
+    pub last_matched_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Default)]
+pub struct RuleStats {
+    pending: HashMap<i64, RuleCounter>,
+}
+
+impl RuleStats {
+    /// Record one pass over the rule list: how long each evaluated rule took
+    /// and which ones matched (monitored rules plus the deciding rule, if any)
+    pub fn record(&mut self, evaluated: &[(i64, Duration)], matched: &[i64], now: DateTime<Utc>) {
+        for (rule_id, elapsed) in evaluated {
+            let counter = self.pending.entry(*rule_id).or_default();
+            counter.evaluations += 1;
+            counter.eval_time += *elapsed;
+        }
+        for rule_id in matched {
+            let counter = self.pending.entry(*rule_id).or_default();
+            counter.hits += 1;
+            counter.last_matched_at = Some(now);
+        }
+    }
+
+    /// Take the counts gathered since the previous call
+    pub fn take(&mut self) -> HashMap<i64, RuleCounter> {
+        std::mem::take(&mut self.pending)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_and_take() {
+        let now = Utc::now();
+        let mut stats = RuleStats::default();
+        stats.record(&[(1, Duration::from_micros(3)), (2, Duration::from_micros(5))], &[2], now);
+        stats.record(&[(1, Duration::from_micros(1))], &[], now);
+
+        let taken = stats.take();
+        assert_eq!(taken[&1].evaluations, 2);
+        assert_eq!(taken[&1].hits, 0);
+        assert_eq!(taken[&1].eval_time, Duration::from_micros(4));
+        assert_eq!(taken[&1].last_matched_at, None);
+        assert_eq!(taken[&2].hits, 1);
+        assert_eq!(taken[&2].last_matched_at, Some(now));
+        assert!(stats.take().is_empty());
+    }
+}
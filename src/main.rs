@@ -1,13 +1,6 @@
-mod db;
-mod nostr;
-mod proxy;
-mod filter;
-mod parser;
-mod auth;
-mod api;
 mod docs;
 
-use db::{connect, migrate::migrate};
+use proxy_nostr_relay::{api, auth, db::{connect, migrate::migrate}, filter::{engine::FilterEngine, event_samples, kind1_cache}, proxy::{pool::UpstreamPool, rate_limit::RateLimiter, ws_proxy::ProxyConfig}};
 use anyhow::Context;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 use axum::{
@@ -22,18 +15,6 @@ use std::net::SocketAddr;
 use tower_http::services::{ServeDir, ServeFile};
 use sqlx::SqlitePool;
 
-/// DBから有効なバックエンドリレーURLを取得
-async fn get_backend_relay_url(pool: &SqlitePool) -> String {
-    let result: Option<(String,)> = sqlx::query_as(
-        "SELECT url FROM relay_config WHERE enabled = 1 ORDER BY id ASC LIMIT 1"
-    )
-    .fetch_optional(pool)
-    .await
-    .unwrap_or(None);
-    
-    result.map(|(url,)| url).unwrap_or_default()
-}
-
 /// NIP-11 Relay Information Document
 async fn get_nip11_info(pool: &SqlitePool) -> serde_json::Value {
     let row = sqlx::query_as::<_, (
@@ -67,6 +48,11 @@ async fn get_nip11_info(pool: &SqlitePool) -> serde_json::Value {
     let supported_nips: Vec<i64> = supported_nips_str
         .and_then(|s| serde_json::from_str(&s).ok())
         .unwrap_or_else(|| vec![1, 11]);
+    // NIP-42 AUTHは常に有効
+    let mut supported_nips = supported_nips;
+    if !supported_nips.contains(&42) {
+        supported_nips.push(42);
+    }
 
     // Build limitation object if any limits are set
     let mut limitation = serde_json::Map::new();
@@ -92,6 +78,16 @@ async fn get_nip11_info(pool: &SqlitePool) -> serde_json::Value {
     serde_json::Value::Object(info)
 }
 
+fn env_flag(name: &str) -> bool {
+    std::env::var(name)
+        .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+        .unwrap_or(false)
+}
+
+fn env_parse<T: std::str::FromStr>(name: &str) -> Option<T> {
+    std::env::var(name).ok().and_then(|v| v.trim().parse().ok())
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     // .envファイルを読み込む（存在しなくてもエラーにならない）
@@ -157,17 +153,73 @@ async fn main() -> anyhow::Result<()> {
             auth::basic_auth,
         ));
 
+    // 全クライアントで共有するバックエンド接続プールとレート制限
+    let upstreams = UpstreamPool::new(pool.clone());
+    let limiter = RateLimiter::new(pool.clone());
+    // フィルタエンジンも全クライアントで共有し、kind1キャッシュとルールを使い回す
+    let mut filter_engine = FilterEngine::new()
+        .with_signature_verification(env_flag("VERIFY_BACKEND_EVENTS"))
+        .with_kind1_cache(
+            env_parse("KIND1_CACHE_MAX_ENTRIES").unwrap_or(kind1_cache::DEFAULT_CAPACITY),
+            env_parse("KIND1_CACHE_TTL_SECS")
+                .map(std::time::Duration::from_secs)
+                .unwrap_or(kind1_cache::DEFAULT_TTL),
+        )
+        // フィルタのバックテスト用に直近のイベントをサンプリングする（0で無効）
+        .with_event_sampling(
+            env_parse("EVENT_SAMPLE_MAX_ROWS").unwrap_or(event_samples::DEFAULT_MAX_ROWS),
+            env_parse("EVENT_SAMPLE_EVERY").unwrap_or(event_samples::DEFAULT_SAMPLE_EVERY),
+        );
+    // 参照先kind1がキャッシュにない場合、バックエンドへidでREQして確認する
+    if env_flag("KIND1_CACHE_FETCH_ON_MISS") {
+        let upstreams = upstreams.clone();
+        filter_engine = filter_engine.with_kind1_fetcher(std::sync::Arc::new(move |id: String| {
+            let upstreams = upstreams.clone();
+            Box::pin(async move {
+                upstreams
+                    .fetch_event(
+                        serde_json::json!({"ids": [id], "kinds": [1], "limit": 1}),
+                        std::time::Duration::from_secs(2),
+                    )
+                    .await
+            })
+        }));
+    }
+    // ルールごとのヒット数・評価時間を定期的にDBへ書き出す
+    filter_engine.spawn_rule_stats_flush(pool.clone(), std::time::Duration::from_secs(30));
+    filter_engine.spawn_event_sample_flush(pool.clone(), std::time::Duration::from_secs(10));
+    let proxy_config = ProxyConfig {
+        // NIP-42 AUTHイベントのrelayタグと照合する
+        relay_url: std::env::var("RELAY_URL").ok().filter(|v| !v.is_empty()),
+        // Kindブラックリスト使用時、kinds未指定のREQに補うkind一覧（例: "0,1,3,6"）
+        req_default_kinds: std::env::var("REQ_DEFAULT_KINDS").ok().and_then(|v| {
+            let kinds: Vec<i64> = v.split(',').filter_map(|k| k.trim().parse().ok()).collect();
+            (!kinds.is_empty()).then_some(kinds)
+        }),
+    };
+
     let app = Router::new()
         .merge(protected)
-        .nest("/api", api::routes::router(pool.clone()))
+        .nest("/api", api::routes::router(api::routes::AppState {
+            pool: pool.clone(),
+            filter_engine: filter_engine.clone(),
+        }))
         .nest("/docs", docs::router())
         .route(
             "/",
             get({
                 let pool = pool.clone();
+                let upstreams = upstreams.clone();
+                let limiter = limiter.clone();
+                let filter_engine = filter_engine.clone();
+                let proxy_config = proxy_config.clone();
                 let landing_config = landing_config.clone();
                 move |ws: Option<WebSocketUpgrade>, headers: HeaderMap, ConnectInfo(addr): ConnectInfo<SocketAddr>| {
                     let pool = pool.clone();
+                    let upstreams = upstreams.clone();
+                    let limiter = limiter.clone();
+                    let filter_engine = filter_engine.clone();
+                    let proxy_config = proxy_config.clone();
                     let landing_config = landing_config.clone();
                     let client_ip = addr.ip().to_string();
                     async move {
@@ -190,15 +242,9 @@ async fn main() -> anyhow::Result<()> {
                                 // WebSocket接続の場合
                                 tracing::info!(ip = %client_ip, "WebSocket upgrade request received");
                                 ws.on_upgrade(move |socket| async move {
-                                    // DBから有効なリレーURLを取得
-                                    let backend_url = get_backend_relay_url(&pool).await;
-                                    if backend_url.is_empty() {
-                                        tracing::warn!(ip = %client_ip, "No backend relay configured, closing connection");
-                                        return;
-                                    }
-                                    tracing::info!(ip = %client_ip, backend_url = %backend_url, "Starting WebSocket proxy");
+                                    tracing::info!(ip = %client_ip, "Starting WebSocket proxy");
                                     if let Err(e) =
-                                        crate::proxy::ws_proxy::proxy_ws_with_pool(socket, backend_url, Some(pool), Some(client_ip.clone())).await
+                                        proxy_nostr_relay::proxy::ws_proxy::proxy_ws_with_pool(socket, upstreams, limiter, filter_engine, proxy_config, Some(pool), Some(client_ip.clone())).await
                                     {
                                         tracing::warn!(ip = %client_ip, error = %e, "WebSocket proxy ended with error");
                                     } else {
@@ -1,7 +1,8 @@
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 
 /// NIP-01 event (minimal).
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Event {
     pub id: String,
     pub pubkey: String,
@@ -12,6 +13,31 @@ pub struct Event {
     pub sig: String,
 }
 
+/// Why an event failed NIP-01 verification
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum VerifyError {
+    #[error("event id does not match its content")]
+    IdMismatch,
+    #[error("malformed pubkey")]
+    BadPubkey,
+    #[error("malformed signature")]
+    MalformedSignature,
+    #[error("signature verification failed")]
+    BadSignature,
+}
+
+impl VerifyError {
+    /// Reason recorded in `event_rejection_logs`
+    pub fn reason(&self) -> &'static str {
+        match self {
+            VerifyError::IdMismatch => "invalid_id",
+            VerifyError::BadPubkey | VerifyError::MalformedSignature | VerifyError::BadSignature => {
+                "invalid_signature"
+            }
+        }
+    }
+}
+
 impl Event {
     /// Returns the first `e` tag's event id if present.
     pub fn first_e_tag_event_id(&self) -> Option<&str> {
@@ -21,5 +47,94 @@ impl Event {
             .and_then(|t| t.get(1))
             .map(|s| s.as_str())
     }
+
+    /// Returns the value of the first tag named `name` if present.
+    pub fn first_tag_value(&self, name: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|t| t.first().map(|s| s.as_str()) == Some(name))
+            .and_then(|t| t.get(1))
+            .map(|s| s.as_str())
+    }
+
+    /// NIP-01 id: sha256 of `[0, pubkey, created_at, kind, tags, content]`
+    pub fn compute_id(&self) -> [u8; 32] {
+        let canonical = serde_json::json!([
+            0,
+            self.pubkey,
+            self.created_at,
+            self.kind,
+            self.tags,
+            self.content
+        ]);
+        Sha256::digest(canonical.to_string().as_bytes()).into()
+    }
+
+    /// Check the id against the content and the BIP-340 signature against the id
+    pub fn verify(&self) -> Result<(), VerifyError> {
+        let id = self.compute_id();
+        if hex::encode(id) != self.id.to_lowercase() {
+            return Err(VerifyError::IdMismatch);
+        }
+
+        let pubkey_bytes = hex::decode(&self.pubkey).map_err(|_| VerifyError::BadPubkey)?;
+        let pubkey = secp256k1::XOnlyPublicKey::from_slice(&pubkey_bytes)
+            .map_err(|_| VerifyError::BadPubkey)?;
+        let sig_bytes = hex::decode(&self.sig).map_err(|_| VerifyError::MalformedSignature)?;
+        let sig = secp256k1::schnorr::Signature::from_slice(&sig_bytes)
+            .map_err(|_| VerifyError::MalformedSignature)?;
+
+        let msg = secp256k1::Message::from_digest(id);
+        secp256k1::SECP256K1
+            .verify_schnorr(&sig, &msg, &pubkey)
+            .map_err(|_| VerifyError::BadSignature)
+    }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn signed_event(content: &str) -> Event {
+        let keypair = secp256k1::Keypair::from_seckey_slice(secp256k1::SECP256K1, &[0x11; 32]).unwrap();
+        let (pubkey, _) = keypair.x_only_public_key();
+        let mut event = Event {
+            id: String::new(),
+            pubkey: hex::encode(pubkey.serialize()),
+            created_at: 1700000000,
+            kind: 1,
+            tags: vec![vec!["t".to_string(), "nostr".to_string()]],
+            content: content.to_string(),
+            sig: String::new(),
+        };
+        let id = event.compute_id();
+        event.id = hex::encode(id);
+        let sig = secp256k1::SECP256K1
+            .sign_schnorr_no_aux_rand(&secp256k1::Message::from_digest(id), &keypair);
+        event.sig = hex::encode(sig.serialize());
+        event
+    }
+
+    #[test]
+    fn test_verify_valid_event() {
+        let event = signed_event("hello \"world\"\n");
+        assert_eq!(event.verify(), Ok(()));
+    }
+
+    #[test]
+    fn test_verify_tampered_content() {
+        let mut event = signed_event("hello");
+        event.content = "goodbye".to_string();
+        assert_eq!(event.verify(), Err(VerifyError::IdMismatch));
+    }
+
+    #[test]
+    fn test_verify_forged_pubkey() {
+        let mut event = signed_event("hello");
+        let other = secp256k1::Keypair::from_seckey_slice(secp256k1::SECP256K1, &[0x22; 32]).unwrap();
+        event.pubkey = hex::encode(other.x_only_public_key().0.serialize());
+        event.id = hex::encode(event.compute_id());
+        assert_eq!(event.verify(), Err(VerifyError::BadSignature));
+        assert_eq!(VerifyError::BadSignature.reason(), "invalid_signature");
+    }
+}
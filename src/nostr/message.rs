@@ -1,4 +1,4 @@
-use serde::{Deserialize, Serialize};
+use serde::{de, ser::SerializeSeq, Deserialize, Deserializer, Serialize, Serializer};
 use serde_json::Value;
 
 use super::event::Event;
@@ -9,11 +9,14 @@ pub enum ClientMsg {
     Req { sub_id: String, filters: Vec<Value> },
     Close { sub_id: String },
     Event { event: Event },
+    /// NIP-42 authentication response
+    Auth { event: Event },
 }
 
 /// NIP-01 relay -> client messages (subset we need).
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(untagged)]
+///
+/// Serialized as the NIP-01 array form, e.g. `["OK", <event_id>, false, "blocked: ..."]`.
+#[derive(Debug, Clone, PartialEq)]
 pub enum RelayMsg {
     /// ["EVENT", <sub_id>, <event>]
     Event(String, Event),
@@ -21,6 +24,84 @@ pub enum RelayMsg {
     Eose(String),
     /// ["NOTICE", <message>]
     Notice(String),
+    /// ["OK", <event_id>, <accepted>, <message>]
+    Ok(String, bool, String),
+}
+
+impl RelayMsg {
+    /// Rejection for a client EVENT. `prefix` is one of the NIP-01 machine-readable
+    /// prefixes (`blocked`, `invalid`, `rate-limited`, `restricted`, `auth-required`, ...).
+    pub fn rejected(event_id: &str, prefix: &str, reason: &str) -> Self {
+        RelayMsg::Ok(event_id.to_string(), false, format!("{prefix}: {reason}"))
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("relay message serializes")
+    }
+}
+
+impl Serialize for RelayMsg {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = match self {
+            RelayMsg::Event(..) => serializer.serialize_seq(Some(3))?,
+            RelayMsg::Eose(_) | RelayMsg::Notice(_) => serializer.serialize_seq(Some(2))?,
+            RelayMsg::Ok(..) => serializer.serialize_seq(Some(4))?,
+        };
+        match self {
+            RelayMsg::Event(sub_id, event) => {
+                seq.serialize_element("EVENT")?;
+                seq.serialize_element(sub_id)?;
+                seq.serialize_element(event)?;
+            }
+            RelayMsg::Eose(sub_id) => {
+                seq.serialize_element("EOSE")?;
+                seq.serialize_element(sub_id)?;
+            }
+            RelayMsg::Notice(message) => {
+                seq.serialize_element("NOTICE")?;
+                seq.serialize_element(message)?;
+            }
+            RelayMsg::Ok(event_id, accepted, message) => {
+                seq.serialize_element("OK")?;
+                seq.serialize_element(event_id)?;
+                seq.serialize_element(accepted)?;
+                seq.serialize_element(message)?;
+            }
+        }
+        seq.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for RelayMsg {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let arr = Vec::<Value>::deserialize(deserializer)?;
+        let str_at = |i: usize| -> Result<String, D::Error> {
+            arr.get(i)
+                .and_then(|v| v.as_str())
+                .map(|s| s.to_string())
+                .ok_or_else(|| de::Error::custom(format!("element {i} must be a string")))
+        };
+        match str_at(0)?.as_str() {
+            "EVENT" => {
+                let event = arr
+                    .get(2)
+                    .cloned()
+                    .ok_or_else(|| de::Error::custom("EVENT missing event"))?;
+                let event = serde_json::from_value(event).map_err(de::Error::custom)?;
+                Ok(RelayMsg::Event(str_at(1)?, event))
+            }
+            "EOSE" => Ok(RelayMsg::Eose(str_at(1)?)),
+            "NOTICE" => Ok(RelayMsg::Notice(str_at(1)?)),
+            "OK" => {
+                let accepted = arr
+                    .get(2)
+                    .and_then(|v| v.as_bool())
+                    .ok_or_else(|| de::Error::custom("OK missing accepted flag"))?;
+                Ok(RelayMsg::Ok(str_at(1)?, accepted, str_at(3).unwrap_or_default()))
+            }
+            other => Err(de::Error::custom(format!("unsupported relay message: {other}"))),
+        }
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -42,7 +123,7 @@ pub enum ParseClientMsgError {
 pub fn parse_client_msg(text: &str) -> Result<ClientMsg, ParseClientMsgError> {
     let v: Value = serde_json::from_str(text)?;
     let arr = v.as_array().ok_or(ParseClientMsgError::NotArray)?;
-    let cmd_v = arr.get(0).ok_or(ParseClientMsgError::MissingCommand)?;
+    let cmd_v = arr.first().ok_or(ParseClientMsgError::MissingCommand)?;
     let cmd = cmd_v
         .as_str()
         .ok_or(ParseClientMsgError::CommandNotString)?
@@ -73,7 +154,37 @@ pub fn parse_client_msg(text: &str) -> Result<ClientMsg, ParseClientMsgError> {
             let event: Event = serde_json::from_value(ev_v.clone())?;
             Ok(ClientMsg::Event { event })
         }
+        "AUTH" => {
+            let ev_v = arr
+                .get(1)
+                .ok_or_else(|| ParseClientMsgError::Invalid("AUTH missing event".into()))?;
+            let event: Event = serde_json::from_value(ev_v.clone())?;
+            Ok(ClientMsg::Auth { event })
+        }
         other => Err(ParseClientMsgError::UnsupportedCommand(other.to_string())),
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_relay_msg_ok_round_trip() {
+        let msg = RelayMsg::rejected("abc", "blocked", "not in safelist");
+        let text = msg.to_json();
+        assert_eq!(text, r#"["OK","abc",false,"blocked: not in safelist"]"#);
+        assert_eq!(serde_json::from_str::<RelayMsg>(&text).unwrap(), msg);
+    }
+
+    #[test]
+    fn test_relay_msg_eose_and_notice() {
+        assert_eq!(RelayMsg::Eose("sub".into()).to_json(), r#"["EOSE","sub"]"#);
+        assert_eq!(
+            serde_json::from_str::<RelayMsg>(r#"["NOTICE","hi"]"#).unwrap(),
+            RelayMsg::Notice("hi".into())
+        );
+        assert!(serde_json::from_str::<RelayMsg>(r#"["AUTH","challenge"]"#).is_err());
+    }
+}
@@ -1,3 +1,3 @@
 pub mod event;
 pub mod message;
-
+pub mod nip42;
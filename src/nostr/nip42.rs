@@ -0,0 +1,109 @@
+//! NIP-42 client authentication.
+
+use super::event::{Event, VerifyError};
+
+/// Kind of the ephemeral event a client signs to authenticate
+pub const AUTH_KIND: i64 = 22242;
+/// Accepted clock skew for the AUTH event's created_at (seconds)
+pub const MAX_AUTH_SKEW_SECS: i64 = 600;
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum AuthError {
+    #[error("auth event must be kind 22242")]
+    WrongKind,
+    #[error("challenge does not match")]
+    ChallengeMismatch,
+    #[error("relay url does not match")]
+    RelayMismatch,
+    #[error("auth event is too old or in the future")]
+    Stale,
+    #[error(transparent)]
+    Invalid(#[from] VerifyError),
+}
+
+/// Generate a fresh challenge for a connection
+pub fn new_challenge() -> String {
+    uuid::Uuid::new_v4().simple().to_string()
+}
+
+/// Verify a kind 22242 response against the challenge we sent.
+///
+/// `relay_url` is compared loosely (case and trailing slash are ignored);
+/// pass None to skip the relay tag check.
+pub fn verify_auth_event(
+    event: &Event,
+    challenge: &str,
+    relay_url: Option<&str>,
+    now: i64,
+) -> Result<(), AuthError> {
+    if event.kind != AUTH_KIND {
+        return Err(AuthError::WrongKind);
+    }
+    if event.first_tag_value("challenge") != Some(challenge) {
+        return Err(AuthError::ChallengeMismatch);
+    }
+    if let Some(expected) = relay_url {
+        let normalize = |u: &str| u.trim().trim_end_matches('/').to_lowercase();
+        match event.first_tag_value("relay") {
+            Some(relay) if normalize(relay) == normalize(expected) => {}
+            _ => return Err(AuthError::RelayMismatch),
+        }
+    }
+    if (event.created_at - now).abs() > MAX_AUTH_SKEW_SECS {
+        return Err(AuthError::Stale);
+    }
+    event.verify()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn auth_event(challenge: &str, relay: &str, created_at: i64) -> Event {
+        let keypair = secp256k1::Keypair::from_seckey_slice(secp256k1::SECP256K1, &[0x33; 32]).unwrap();
+        let mut event = Event {
+            id: String::new(),
+            pubkey: hex::encode(keypair.x_only_public_key().0.serialize()),
+            created_at,
+            kind: AUTH_KIND,
+            tags: vec![
+                vec!["relay".to_string(), relay.to_string()],
+                vec!["challenge".to_string(), challenge.to_string()],
+            ],
+            content: String::new(),
+            sig: String::new(),
+        };
+        let id = event.compute_id();
+        event.id = hex::encode(id);
+        let sig = secp256k1::SECP256K1
+            .sign_schnorr_no_aux_rand(&secp256k1::Message::from_digest(id), &keypair);
+        event.sig = hex::encode(sig.serialize());
+        event
+    }
+
+    #[test]
+    fn test_verify_auth_event() {
+        let event = auth_event("abc", "wss://relay.example.com/", 1000);
+        assert_eq!(verify_auth_event(&event, "abc", Some("wss://Relay.example.com"), 1100), Ok(()));
+        assert_eq!(verify_auth_event(&event, "abc", None, 1100), Ok(()));
+    }
+
+    #[test]
+    fn test_verify_auth_event_rejections() {
+        let event = auth_event("abc", "wss://relay.example.com", 1000);
+        assert_eq!(verify_auth_event(&event, "xyz", None, 1000), Err(AuthError::ChallengeMismatch));
+        assert_eq!(
+            verify_auth_event(&event, "abc", Some("wss://other.example.com"), 1000),
+            Err(AuthError::RelayMismatch)
+        );
+        assert_eq!(verify_auth_event(&event, "abc", None, 5000), Err(AuthError::Stale));
+
+        let mut forged = event.clone();
+        forged.content = "x".to_string();
+        assert_eq!(
+            verify_auth_event(&forged, "abc", None, 1000),
+            Err(AuthError::Invalid(VerifyError::IdMismatch))
+        );
+    }
+}
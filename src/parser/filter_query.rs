@@ -2,7 +2,9 @@
 //!
 //! This module implements a complete DSL for filtering Nostr events.
 
-use std::collections::HashMap;
+use std::cell::OnceCell;
+use std::collections::{HashMap, HashSet};
+use aho_corasick::AhoCorasick;
 use regex::Regex;
 
 use super::filter_query_ast::*;
@@ -11,9 +13,12 @@ use crate::nostr::event::Event;
 // Re-export AST types for external use
 pub use super::filter_query_ast::{
     Expr, Condition, Field, Operator, Value, 
-    ParseError, ValidationResult, extract_fields
+    ParseError, ValidationResult, TraceNode, extract_fields, extract_list_refs, list_ref_positions
 };
 
+/// Named lists available to `@name` references, by name
+pub type FilterLists = HashMap<String, Vec<Value>>;
+
 // ============================================================================
 // Lexer
 // ============================================================================
@@ -23,6 +28,8 @@ pub struct Lexer<'a> {
     input: &'a str,
     chars: std::iter::Peekable<std::str::CharIndices<'a>>,
     current_pos: usize,
+    // The previous token ends a value, so a following '-' is subtraction
+    after_value: bool,
 }
 
 impl<'a> Lexer<'a> {
@@ -31,6 +38,7 @@ impl<'a> Lexer<'a> {
             input,
             chars: input.char_indices().peekable(),
             current_pos: 0,
+            after_value: false,
         }
     }
 
@@ -109,6 +117,30 @@ impl<'a> Lexer<'a> {
         Ok(s)
     }
 
+    /// Read a number, or a duration literal when a unit (s, m, h, d, w) follows
+    fn read_number_or_duration(&mut self) -> Result<Token, ParseError> {
+        let start = self.chars.peek().map(|(pos, _)| *pos).unwrap_or(self.input.len());
+        let n = self.read_number();
+        let unit_secs = match self.peek_char() {
+            Some('s') => 1,
+            Some('m') => 60,
+            Some('h') => 60 * 60,
+            Some('d') => 24 * 60 * 60,
+            Some('w') => 7 * 24 * 60 * 60,
+            _ => return Ok(Token::Number(n)),
+        };
+        // `10min` などは単位として扱わない
+        let mut rest = self.input[self.current_pos + 1..].chars().skip(1);
+        if rest.next().is_some_and(|c| c.is_alphanumeric() || c == '_') {
+            return Ok(Token::Number(n));
+        }
+        self.next_char();
+        n.checked_mul(unit_secs).map(Token::Duration).ok_or(ParseError {
+            message: "Duration is too large".to_string(),
+            position: start,
+        })
+    }
+
     fn read_number(&mut self) -> i64 {
         let mut s = String::new();
         let negative = if self.peek_char() == Some('-') {
@@ -154,15 +186,30 @@ impl<'a> Lexer<'a> {
         let token = match self.peek_char() {
             None => Token::Eof,
             Some('"') => Token::String(self.read_string()?),
-            Some(c) if c.is_ascii_digit() || (c == '-' && self.input[start..].len() > 1 && self.input[start+1..].chars().next().map(|c| c.is_ascii_digit()).unwrap_or(false)) => {
-                Token::Number(self.read_number())
+            Some(c) if c.is_ascii_digit() || (c == '-' && !self.after_value && self.input[start..].len() > 1 && self.input[start+1..].chars().next().map(|c| c.is_ascii_digit()).unwrap_or(false)) => {
+                self.read_number_or_duration()?
             }
+            Some('+') => { self.next_char(); Token::Plus }
+            Some('-') => { self.next_char(); Token::Minus }
+            Some('*') => { self.next_char(); Token::Star }
+            Some('/') => { self.next_char(); Token::Slash }
             Some('(') => { self.next_char(); Token::LParen }
             Some(')') => { self.next_char(); Token::RParen }
             Some('[') => { self.next_char(); Token::LBracket }
             Some(']') => { self.next_char(); Token::RBracket }
             Some(',') => { self.next_char(); Token::Comma }
             Some('.') => { self.next_char(); Token::Dot }
+            Some('@') => {
+                self.next_char();
+                let name = self.read_ident();
+                if name.is_empty() {
+                    return Err(ParseError {
+                        message: "Expected list name after '@'".to_string(),
+                        position: start,
+                    });
+                }
+                Token::ListRef(name)
+            }
             Some('=') => {
                 self.next_char();
                 if self.peek_char() == Some('=') {
@@ -212,12 +259,15 @@ impl<'a> Lexer<'a> {
                     "or" => Token::Or,
                     "not" => Token::Not,
                     "contains" => Token::Contains,
+                    "contains_any" => Token::ContainsAny,
                     "starts_with" => Token::StartsWith,
                     "ends_with" => Token::EndsWith,
                     "matches" => Token::Matches,
                     "in" => Token::In,
                     "not_in" => Token::NotIn,
                     "exists" => Token::Exists,
+                    "any" => Token::Any,
+                    "all" => Token::All,
                     "true" => Token::Ident("true".to_string()),
                     "false" => Token::Ident("false".to_string()),
                     _ => Token::Ident(ident),
@@ -232,6 +282,10 @@ impl<'a> Lexer<'a> {
         };
         
         let end = self.chars.peek().map(|(pos, _)| *pos).unwrap_or(self.input.len());
+        self.after_value = matches!(
+            token,
+            Token::Ident(_) | Token::String(_) | Token::Number(_) | Token::Duration(_) | Token::ListRef(_) | Token::RParen | Token::RBracket
+        );
         
         Ok(SpannedToken { token, start, end })
     }
@@ -354,25 +408,89 @@ impl Parser {
     /// Parse primary: ( expr ) | condition
     fn parse_primary(&mut self) -> Result<Expr, ParseError> {
         if *self.peek() == Token::LParen {
+            // `(a - b) * 2 > c` のように条件の左辺が括弧で始まる場合もある
+            let start = self.pos;
             self.advance();
-            let expr = self.parse_or_expr()?;
-            self.expect(Token::RParen)?;
-            Ok(expr)
+            let grouped = self.parse_or_expr().and_then(|expr| {
+                self.expect(Token::RParen)?;
+                Ok(expr)
+            });
+            match grouped {
+                Ok(expr) => Ok(expr),
+                Err(e) => {
+                    self.pos = start;
+                    self.parse_condition().map_err(|_| e)
+                }
+            }
         } else {
             self.parse_condition()
         }
     }
 
-    /// Parse condition: field operator value
+    /// Parse condition: [any | all] (field | arithmetic over fields) operator value
     fn parse_condition(&mut self) -> Result<Expr, ParseError> {
-        let field = self.parse_field()?;
+        let quantifier = match self.peek() {
+            Token::Any => Some(Quantifier::Any),
+            Token::All => Some(Quantifier::All),
+            _ => None,
+        };
+        if quantifier.is_some() {
+            self.advance();
+        }
+        let start = self.current().clone();
+        if !matches!(start.token, Token::Ident(_) | Token::Number(_) | Token::Duration(_) | Token::LParen) {
+            return Err(ParseError {
+                message: format!("Expected field name but got '{}'", start.token),
+                position: start.start,
+            });
+        }
+        let field = match self.parse_value()? {
+            Value::Field(field) => *field,
+            Value::Arith(expr) => Field::Computed { expr },
+            _ => {
+                return Err(ParseError {
+                    message: format!("Expected field name but got '{}'", start.token),
+                    position: start.start,
+                });
+            }
+        };
+        if let Some(quantifier) = quantifier {
+            if !field.is_per_tag() {
+                return Err(ParseError {
+                    message: format!("'{}' needs tag[X].value or tag[X][N] but got '{}'", quantifier, field.name()),
+                    position: start.start,
+                });
+            }
+        }
         let op = self.parse_operator()?;
+        let value_start = self.current().start;
         let value = self.parse_value()?;
+        if let Value::ListRef { list, .. } = &value {
+            if !matches!(op, Operator::In | Operator::NotIn | Operator::ContainsAny) {
+                return Err(ParseError {
+                    message: format!("'@{}' can only be used with in / not_in / contains_any", list),
+                    position: value_start,
+                });
+            }
+        }
+        if op == Operator::ContainsAny {
+            let strings = match &value {
+                Value::List(items) => items.iter().all(|v| matches!(v, Value::String(_))),
+                Value::ListRef { .. } => true,
+                _ => false,
+            };
+            if !strings {
+                return Err(ParseError {
+                    message: "'contains_any' needs a list of strings or @list".to_string(),
+                    position: value_start,
+                });
+            }
+        }
         
-        Ok(Expr::Condition(Condition { field, op, value }))
+        Ok(Expr::Condition(Condition { quantifier, field, op, value }))
     }
 
-    /// Parse field: ident | tag[name] | tag[name].count | tag[name].value
+    /// Parse field: ident | tag[name] | tag[name].count | tag[name].value | tag[name][index]
     fn parse_field(&mut self) -> Result<Field, ParseError> {
         let token = self.advance().clone();
         
@@ -381,6 +499,7 @@ impl Parser {
                 match name.as_str() {
                     "content_length" => Ok(Field::ContentLength),
                     "referenced_created_at" => Ok(Field::ReferencedCreatedAt),
+                    "age" => Ok(Field::Age),
                     "tag" => {
                         // tag[name] or tag[name].count or tag[name].value
                         self.expect(Token::LBracket)?;
@@ -395,6 +514,23 @@ impl Parser {
                             }
                         };
                         self.expect(Token::RBracket)?;
+
+                        // tag[name][index]: position within the tag (0 is the name)
+                        if *self.peek() == Token::LBracket {
+                            self.advance();
+                            let index_token = self.advance().clone();
+                            let index = match index_token.token {
+                                Token::Number(n) if n >= 0 => n as usize,
+                                _ => {
+                                    return Err(ParseError {
+                                        message: format!("Expected tag index but got '{}'", index_token.token),
+                                        position: index_token.start,
+                                    });
+                                }
+                            };
+                            self.expect(Token::RBracket)?;
+                            return Ok(Field::TagIndex { tag_name, index });
+                        }
                         
                         // Check for .count or .value
                         if *self.peek() == Token::Dot {
@@ -442,6 +578,7 @@ impl Parser {
             Token::Ge => Ok(Operator::Ge),
             Token::Le => Ok(Operator::Le),
             Token::Contains => Ok(Operator::Contains),
+            Token::ContainsAny => Ok(Operator::ContainsAny),
             Token::StartsWith => Ok(Operator::StartsWith),
             Token::EndsWith => Ok(Operator::EndsWith),
             Token::Matches => Ok(Operator::Matches),
@@ -455,11 +592,51 @@ impl Parser {
         }
     }
 
-    /// Parse value: string | number | bool | list | field_ref
+    /// Parse value: product ((+ | -) product)*
     fn parse_value(&mut self) -> Result<Value, ParseError> {
+        let mut value = self.parse_value_product()?;
+
+        while matches!(self.peek(), Token::Plus | Token::Minus) {
+            let op_token = self.advance().clone();
+            let op = if op_token.token == Token::Plus { ArithOp::Add } else { ArithOp::Sub };
+            let right = self.parse_value_product()?;
+            value = arith(op, value, right, op_token.start)?;
+        }
+
+        Ok(value)
+    }
+
+    /// Parse product: term ((* | /) term)*
+    fn parse_value_product(&mut self) -> Result<Value, ParseError> {
+        let mut value = self.parse_value_term()?;
+
+        while matches!(self.peek(), Token::Star | Token::Slash) {
+            let op_token = self.advance().clone();
+            let op = if op_token.token == Token::Star { ArithOp::Mul } else { ArithOp::Div };
+            let right = self.parse_value_term()?;
+            value = arith(op, value, right, op_token.start)?;
+        }
+
+        Ok(value)
+    }
+
+    /// Parse value term: string | number | duration | bool | now() | ( arithmetic ) | list | @list | field_ref
+    fn parse_value_term(&mut self) -> Result<Value, ParseError> {
         let token = self.current().clone();
         
         match &token.token {
+            Token::LParen => {
+                self.advance();
+                let value = self.parse_value()?;
+                self.expect(Token::RParen)?;
+                if !is_numeric_operand(&value) {
+                    return Err(ParseError {
+                        message: "Parentheses in a value must contain arithmetic".to_string(),
+                        position: token.start,
+                    });
+                }
+                Ok(value)
+            }
             Token::String(s) => {
                 self.advance();
                 Ok(Value::String(s.clone()))
@@ -468,6 +645,20 @@ impl Parser {
                 self.advance();
                 Ok(Value::Number(*n))
             }
+            Token::Duration(secs) => {
+                self.advance();
+                Ok(Value::Number(*secs))
+            }
+            Token::ListRef(name) => {
+                self.advance();
+                Ok(Value::ListRef { list: name.clone(), position: token.start })
+            }
+            Token::Ident(s) if s == "now" && self.tokens.get(self.pos + 1).is_some_and(|t| t.token == Token::LParen) => {
+                self.advance();
+                self.expect(Token::LParen)?;
+                self.expect(Token::RParen)?;
+                Ok(Value::Arith(Box::new(Arith::Now)))
+            }
             Token::Ident(s) if s == "true" => {
                 self.advance();
                 Ok(Value::Bool(true))
@@ -476,7 +667,7 @@ impl Parser {
                 self.advance();
                 Ok(Value::Bool(false))
             }
-            Token::Ident(s) => {
+            Token::Ident(_) => {
                 // Field reference
                 let field = self.parse_field()?;
                 Ok(Value::Field(Box::new(field)))
@@ -506,22 +697,349 @@ impl Parser {
     }
 }
 
+/// Whether a value can take part in arithmetic
+fn is_numeric_operand(value: &Value) -> bool {
+    match value {
+        Value::Number(_) | Value::Arith(_) => true,
+        Value::Field(field) => field.is_numeric(),
+        Value::String(_) | Value::Bool(_) | Value::List(_) | Value::ListRef { .. } => false,
+    }
+}
+
+/// Build `left op right`, rejecting non-numeric operands
+fn arith(op: ArithOp, left: Value, right: Value, position: usize) -> Result<Value, ParseError> {
+    if !is_numeric_operand(&left) || !is_numeric_operand(&right) {
+        return Err(ParseError {
+            message: format!("'{}' needs numbers, durations, now() or numeric fields", op),
+            position,
+        });
+    }
+    Ok(Value::Arith(Box::new(Arith::Binary { op, left, right })))
+}
+
 // ============================================================================
 // Compiler and Evaluator
 // ============================================================================
 
+/// Lookup of cached kind1 events for `referenced_created_at`
+pub trait Kind1Lookup {
+    /// created_at of the kind1 event with this id, if known
+    fn created_at(&self, event_id: &str) -> Option<i64>;
+}
+
+impl Kind1Lookup for HashMap<String, i64> {
+    fn created_at(&self, event_id: &str) -> Option<i64> {
+        self.get(event_id).copied()
+    }
+}
+
+/// Per-event state shared by every filter evaluated against the same event
+pub struct EvalContext<'a> {
+    event: &'a Event,
+    kind1_cache: &'a dyn Kind1Lookup,
+    // bech32 of the pubkey, encoded on first use
+    npub: OnceCell<Option<String>>,
+}
+
+impl<'a> EvalContext<'a> {
+    pub fn new(event: &'a Event, kind1_cache: &'a dyn Kind1Lookup) -> Self {
+        Self { event, kind1_cache, npub: OnceCell::new() }
+    }
+
+    fn npub(&self) -> Option<String> {
+        self.npub
+            .get_or_init(|| {
+                let bytes = hex::decode(&self.event.pubkey).ok()?;
+                let hrp = bech32::Hrp::parse("npub").ok()?;
+                bech32::encode::<bech32::Bech32>(hrp, &bytes).ok()
+            })
+            .clone()
+    }
+}
+
 /// Compiled filter ready for evaluation
 pub struct CompiledFilter {
     ast: Expr,
+    // evaluation tree built from `ast`: flattened, cheap operands first,
+    // top-level kind checks moved to `kinds`
+    plan: Plan,
+    // the only kinds the filter can match, if limited by `kind == N` / `kind in [...]`
+    kinds: Option<HashSet<i64>>,
     regex_cache: HashMap<String, Regex>,
+    // items of the lists referenced as `@name`, copied when compiled
+    lists: FilterLists,
+    // one automaton per field for contains / starts_with / contains_any
+    pattern_sets: Vec<PatternSet>,
+}
+
+/// Evaluation tree built from the AST when compiling
+enum Plan {
+    /// Every operand holds (an empty AND is true)
+    And(Vec<Plan>),
+    /// Some operand holds
+    Or(Vec<Plan>),
+    Not(Box<Plan>),
+    Condition(Condition),
+    /// contains / starts_with / contains_any on literals: any of `ids` found in
+    /// the field of pattern set `set` (at the start for starts_with)
+    Patterns { set: usize, ids: Vec<usize>, at_start: bool },
+}
+
+/// Lowercased literal patterns of every contains / starts_with / contains_any
+/// on one field, so a single pass over the value answers all of them
+struct PatternSet {
+    field: Field,
+    automaton: AhoCorasick,
+}
+
+/// Which patterns of a set a field value contains, and which it starts with
+struct PatternHits {
+    found: Vec<bool>,
+    at_start: Vec<bool>,
+}
+
+impl Plan {
+    /// AND of `operands`, cheapest first
+    fn all(operands: Vec<Plan>) -> Plan {
+        Self::chain(operands, Plan::And)
+    }
+
+    /// OR of `operands`, cheapest first
+    fn any(operands: Vec<Plan>) -> Plan {
+        Self::chain(operands, Plan::Or)
+    }
+
+    fn chain(mut operands: Vec<Plan>, build: fn(Vec<Plan>) -> Plan) -> Plan {
+        if operands.len() == 1 {
+            return operands.remove(0);
+        }
+        // 評価に副作用はないので、安い条件から試して短絡させる
+        operands.sort_by_cached_key(Plan::cost);
+        build(operands)
+    }
+
+    /// Rough relative cost of evaluating the node
+    fn cost(&self) -> u32 {
+        match self {
+            Plan::And(operands) | Plan::Or(operands) => operands.iter().map(Plan::cost).sum(),
+            Plan::Not(plan) => plan.cost(),
+            Plan::Condition(cond) => condition_cost(cond),
+            // one pass over the value, shared by every condition on the field
+            Plan::Patterns { .. } => 8,
+        }
+    }
+}
+
+/// Cost of reading a field
+fn field_cost(field: &Field) -> u32 {
+    match field {
+        Field::Simple { name } => match name.as_str() {
+            "id" | "pubkey" | "content" => 2,
+            // bech32 encoding
+            "npub" => 10,
+            _ => 1,
+        },
+        Field::ContentLength | Field::Age => 1,
+        Field::Tag { .. } | Field::TagCount { .. } | Field::TagValue { .. } | Field::TagIndex { .. } => 2,
+        // kind1 cache lookup
+        Field::ReferencedCreatedAt => 4,
+        Field::Computed { expr } => 1 + expr.fields().into_iter().map(field_cost).sum::<u32>(),
+    }
+}
+
+/// Cost of a condition: its fields plus the comparison
+fn condition_cost(cond: &Condition) -> u32 {
+    let fields: u32 = cond.field.fields().into_iter().chain(cond.value.fields()).map(field_cost).sum();
+    let compare = match (cond.op, &cond.value) {
+        (Operator::Matches, _) => 20,
+        (Operator::Contains | Operator::StartsWith | Operator::EndsWith | Operator::ContainsAny, _) => 4,
+        (Operator::In | Operator::NotIn, Value::List(items)) => 1 + items.len() as u32 / 8,
+        (Operator::In | Operator::NotIn, _) => 4,
+        _ => 0,
+    };
+    let per_tag = if cond.quantifier.is_some() { 2 } else { 0 };
+    fields + compare + per_tag
+}
+
+/// Operands of a chain of ANDs (`and`) or ORs: `a AND (b AND c)` -> [a, b, c]
+fn chain_operands<'a>(expr: &'a Expr, and: bool, operands: &mut Vec<&'a Expr>) {
+    match expr {
+        Expr::And { left, right } if and => {
+            chain_operands(left, and, operands);
+            chain_operands(right, and, operands);
+        }
+        Expr::Or { left, right } if !and => {
+            chain_operands(left, and, operands);
+            chain_operands(right, and, operands);
+        }
+        _ => operands.push(expr),
+    }
+}
+
+/// Kinds accepted by an expression made only of `kind == N` / `kind in [...]`
+/// joined by AND / OR, or None if it checks anything else
+fn kind_set(expr: &Expr, lists: &FilterLists) -> Option<HashSet<i64>> {
+    match expr {
+        Expr::And { left, right } => {
+            let left = kind_set(left, lists)?;
+            let right = kind_set(right, lists)?;
+            Some(left.intersection(&right).copied().collect())
+        }
+        Expr::Or { left, right } => {
+            let mut kinds = kind_set(left, lists)?;
+            kinds.extend(kind_set(right, lists)?);
+            Some(kinds)
+        }
+        Expr::Condition(cond) if cond.quantifier.is_none() && matches!(&cond.field, Field::Simple { name } if name == "kind") => {
+            let items = match (cond.op, &cond.value) {
+                (Operator::Eq, Value::Number(n)) => return Some(HashSet::from([*n])),
+                (Operator::In, Value::List(items)) => items,
+                (Operator::In, Value::ListRef { list, .. }) => lists.get(list)?,
+                _ => return None,
+            };
+            items.iter().map(Value::as_number).collect()
+        }
+        _ => None,
+    }
+}
+
+impl PatternSet {
+    fn scan(&self, value: &str) -> PatternHits {
+        let len = self.automaton.patterns_len();
+        let mut hits = PatternHits { found: vec![false; len], at_start: vec![false; len] };
+        // contains / starts_with と同じく小文字にそろえて比べる
+        let haystack = value.to_lowercase();
+        for m in self.automaton.find_overlapping_iter(&haystack) {
+            let id = m.pattern().as_usize();
+            hits.found[id] = true;
+            if m.start() == 0 {
+                hits.at_start[id] = true;
+            }
+        }
+        hits
+    }
+}
+
+/// Patterns collected per field while planning
+#[derive(Default)]
+struct PatternSetBuilder {
+    // field name -> index into `sets`
+    by_field: HashMap<String, usize>,
+    // field, patterns in id order, pattern -> id
+    sets: Vec<(Field, Vec<String>, HashMap<String, usize>)>,
+}
+
+impl PatternSetBuilder {
+    /// Set index and pattern ids of `patterns` on `field`, adding new ones
+    fn add(&mut self, field: &Field, patterns: &[&str]) -> (usize, Vec<usize>) {
+        let set = match self.by_field.get(&field.name()) {
+            Some(set) => *set,
+            None => {
+                self.sets.push((field.clone(), Vec::new(), HashMap::new()));
+                self.by_field.insert(field.name(), self.sets.len() - 1);
+                self.sets.len() - 1
+            }
+        };
+        let (_, list, ids) = &mut self.sets[set];
+        let mut pattern_ids: Vec<usize> = patterns
+            .iter()
+            .map(|pattern| {
+                let pattern = pattern.to_lowercase();
+                *ids.entry(pattern.clone()).or_insert_with(|| {
+                    list.push(pattern);
+                    list.len() - 1
+                })
+            })
+            .collect();
+        pattern_ids.sort_unstable();
+        pattern_ids.dedup();
+        (set, pattern_ids)
+    }
+
+    fn build(self) -> Result<Vec<PatternSet>, ParseError> {
+        self.sets
+            .into_iter()
+            .map(|(field, patterns, _)| {
+                let automaton = AhoCorasick::new(&patterns).map_err(|e| ParseError {
+                    message: format!("Too many patterns for {}: {}", field.name(), e),
+                    position: 0,
+                })?;
+                Ok(PatternSet { field, automaton })
+            })
+            .collect()
+    }
 }
 
 impl CompiledFilter {
     /// Compile an AST into a filter
     pub fn compile(ast: Expr) -> Result<Self, ParseError> {
+        Self::compile_with_lists(ast, &FilterLists::new())
+    }
+
+    /// Compile an AST, resolving `@name` references against `lists`
+    pub fn compile_with_lists(ast: Expr, lists: &FilterLists) -> Result<Self, ParseError> {
         let mut regex_cache = HashMap::new();
         Self::compile_regexes(&ast, &mut regex_cache)?;
-        Ok(Self { ast, regex_cache })
+        let mut resolved = FilterLists::new();
+        for (name, position) in list_ref_positions(&ast) {
+            let Some(items) = lists.get(&name) else {
+                return Err(ParseError {
+                    message: format!("Unknown list '@{}'", name),
+                    position,
+                });
+            };
+            resolved.insert(name, items.clone());
+        }
+
+        // トップレベルの kind 条件は評価の前に集合で判定する
+        let mut conjuncts = Vec::new();
+        chain_operands(&ast, true, &mut conjuncts);
+        let mut kinds: Option<HashSet<i64>> = None;
+        let mut rest = Vec::new();
+        for conjunct in conjuncts {
+            match kind_set(conjunct, &resolved) {
+                Some(set) => {
+                    kinds = Some(match kinds {
+                        Some(kinds) => kinds.intersection(&set).copied().collect(),
+                        None => set,
+                    });
+                }
+                None => rest.push(conjunct),
+            }
+        }
+
+        let mut patterns = PatternSetBuilder::default();
+        let plan = Plan::all(
+            rest.into_iter()
+                .map(|expr| Self::plan(expr, &resolved, &mut patterns))
+                .collect::<Result<_, _>>()?,
+        );
+        let pattern_sets = patterns.build()?;
+        Ok(Self { ast, plan, kinds, regex_cache, lists: resolved, pattern_sets })
+    }
+
+    /// Build the evaluation tree, moving literal string matches into pattern sets
+    fn plan(expr: &Expr, lists: &FilterLists, patterns: &mut PatternSetBuilder) -> Result<Plan, ParseError> {
+        Ok(match expr {
+            Expr::And { .. } | Expr::Or { .. } => {
+                let and = matches!(expr, Expr::And { .. });
+                let mut operands = Vec::new();
+                chain_operands(expr, and, &mut operands);
+                let operands = operands
+                    .into_iter()
+                    .map(|expr| Self::plan(expr, lists, patterns))
+                    .collect::<Result<_, _>>()?;
+                if and { Plan::all(operands) } else { Plan::any(operands) }
+            }
+            Expr::Not { expr } => Plan::Not(Box::new(Self::plan(expr, lists, patterns)?)),
+            Expr::Condition(cond) => match pattern_literals(cond, lists)? {
+                Some(literals) => {
+                    let (set, ids) = patterns.add(&cond.field, &literals);
+                    Plan::Patterns { set, ids, at_start: cond.op == Operator::StartsWith }
+                }
+                None => Plan::Condition(cond.clone()),
+            },
+        })
     }
 
     fn compile_regexes(expr: &Expr, cache: &mut HashMap<String, Regex>) -> Result<(), ParseError> {
@@ -556,28 +1074,136 @@ impl CompiledFilter {
 
     /// Evaluate the filter against an event
     pub fn matches(&self, event: &Event, kind1_cache: &HashMap<String, i64>) -> bool {
-        self.evaluate(&self.ast, event, kind1_cache)
+        self.matches_with(event, kind1_cache)
+    }
+
+    /// Evaluate the filter, looking up referenced kind1 events through `kind1_cache`
+    pub fn matches_with(&self, event: &Event, kind1_cache: &dyn Kind1Lookup) -> bool {
+        self.matches_in(&EvalContext::new(event, kind1_cache))
+    }
+
+    /// Evaluate the filter, reusing values other filters derived from the same event
+    pub fn matches_in(&self, cx: &EvalContext) -> bool {
+        if !self.accepts_kind(cx.event.kind) {
+            return false;
+        }
+        // pattern sets are scanned at most once per event, on first use
+        let hits: Vec<OnceCell<Option<PatternHits>>> = self.pattern_sets.iter().map(|_| OnceCell::new()).collect();
+        self.evaluate(&self.plan, cx, &hits)
     }
 
-    fn evaluate(&self, expr: &Expr, event: &Event, kind1_cache: &HashMap<String, i64>) -> bool {
+    /// The only kinds the filter can match, if its top-level `kind` checks limit them
+    pub fn kinds(&self) -> Option<&HashSet<i64>> {
+        self.kinds.as_ref()
+    }
+
+    /// Whether an event of `kind` can match at all
+    pub fn accepts_kind(&self, kind: i64) -> bool {
+        self.kinds.as_ref().is_none_or(|kinds| kinds.contains(&kind))
+    }
+
+    /// Evaluate the filter and record the result of every node.
+    ///
+    /// Unlike `matches_with` both sides of AND/OR are always evaluated, in the
+    /// order written, so the trace shows every condition.
+    pub fn explain(&self, event: &Event, kind1_cache: &dyn Kind1Lookup) -> TraceNode {
+        self.explain_expr(&self.ast, &EvalContext::new(event, kind1_cache))
+    }
+
+    fn explain_expr(&self, expr: &Expr, cx: &EvalContext) -> TraceNode {
+        let branch = |node: &str, result: bool, children: Vec<TraceNode>| TraceNode {
+            node: node.to_lowercase(),
+            text: node.to_string(),
+            result,
+            field_value: None,
+            compared_value: None,
+            children,
+        };
         match expr {
             Expr::And { left, right } => {
-                self.evaluate(left, event, kind1_cache) && self.evaluate(right, event, kind1_cache)
+                let children = vec![self.explain_expr(left, cx), self.explain_expr(right, cx)];
+                branch("AND", children.iter().all(|c| c.result), children)
             }
             Expr::Or { left, right } => {
-                self.evaluate(left, event, kind1_cache) || self.evaluate(right, event, kind1_cache)
+                let children = vec![self.explain_expr(left, cx), self.explain_expr(right, cx)];
+                branch("OR", children.iter().any(|c| c.result), children)
             }
             Expr::Not { expr } => {
-                !self.evaluate(expr, event, kind1_cache)
+                let child = self.explain_expr(expr, cx);
+                branch("NOT", !child.result, vec![child])
             }
             Expr::Condition(cond) => {
-                self.evaluate_condition(cond, event, kind1_cache)
+                let resolve = |field: &Field| {
+                    self.get_field_value(field, cx)
+                        .map(FieldValue::into_json)
+                        .unwrap_or(serde_json::Value::Null)
+                };
+                let field_value = match cond.quantifier {
+                    // any / all はタグごとの値をすべて返す
+                    Some(_) => serde_json::Value::Array(
+                        tag_values(&cond.field, cx.event).into_iter().map(FieldValue::into_json).collect(),
+                    ),
+                    None => resolve(&cond.field),
+                };
+                TraceNode {
+                    node: "condition".to_string(),
+                    text: cond.to_string(),
+                    result: self.evaluate_condition(cond, cx),
+                    field_value: Some(field_value),
+                    compared_value: match &cond.value {
+                        Value::Field(field) => Some(resolve(field)),
+                        Value::Arith(_) => Some(
+                            self.resolve_number(&cond.value, cx)
+                                .map(serde_json::Value::from)
+                                .unwrap_or(serde_json::Value::Null),
+                        ),
+                        _ => None,
+                    },
+                    children: Vec::new(),
+                }
             }
         }
     }
 
-    fn evaluate_condition(&self, cond: &Condition, event: &Event, kind1_cache: &HashMap<String, i64>) -> bool {
-        let field_value = self.get_field_value(&cond.field, event, kind1_cache);
+    fn evaluate(&self, plan: &Plan, cx: &EvalContext, hits: &[OnceCell<Option<PatternHits>>]) -> bool {
+        match plan {
+            Plan::And(operands) => operands.iter().all(|plan| self.evaluate(plan, cx, hits)),
+            Plan::Or(operands) => operands.iter().any(|plan| self.evaluate(plan, cx, hits)),
+            Plan::Not(plan) => {
+                !self.evaluate(plan, cx, hits)
+            }
+            Plan::Condition(cond) => {
+                self.evaluate_condition(cond, cx)
+            }
+            Plan::Patterns { set, ids, at_start } => {
+                let hits = hits[*set].get_or_init(|| {
+                    let set = &self.pattern_sets[*set];
+                    match self.get_field_value(&set.field, cx)? {
+                        FieldValue::String(s) => Some(set.scan(&s)),
+                        _ => None,
+                    }
+                });
+                let Some(hits) = hits else { return false };
+                let table = if *at_start { &hits.at_start } else { &hits.found };
+                ids.iter().any(|id| table[*id])
+            }
+        }
+    }
+
+    fn evaluate_condition(&self, cond: &Condition, cx: &EvalContext) -> bool {
+        if let Some(quantifier) = cond.quantifier {
+            let values = tag_values(&cond.field, cx.event);
+            let holds = |fv: &FieldValue| {
+                cond.op == Operator::Exists || self.compare(fv, &cond.op, &cond.value, cx)
+            };
+            return match quantifier {
+                Quantifier::Any => values.iter().any(holds),
+                // タグが1つもない場合はマッチしない
+                Quantifier::All => !values.is_empty() && values.iter().all(holds),
+            };
+        }
+
+        let field_value = self.get_field_value(&cond.field, cx);
         
         match cond.op {
             Operator::Exists => {
@@ -586,88 +1212,136 @@ impl CompiledFilter {
             }
             _ => {
                 let Some(fv) = field_value else { return false };
-                self.compare(&fv, &cond.op, &cond.value, event, kind1_cache)
+                self.compare(&fv, &cond.op, &cond.value, cx)
             }
         }
     }
 
-    fn get_field_value(&self, field: &Field, event: &Event, kind1_cache: &HashMap<String, i64>) -> Option<FieldValue> {
+    fn get_field_value(&self, field: &Field, cx: &EvalContext) -> Option<FieldValue> {
         match field {
             Field::Simple { name } => match name.as_str() {
-                "id" => Some(FieldValue::String(event.id.clone())),
-                "pubkey" => Some(FieldValue::String(event.pubkey.clone())),
-                "npub" => {
-                    // Convert pubkey to npub
-                    hex::decode(&event.pubkey).ok().and_then(|bytes| {
-                        bech32::Hrp::parse("npub").ok().and_then(|hrp| {
-                            bech32::encode::<bech32::Bech32>(hrp, &bytes).ok()
-                        })
-                    }).map(FieldValue::String)
-                }
-                "kind" => Some(FieldValue::Number(event.kind)),
-                "created_at" => Some(FieldValue::Number(event.created_at)),
-                "content" => Some(FieldValue::String(event.content.clone())),
+                "id" => Some(FieldValue::String(cx.event.id.clone())),
+                "pubkey" => Some(FieldValue::String(cx.event.pubkey.clone())),
+                "npub" => cx.npub().map(FieldValue::String),
+                "kind" => Some(FieldValue::Number(cx.event.kind)),
+                "created_at" => Some(FieldValue::Number(cx.event.created_at)),
+                "content" => Some(FieldValue::String(cx.event.content.clone())),
                 _ => None,
             },
-            Field::ContentLength => Some(FieldValue::Number(event.content.len() as i64)),
+            Field::ContentLength => Some(FieldValue::Number(cx.event.content.len() as i64)),
             Field::Tag { tag_name } => {
                 // Check if tag exists (return true as a marker)
-                if event.tags.iter().any(|t| t.first().map(|s| s.as_str()) == Some(tag_name.as_str())) {
+                if cx.event.tags.iter().any(|t| t.first().map(|s| s.as_str()) == Some(tag_name.as_str())) {
                     Some(FieldValue::Bool(true))
                 } else {
                     None
                 }
             }
             Field::TagCount { tag_name } => {
-                let count = event.tags.iter()
+                let count = cx.event.tags.iter()
                     .filter(|t| t.first().map(|s| s.as_str()) == Some(tag_name.as_str()))
                     .count();
                 Some(FieldValue::Number(count as i64))
             }
             Field::TagValue { tag_name } => {
-                event.tags.iter()
+                cx.event.tags.iter()
                     .find(|t| t.first().map(|s| s.as_str()) == Some(tag_name.as_str()))
                     .and_then(|t| t.get(1))
                     .cloned()
                     .map(FieldValue::String)
             }
+            Field::TagIndex { tag_name, index } => {
+                cx.event.tags.iter()
+                    .find(|t| t.first().map(|s| s.as_str()) == Some(tag_name.as_str()))
+                    .and_then(|t| t.get(*index))
+                    .cloned()
+                    .map(FieldValue::String)
+            }
             Field::ReferencedCreatedAt => {
                 // Get the created_at of the referenced kind1 event
-                event.first_e_tag_event_id()
-                    .and_then(|id| kind1_cache.get(id))
-                    .copied()
+                cx.event.first_e_tag_event_id()
+                    .and_then(|id| cx.kind1_cache.created_at(id))
                     .map(FieldValue::Number)
             }
+            Field::Age => unix_now().checked_sub(cx.event.created_at).map(FieldValue::Number),
+            Field::Computed { expr } => self.resolve_arith(expr, cx).map(FieldValue::Number),
+        }
+    }
+
+    fn resolve_arith(&self, arith: &Arith, cx: &EvalContext) -> Option<i64> {
+        match arith {
+            Arith::Now => Some(unix_now()),
+            Arith::Binary { op, left, right } => {
+                let left = self.resolve_number(left, cx)?;
+                let right = self.resolve_number(right, cx)?;
+                match op {
+                    ArithOp::Add => left.checked_add(right),
+                    ArithOp::Sub => left.checked_sub(right),
+                    ArithOp::Mul => left.checked_mul(right),
+                    ArithOp::Div => left.checked_div(right),
+                }
+            }
+        }
+    }
+
+    /// Resolve a string literal or string field to its value
+    fn resolve_string(&self, value: &Value, cx: &EvalContext) -> Option<String> {
+        match value {
+            Value::String(s) => Some(s.clone()),
+            Value::Field(field) => match self.get_field_value(field, cx)? {
+                FieldValue::String(s) => Some(s),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Resolve a number, numeric field, `now()` or arithmetic to its value
+    fn resolve_number(&self, value: &Value, cx: &EvalContext) -> Option<i64> {
+        match value {
+            Value::Number(n) => Some(*n),
+            Value::Field(field) => match self.get_field_value(field, cx)? {
+                FieldValue::Number(n) => Some(n),
+                _ => None,
+            },
+            Value::Arith(arith) => self.resolve_arith(arith, cx),
+            Value::String(_) | Value::Bool(_) | Value::List(_) | Value::ListRef { .. } => None,
         }
     }
 
-    fn compare(&self, field_value: &FieldValue, op: &Operator, value: &Value, event: &Event, kind1_cache: &HashMap<String, i64>) -> bool {
+    fn compare(&self, field_value: &FieldValue, op: &Operator, value: &Value, cx: &EvalContext) -> bool {
         match op {
-            Operator::Eq => self.compare_eq(field_value, value, event, kind1_cache),
-            Operator::Ne => !self.compare_eq(field_value, value, event, kind1_cache),
-            Operator::Gt => self.compare_numeric(field_value, value, event, kind1_cache, |a, b| a > b),
-            Operator::Lt => self.compare_numeric(field_value, value, event, kind1_cache, |a, b| a < b),
-            Operator::Ge => self.compare_numeric(field_value, value, event, kind1_cache, |a, b| a >= b),
-            Operator::Le => self.compare_numeric(field_value, value, event, kind1_cache, |a, b| a <= b),
+            Operator::Eq => self.compare_eq(field_value, value, cx),
+            Operator::Ne => !self.compare_eq(field_value, value, cx),
+            Operator::Gt => self.compare_numeric(field_value, value, cx, |a, b| a > b),
+            Operator::Lt => self.compare_numeric(field_value, value, cx, |a, b| a < b),
+            Operator::Ge => self.compare_numeric(field_value, value, cx, |a, b| a >= b),
+            Operator::Le => self.compare_numeric(field_value, value, cx, |a, b| a <= b),
             Operator::Contains => {
-                if let (FieldValue::String(s), Value::String(pattern)) = (field_value, value) {
-                    s.to_lowercase().contains(&pattern.to_lowercase())
-                } else {
-                    false
+                match (field_value, self.resolve_string(value, cx)) {
+                    (FieldValue::String(s), Some(pattern)) => s.to_lowercase().contains(&pattern.to_lowercase()),
+                    _ => false,
+                }
+            }
+            Operator::ContainsAny => {
+                match (field_value, self.list_items(value)) {
+                    (FieldValue::String(s), Some(list)) => {
+                        let s = s.to_lowercase();
+                        list.iter().filter_map(Value::as_string).any(|pattern| s.contains(&pattern.to_lowercase()))
+                    }
+                    _ => false,
                 }
             }
             Operator::StartsWith => {
-                if let (FieldValue::String(s), Value::String(pattern)) = (field_value, value) {
-                    s.to_lowercase().starts_with(&pattern.to_lowercase())
-                } else {
-                    false
+                match (field_value, self.resolve_string(value, cx)) {
+                    (FieldValue::String(s), Some(pattern)) => s.to_lowercase().starts_with(&pattern.to_lowercase()),
+                    _ => false,
                 }
             }
             Operator::EndsWith => {
-                if let (FieldValue::String(s), Value::String(pattern)) = (field_value, value) {
-                    s.to_lowercase().ends_with(&pattern.to_lowercase())
-                } else {
-                    false
+                match (field_value, self.resolve_string(value, cx)) {
+                    (FieldValue::String(s), Some(pattern)) => s.to_lowercase().ends_with(&pattern.to_lowercase()),
+                    _ => false,
                 }
             }
             Operator::Matches => {
@@ -678,15 +1352,15 @@ impl CompiledFilter {
                 }
             }
             Operator::In => {
-                if let Value::List(list) = value {
-                    list.iter().any(|v| self.compare_eq(field_value, v, event, kind1_cache))
+                if let Some(list) = self.list_items(value) {
+                    list.iter().any(|v| self.compare_eq(field_value, v, cx))
                 } else {
                     false
                 }
             }
             Operator::NotIn => {
-                if let Value::List(list) = value {
-                    !list.iter().any(|v| self.compare_eq(field_value, v, event, kind1_cache))
+                if let Some(list) = self.list_items(value) {
+                    !list.iter().any(|v| self.compare_eq(field_value, v, cx))
                 } else {
                     true
                 }
@@ -698,42 +1372,42 @@ impl CompiledFilter {
         }
     }
 
-    fn compare_eq(&self, field_value: &FieldValue, value: &Value, event: &Event, kind1_cache: &HashMap<String, i64>) -> bool {
+    /// Items of a list literal or a resolved `@name` reference
+    fn list_items<'v>(&'v self, value: &'v Value) -> Option<&'v [Value]> {
+        match value {
+            Value::List(list) => Some(list),
+            Value::ListRef { list, .. } => self.lists.get(list).map(Vec::as_slice),
+            _ => None,
+        }
+    }
+
+    fn compare_eq(&self, field_value: &FieldValue, value: &Value, cx: &EvalContext) -> bool {
         match (field_value, value) {
             (FieldValue::String(a), Value::String(b)) => a == b,
             (FieldValue::Number(a), Value::Number(b)) => a == b,
             (FieldValue::Bool(a), Value::Bool(b)) => a == b,
-            (FieldValue::Number(a), Value::Field(field)) => {
-                if let Some(FieldValue::Number(b)) = self.get_field_value(field, event, kind1_cache) {
-                    *a == b
-                } else {
-                    false
-                }
-            }
-            (FieldValue::String(a), Value::Field(field)) => {
-                if let Some(FieldValue::String(b)) = self.get_field_value(field, event, kind1_cache) {
-                    *a == b
-                } else {
-                    false
-                }
+            // 型が異なるフィールド同士は一致しない
+            (a, Value::Field(field)) => match (a, self.get_field_value(field, cx)) {
+                (FieldValue::String(a), Some(FieldValue::String(b))) => *a == b,
+                (FieldValue::Number(a), Some(FieldValue::Number(b))) => *a == b,
+                (FieldValue::Bool(a), Some(FieldValue::Bool(b))) => *a == b,
+                _ => false,
+            },
+            (FieldValue::Number(a), Value::Arith(_)) => {
+                self.resolve_number(value, cx) == Some(*a)
             }
             _ => false,
         }
     }
 
-    fn compare_numeric<F>(&self, field_value: &FieldValue, value: &Value, event: &Event, kind1_cache: &HashMap<String, i64>, cmp: F) -> bool
+    fn compare_numeric<F>(&self, field_value: &FieldValue, value: &Value, cx: &EvalContext, cmp: F) -> bool
     where
         F: Fn(i64, i64) -> bool,
     {
-        match (field_value, value) {
-            (FieldValue::Number(a), Value::Number(b)) => cmp(*a, *b),
-            (FieldValue::Number(a), Value::Field(field)) => {
-                if let Some(FieldValue::Number(b)) = self.get_field_value(field, event, kind1_cache) {
-                    cmp(*a, b)
-                } else {
-                    false
-                }
-            }
+        match field_value {
+            FieldValue::Number(a) => self
+                .resolve_number(value, cx)
+                .is_some_and(|b| cmp(*a, b)),
             _ => false,
         }
     }
@@ -752,6 +1426,55 @@ enum FieldValue {
     Bool(bool),
 }
 
+impl FieldValue {
+    fn into_json(self) -> serde_json::Value {
+        match self {
+            FieldValue::String(s) => serde_json::Value::String(s),
+            FieldValue::Number(n) => serde_json::Value::from(n),
+            FieldValue::Bool(b) => serde_json::Value::Bool(b),
+        }
+    }
+}
+
+/// Literal patterns of a condition a pattern set can answer, if any
+fn pattern_literals<'a>(cond: &'a Condition, lists: &'a FilterLists) -> Result<Option<Vec<&'a str>>, ParseError> {
+    if cond.quantifier.is_some() {
+        return Ok(None);
+    }
+    let items = match (cond.op, &cond.value) {
+        (Operator::Contains | Operator::StartsWith, Value::String(s)) => return Ok(Some(vec![s.as_str()])),
+        (Operator::ContainsAny, Value::List(items)) => items,
+        (Operator::ContainsAny, Value::ListRef { list, .. }) => lists.get(list).map(Vec::as_slice).unwrap_or_default(),
+        _ => return Ok(None),
+    };
+    match items.iter().map(Value::as_string).collect::<Option<Vec<_>>>() {
+        Some(literals) => Ok(Some(literals)),
+        None => Err(ParseError {
+            message: format!("'contains_any' needs strings but {} has other values", cond.value.text()),
+            position: 0,
+        }),
+    }
+}
+
+/// Values of a per-tag field across every tag of the name (tags too short are skipped)
+fn tag_values(field: &Field, event: &Event) -> Vec<FieldValue> {
+    let (tag_name, index) = match field {
+        Field::TagValue { tag_name } => (tag_name, 1),
+        Field::TagIndex { tag_name, index } => (tag_name, *index),
+        _ => return Vec::new(),
+    };
+    event.tags.iter()
+        .filter(|t| t.first().map(|s| s.as_str()) == Some(tag_name.as_str()))
+        .filter_map(|t| t.get(index).cloned())
+        .map(FieldValue::String)
+        .collect()
+}
+
+/// Current Unix time in seconds, for `now()` and `age`
+fn unix_now() -> i64 {
+    chrono::Utc::now().timestamp()
+}
+
 // ============================================================================
 // Public API
 // ============================================================================
@@ -764,18 +1487,33 @@ pub fn parse(input: &str) -> Result<Expr, ParseError> {
     parser.parse()
 }
 
+/// Reformat a filter query string as canonical text (comments are dropped)
+pub fn format(input: &str) -> Result<String, ParseError> {
+    parse(input).map(|ast| ast.to_string())
+}
+
 /// Parse and compile a filter query string
 pub fn compile(input: &str) -> Result<CompiledFilter, ParseError> {
+    compile_with_lists(input, &FilterLists::new())
+}
+
+/// Parse and compile a filter query string, resolving `@name` against `lists`
+pub fn compile_with_lists(input: &str, lists: &FilterLists) -> Result<CompiledFilter, ParseError> {
     let ast = parse(input)?;
-    CompiledFilter::compile(ast)
+    CompiledFilter::compile_with_lists(ast, lists)
 }
 
 /// Validate a filter query string and return detailed results
 pub fn validate(input: &str) -> ValidationResult {
+    validate_with_lists(input, &FilterLists::new())
+}
+
+/// Validate a filter query string whose `@name` references resolve against `lists`
+pub fn validate_with_lists(input: &str, lists: &FilterLists) -> ValidationResult {
     match parse(input) {
         Ok(ast) => {
-            // Try to compile to check regex patterns
-            match CompiledFilter::compile(ast.clone()) {
+            // Try to compile to check regex patterns and list references
+            match CompiledFilter::compile_with_lists(ast.clone(), lists) {
                 Ok(_) => {
                     let fields = extract_fields(&ast);
                     ValidationResult::success(ast, fields)
@@ -913,6 +1651,531 @@ mod tests {
         assert!(filter.matches(&event, &cache));
     }
 
+    #[test]
+    fn test_explain_trace() {
+        let filter = compile("kind == 7 AND (content contains \"spam\" OR created_at == referenced_created_at)").unwrap();
+        let event = Event {
+            id: "test".to_string(),
+            pubkey: "abc".to_string(),
+            created_at: 100,
+            kind: 7,
+            tags: vec![vec!["e".to_string(), "target".to_string()]],
+            content: "+".to_string(),
+            sig: "sig".to_string(),
+        };
+        let cache = HashMap::from([("target".to_string(), 100)]);
+
+        let trace = filter.explain(&event, &cache);
+        assert!(trace.result);
+        assert_eq!(trace.result, filter.matches(&event, &cache));
+        assert_eq!(trace.node, "and");
+        assert_eq!(trace.children[0].text, "kind == 7");
+        assert_eq!(trace.children[0].field_value, Some(serde_json::json!(7)));
+        let or = &trace.children[1];
+        assert_eq!(or.children[0].text, "content contains \"spam\"");
+        assert!(!or.children[0].result);
+        assert_eq!(or.children[1].text, "created_at == referenced_created_at");
+        assert!(or.children[1].result);
+        assert_eq!(or.children[1].compared_value, Some(serde_json::json!(100)));
+    }
+
+    #[test]
+    fn test_lex_durations_and_minus() {
+        let tokens = |input: &str| -> Vec<Token> {
+            Lexer::new(input).tokenize().unwrap().into_iter().map(|t| t.token).collect()
+        };
+        assert_eq!(tokens("10s 10m 2h 7d 1w"), vec![
+            Token::Duration(10), Token::Duration(600), Token::Duration(7200),
+            Token::Duration(604800), Token::Duration(604800), Token::Eof,
+        ]);
+        assert_eq!(tokens("now()-10m"), vec![
+            Token::Ident("now".to_string()), Token::LParen, Token::RParen,
+            Token::Minus, Token::Duration(600), Token::Eof,
+        ]);
+        assert_eq!(tokens("kind in [-1]")[3], Token::Number(-1));
+        assert_eq!(tokens("10min")[0], Token::Number(10));
+    }
+
+    #[test]
+    fn test_parse_relative_time() {
+        let expr = parse("created_at > now() + 10m").unwrap();
+        let Expr::Condition(cond) = expr else { panic!("Expected Condition") };
+        assert_eq!(cond.value, Value::Arith(Box::new(Arith::Binary {
+            op: ArithOp::Add,
+            left: Value::Arith(Box::new(Arith::Now)),
+            right: Value::Number(600),
+        })));
+
+        assert!(parse("age > 1d").is_ok());
+        assert!(parse("created_at < referenced_created_at - 5s").is_ok());
+        let err = parse("kind == \"a\" + 1").unwrap_err();
+        assert!(err.message.contains("needs numbers"));
+        assert!(parse("created_at > content + 1").is_err());
+    }
+
+    #[test]
+    fn test_relative_time_match() {
+        let now = chrono::Utc::now().timestamp();
+        let event = |created_at: i64| Event {
+            id: "test".to_string(),
+            pubkey: "abc".to_string(),
+            created_at,
+            kind: 1,
+            tags: vec![],
+            content: "".to_string(),
+            sig: "sig".to_string(),
+        };
+        let cache = HashMap::new();
+
+        let future = compile("created_at > now() + 10m").unwrap();
+        assert!(future.matches(&event(now + 3600), &cache));
+        assert!(!future.matches(&event(now + 60), &cache));
+
+        let old = compile("age > 1d").unwrap();
+        assert!(old.matches(&event(now - 2 * 86400), &cache));
+        assert!(!old.matches(&event(now - 3600), &cache));
+        assert!(compile("age < 0").unwrap().matches(&event(now + 3600), &cache));
+
+        let window = compile("created_at >= now() - 1h AND created_at <= now()").unwrap();
+        assert!(window.matches(&event(now - 60), &cache));
+        assert!(!window.matches(&event(now - 7200), &cache));
+    }
+
+    #[test]
+    fn test_parse_field_arithmetic() {
+        let expr = parse("created_at - referenced_created_at < 2").unwrap();
+        let Expr::Condition(cond) = expr else { panic!("Expected Condition") };
+        assert_eq!(cond.field.name(), "created_at - referenced_created_at");
+        assert_eq!(extract_fields(&parse("created_at - referenced_created_at < 2").unwrap()), vec!["created_at", "referenced_created_at"]);
+
+        // * / は + - より先に結合する
+        let Expr::Condition(cond) = parse("kind > 1 + 2 * tag[p].count").unwrap() else { panic!("Expected Condition") };
+        assert_eq!(cond.value.text(), "1 + 2 * tag[p].count");
+        let Value::Arith(arith) = &cond.value else { panic!("Expected Arith") };
+        assert!(matches!(&**arith, Arith::Binary { op: ArithOp::Add, .. }));
+
+        let Expr::Condition(cond) = parse("(created_at - referenced_created_at) * 2 <= 4").unwrap() else { panic!("Expected Condition") };
+        assert_eq!(cond.field.name(), "(created_at - referenced_created_at) * 2");
+        let Expr::Condition(cond) = parse("kind < 10 - (4 - 1)").unwrap() else { panic!("Expected Condition") };
+        assert_eq!(cond.value.text(), "10 - (4 - 1)");
+
+        // 論理式の括弧は従来どおり
+        assert!(matches!(parse("(kind == 6 OR kind == 7) AND content_length < 3").unwrap(), Expr::And { .. }));
+        assert!(parse("\"a\" == content").is_err());
+        assert!(parse("content * 2 > 1").is_err());
+    }
+
+    #[test]
+    fn test_field_arithmetic_match() {
+        let reaction = |created_at: i64| Event {
+            id: "test".to_string(),
+            pubkey: "abc".to_string(),
+            created_at,
+            kind: 7,
+            tags: vec![
+                vec!["e".to_string(), "target".to_string()],
+                vec!["p".to_string(), "abc".to_string()],
+                vec!["t".to_string(), "nostr".to_string()],
+            ],
+            content: "#nostr".to_string(),
+            sig: "sig".to_string(),
+        };
+        let cache = HashMap::from([("target".to_string(), 100)]);
+
+        let quick = compile("created_at - referenced_created_at < 2").unwrap();
+        assert!(quick.matches(&reaction(100), &cache));
+        assert!(quick.matches(&reaction(101), &cache));
+        assert!(!quick.matches(&reaction(105), &cache));
+        assert!(!quick.matches(&reaction(101), &HashMap::new()));
+
+        assert!(compile("tag[p].count * 10 > content_length").unwrap().matches(&reaction(100), &cache));
+        assert!(!compile("kind / 0 == 0").unwrap().matches(&reaction(100), &cache));
+        assert!(compile("tag[p].value == pubkey").unwrap().matches(&reaction(100), &cache));
+        assert!(compile("content contains tag[t].value").unwrap().matches(&reaction(100), &cache));
+        assert!(!compile("tag[t].value == kind").unwrap().matches(&reaction(100), &cache));
+    }
+
+    #[test]
+    fn test_parse_quantified_tags() {
+        let Expr::Condition(cond) = parse("any tag[p].value in [\"a\", \"b\"]").unwrap() else { panic!("Expected Condition") };
+        assert_eq!(cond.quantifier, Some(Quantifier::Any));
+        assert_eq!(cond.field, Field::TagValue { tag_name: "p".to_string() });
+
+        let Expr::Condition(cond) = parse("ALL tag[e][3] == \"mention\"").unwrap() else { panic!("Expected Condition") };
+        assert_eq!(cond.quantifier, Some(Quantifier::All));
+        assert_eq!(cond.field, Field::TagIndex { tag_name: "e".to_string(), index: 3 });
+
+        assert!(parse("NOT any tag[t].value == \"nsfw\" AND kind == 1").is_ok());
+        assert!(parse("any kind == 1").unwrap_err().message.contains("needs tag[X].value"));
+        assert!(parse("tag[e][-1] == \"x\"").is_err());
+    }
+
+    #[test]
+    fn test_quantified_tag_match() {
+        let event = Event {
+            id: "test".to_string(),
+            pubkey: "abc".to_string(),
+            created_at: 1,
+            kind: 1,
+            tags: vec![
+                vec!["p".to_string(), "alice".to_string()],
+                vec!["e".to_string(), "root".to_string(), "".to_string(), "root".to_string()],
+                vec!["p".to_string(), "bob".to_string(), "wss://spam.example".to_string()],
+                vec!["e".to_string(), "reply".to_string(), "".to_string(), "mention".to_string()],
+                vec!["p".to_string(), "spammer".to_string()],
+            ],
+            content: "".to_string(),
+            sig: "sig".to_string(),
+        };
+        let cache = HashMap::new();
+        let matches = |query: &str| compile(query).unwrap().matches(&event, &cache);
+
+        // 先頭のタグしか見ない従来の形
+        assert!(!matches("tag[p].value == \"spammer\""));
+        assert!(matches("any tag[p].value == \"spammer\""));
+        assert!(matches("any tag[p].value in [\"carol\", \"spammer\"]"));
+        assert!(!matches("all tag[p].value in [\"alice\", \"bob\"]"));
+        assert!(matches("all tag[p].value != \"carol\""));
+        assert!(!matches("all tag[t].value != \"carol\""));
+        assert!(matches("any tag[p][2] contains \"spam.example\""));
+        assert!(matches("tag[e][3] == \"root\""));
+        assert!(matches("any tag[e][3] == \"mention\""));
+        assert!(!matches("all tag[e][3] == \"mention\""));
+        assert!(matches("any tag[p][2] exists true"));
+        assert!(!matches("tag[p][2] exists true"));
+
+        let trace = compile("any tag[p].value == \"bob\"").unwrap().explain(&event, &cache);
+        assert_eq!(trace.text, "any tag[p].value == \"bob\"");
+        assert_eq!(trace.field_value, Some(serde_json::json!(["alice", "bob", "spammer"])));
+    }
+
+    #[test]
+    fn test_list_refs() {
+        let expr = parse("npub in @known_bots AND kind not_in @muted_kinds").unwrap();
+        assert_eq!(extract_list_refs(&expr), vec!["known_bots", "muted_kinds"]);
+        assert!(parse("content contains @spam_words").unwrap_err().message.contains("in / not_in"));
+        assert!(parse("kind in @").is_err());
+
+        let err = compile("kind == 1 AND kind in @muted_kinds").err().unwrap();
+        assert_eq!(err.message, "Unknown list '@muted_kinds'");
+        assert_eq!(err.position, 22);
+        // a list named like a field, or prefixed by another list's name, points at its reference
+        let lists = FilterLists::from([("kind_words".to_string(), vec![Value::String("gm".to_string())])]);
+        let err = compile_with_lists("content in @kind_words AND kind in @kind", &lists).err().unwrap();
+        assert_eq!(err.position, 35);
+
+        let lists = FilterLists::from([("muted_kinds".to_string(), vec![Value::Number(6), Value::Number(7)])]);
+        let filter = compile_with_lists("kind in @muted_kinds", &lists).unwrap();
+        let mut event = Event {
+            id: "test".to_string(),
+            pubkey: "abc".to_string(),
+            created_at: 1,
+            kind: 7,
+            tags: vec![],
+            content: "".to_string(),
+            sig: "sig".to_string(),
+        };
+        let cache = HashMap::new();
+        assert!(filter.matches(&event, &cache));
+        event.kind = 1;
+        assert!(!filter.matches(&event, &cache));
+        assert!(compile_with_lists("kind not_in @muted_kinds", &lists).unwrap().matches(&event, &cache));
+    }
+
+    #[test]
+    fn test_contains_any() {
+        let Expr::Condition(cond) = parse("content contains_any [\"spam\", \"scam\"]").unwrap() else { panic!("Expected Condition") };
+        assert_eq!(cond.op, Operator::ContainsAny);
+        assert!(parse("content contains_any \"spam\"").unwrap_err().message.contains("list of strings"));
+        assert!(parse("content contains_any [1, 2]").is_err());
+
+        let lists = FilterLists::from([
+            ("spam_words".to_string(), vec![Value::String("Free Sats".to_string()), Value::String("airdrop".to_string())]),
+            ("muted_kinds".to_string(), vec![Value::Number(7)]),
+        ]);
+        assert!(compile_with_lists("content contains_any @muted_kinds", &lists).is_err());
+
+        let event = |content: &str| Event {
+            id: "test".to_string(),
+            pubkey: "abc".to_string(),
+            created_at: 1,
+            kind: 1,
+            tags: vec![vec!["t".to_string(), "Nostr".to_string()], vec!["t".to_string(), "airdrop".to_string()]],
+            content: content.to_string(),
+            sig: "sig".to_string(),
+        };
+        let cache = HashMap::new();
+        let filter = compile_with_lists("content contains_any @spam_words", &lists).unwrap();
+        assert!(filter.matches(&event("Claim your FREE sats now"), &cache));
+        assert!(!filter.matches(&event("gm"), &cache));
+        assert!(compile_with_lists("any tag[t].value contains_any @spam_words", &lists).unwrap().matches(&event("gm"), &cache));
+    }
+
+    #[test]
+    fn test_pattern_sets_agree_with_plain_evaluation() {
+        let queries = [
+            "content contains \"spam\" OR content contains \"scam\" OR content contains \"äb\"",
+            "content starts_with \"rt:\" OR content starts_with \"GM\"",
+            "NOT content contains_any [\"nostr\", \"zap\"] AND kind == 1",
+            "content contains \"\" AND tag[t].value starts_with \"nos\"",
+            "(content contains \"gm\" AND content contains_any [\"zap\", \"sats\"]) OR npub starts_with \"npub1\"",
+            "content contains \"spam\" AND NOT content starts_with \"spam\"",
+            "kind contains \"1\" OR tag[x].value contains \"a\"",
+            "(kind == 6 OR kind in [1, 9]) AND content contains \"gm\" AND NOT kind == 9",
+        ];
+        let contents = ["", "RT: spam", "gm, ÄBC zap", "no SCAM here", "GM sats", "spamspam", "Zapped nostr"];
+        let cache = HashMap::new();
+        for query in queries {
+            let filter = compile(query).unwrap();
+            for content in contents {
+                let event = Event {
+                    id: "test".to_string(),
+                    pubkey: "0000000000000000000000000000000000000000000000000000000000000001".to_string(),
+                    created_at: 1,
+                    kind: 1,
+                    tags: vec![vec!["t".to_string(), "Nostr".to_string()]],
+                    content: content.to_string(),
+                    sig: "sig".to_string(),
+                };
+                // explain は AST をそのまま評価する
+                assert_eq!(
+                    filter.matches(&event, &cache),
+                    filter.explain(&event, &cache).result,
+                    "{} on {:?}",
+                    query,
+                    content
+                );
+            }
+        }
+    }
+
+    /// Top-level operands of a plan, as query text
+    fn plan_operands(filter: &CompiledFilter) -> Vec<String> {
+        let text = |plan: &Plan| match plan {
+            Plan::Condition(cond) => cond.to_string(),
+            Plan::Patterns { .. } => "patterns".to_string(),
+            Plan::And(_) => "and".to_string(),
+            Plan::Or(_) => "or".to_string(),
+            Plan::Not(_) => "not".to_string(),
+        };
+        match &filter.plan {
+            Plan::And(operands) => operands.iter().map(text).collect(),
+            plan => vec![text(plan)],
+        }
+    }
+
+    #[test]
+    fn test_plan_flattens_and_orders_by_cost() {
+        let filter = compile(
+            "content matches \"(bot|spam)\" AND (npub == \"npub1x\" AND kind == 7) AND (created_at > 5 AND content contains \"gm\")",
+        )
+        .unwrap();
+        assert_eq!(filter.kinds(), Some(&HashSet::from([7])));
+        assert_eq!(
+            plan_operands(&filter),
+            vec!["created_at > 5", "patterns", "npub == \"npub1x\"", "content matches \"(bot|spam)\""]
+        );
+
+        let filter = compile("(kind == 6 OR kind in [7, 9]) AND kind != 9 AND (kind == 1 OR kind == 7 OR kind == 9)").unwrap();
+        assert_eq!(filter.kinds(), Some(&HashSet::from([7, 9])));
+        assert_eq!(plan_operands(&filter), vec!["kind != 9"]);
+
+        let filter = compile("kind == 7").unwrap();
+        assert!(matches!(&filter.plan, Plan::And(operands) if operands.is_empty()));
+        assert!(compile("kind == 1 AND kind == 7").unwrap().kinds().is_some_and(HashSet::is_empty));
+        assert_eq!(compile("kind == 1 OR content contains \"x\"").unwrap().kinds(), None);
+        assert_eq!(compile("NOT kind == 1").unwrap().kinds(), None);
+
+        let mut event = Event {
+            id: "test".to_string(),
+            pubkey: "0000000000000000000000000000000000000000000000000000000000000001".to_string(),
+            created_at: 1,
+            kind: 9,
+            tags: vec![],
+            content: "".to_string(),
+            sig: "sig".to_string(),
+        };
+        let cache = HashMap::new();
+        let filter = compile("(kind == 6 OR kind in [7, 9]) AND kind != 9").unwrap();
+        assert!(!filter.matches(&event, &cache));
+        event.kind = 7;
+        assert!(filter.matches(&event, &cache));
+        event.kind = 1;
+        assert!(!filter.matches(&event, &cache));
+
+        // 同じイベントの npub は複数のフィルタで1回だけ計算する
+        let npub = crate::filter::engine::pubkey_hex_to_npub(&event.pubkey).unwrap();
+        let cx = EvalContext::new(&event, &cache);
+        assert!(compile(&format!("npub == \"{}\"", npub)).unwrap().matches_in(&cx));
+        assert_eq!(cx.npub.get(), Some(&Some(npub.clone())));
+        assert!(compile("npub starts_with \"npub1\"").unwrap().matches_in(&cx));
+    }
+
+    #[test]
+    fn test_format() {
+        let cases = [
+            ("(kind==6 OR kind==7) AND NOT (npub in [\"npub1x\"])", "(kind == 6 OR kind == 7) AND NOT npub in [\"npub1x\"]"),
+            ("((kind == 1 AND kind == 2)) OR (kind == 3 OR kind == 4)", "kind == 1 AND kind == 2 OR (kind == 3 OR kind == 4)"),
+            ("kind == 1 AND (kind == 2 AND kind == 3)", "kind == 1 AND (kind == 2 AND kind == 3)"),
+            ("NOT NOT (kind == 1 OR kind == 2)", "NOT NOT (kind == 1 OR kind == 2)"),
+            ("created_at > now() - 10m # 10分前より新しい", "created_at > now() - 600"),
+            ("(created_at - referenced_created_at) * 2 < (age)", "(created_at - referenced_created_at) * 2 < age"),
+            ("ANY tag[p].value IN @known_bots", "any tag[p].value in @known_bots"),
+            ("tag[\"e\"][3] == \"mention\" and tag[\"in\"] exists true", "tag[e][3] == \"mention\" AND tag[\"in\"] exists true"),
+            ("content contains \"a\\\"b\\n\"", "content contains \"a\\\"b\\n\""),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(format(input).unwrap(), expected, "{}", input);
+            assert_eq!(parse(expected).unwrap(), parse(input).unwrap(), "{}", input);
+        }
+        assert!(format("kind ==").is_err());
+    }
+
+    mod round_trip {
+        use super::super::*;
+        use proptest::prelude::*;
+
+        fn tag_name() -> impl Strategy<Value = String> {
+            prop_oneof![
+                "[a-zA-Z_][a-zA-Z0-9_]{0,3}",
+                Just("in".to_string()),
+                Just("True".to_string()),
+                "[ -~]{0,4}",
+                prop::collection::vec(any::<char>(), 0..4).prop_map(String::from_iter),
+            ]
+        }
+
+        fn string() -> impl Strategy<Value = String> {
+            prop::collection::vec(any::<char>(), 0..8).prop_map(String::from_iter)
+        }
+
+        // i64::MIN has no literal form
+        fn number() -> impl Strategy<Value = i64> {
+            -i64::MAX..=i64::MAX
+        }
+
+        fn numeric_field() -> impl Strategy<Value = Field> {
+            prop_oneof![
+                prop::sample::select(vec!["kind", "created_at"]).prop_map(|name| Field::Simple { name: name.to_string() }),
+                Just(Field::ContentLength),
+                Just(Field::ReferencedCreatedAt),
+                Just(Field::Age),
+                tag_name().prop_map(|tag_name| Field::TagCount { tag_name }),
+            ]
+        }
+
+        /// Fields a value can refer to (never computed)
+        fn plain_field() -> impl Strategy<Value = Field> {
+            prop_oneof![
+                numeric_field(),
+                prop::sample::select(vec!["id", "pubkey", "npub", "content"]).prop_map(|name| Field::Simple { name: name.to_string() }),
+                tag_name().prop_map(|tag_name| Field::Tag { tag_name }),
+                tag_name().prop_map(|tag_name| Field::TagValue { tag_name }),
+                (tag_name(), 0..20usize).prop_map(|(tag_name, index)| Field::TagIndex { tag_name, index }),
+            ]
+        }
+
+        fn arith() -> impl Strategy<Value = Arith> {
+            let leaf = prop_oneof![
+                number().prop_map(Value::Number),
+                numeric_field().prop_map(|f| Value::Field(Box::new(f))),
+                Just(Value::Arith(Box::new(Arith::Now))),
+            ];
+            let operand = leaf.prop_recursive(3, 8, 2, |inner| {
+                (prop::sample::select(vec![ArithOp::Add, ArithOp::Sub, ArithOp::Mul, ArithOp::Div]), inner.clone(), inner)
+                    .prop_map(|(op, left, right)| Value::Arith(Box::new(Arith::Binary { op, left, right })))
+            });
+            prop_oneof![
+                Just(Arith::Now),
+                (prop::sample::select(vec![ArithOp::Add, ArithOp::Sub, ArithOp::Mul, ArithOp::Div]), operand.clone(), operand)
+                    .prop_map(|(op, left, right)| Arith::Binary { op, left, right }),
+            ]
+        }
+
+        fn value() -> impl Strategy<Value = Value> {
+            let leaf = prop_oneof![
+                string().prop_map(Value::String),
+                number().prop_map(Value::Number),
+                any::<bool>().prop_map(Value::Bool),
+                "[a-z_][a-z0-9_]{0,5}".prop_map(|list| Value::ListRef { list, position: 0 }),
+                plain_field().prop_map(|f| Value::Field(Box::new(f))),
+                arith().prop_map(|a| Value::Arith(Box::new(a))),
+            ];
+            leaf.prop_recursive(2, 8, 3, |inner| prop::collection::vec(inner, 0..3).prop_map(Value::List))
+        }
+
+        fn condition() -> impl Strategy<Value = Condition> {
+            let field = prop_oneof![
+                plain_field(),
+                arith().prop_map(|expr| Field::Computed { expr: Box::new(expr) }),
+            ];
+            let operator = prop::sample::select(vec![
+                Operator::Eq, Operator::Ne, Operator::Gt, Operator::Lt, Operator::Ge, Operator::Le,
+                Operator::Contains, Operator::StartsWith, Operator::EndsWith, Operator::Matches,
+                Operator::In, Operator::NotIn, Operator::Exists,
+            ]);
+            let op_value = prop_oneof![
+                (operator, value()).prop_filter("@list needs in / not_in", |(op, value)| {
+                    !matches!(value, Value::ListRef { .. }) || matches!(op, Operator::In | Operator::NotIn)
+                }),
+                prop_oneof![
+                    prop::collection::vec(string().prop_map(Value::String), 0..3).prop_map(Value::List),
+                    "[a-z_][a-z0-9_]{0,5}".prop_map(|list| Value::ListRef { list, position: 0 }),
+                ]
+                .prop_map(|value| (Operator::ContainsAny, value)),
+            ];
+            let quantifier = prop::option::of(prop::sample::select(vec![Quantifier::Any, Quantifier::All]));
+            (quantifier, field, op_value).prop_map(|(quantifier, field, (op, value))| Condition {
+                quantifier: quantifier.filter(|_| field.is_per_tag()),
+                field,
+                op,
+                value,
+            })
+        }
+
+        /// Generated `@list` values carry no source position
+        fn clear_list_positions(expr: Expr) -> Expr {
+            fn clear_value(value: Value) -> Value {
+                match value {
+                    Value::ListRef { list, .. } => Value::ListRef { list, position: 0 },
+                    Value::List(items) => Value::List(items.into_iter().map(clear_value).collect()),
+                    other => other,
+                }
+            }
+            match expr {
+                Expr::And { left, right } => Expr::And {
+                    left: Box::new(clear_list_positions(*left)),
+                    right: Box::new(clear_list_positions(*right)),
+                },
+                Expr::Or { left, right } => Expr::Or {
+                    left: Box::new(clear_list_positions(*left)),
+                    right: Box::new(clear_list_positions(*right)),
+                },
+                Expr::Not { expr } => Expr::Not { expr: Box::new(clear_list_positions(*expr)) },
+                Expr::Condition(cond) => Expr::Condition(Condition { value: clear_value(cond.value), ..cond }),
+            }
+        }
+
+        fn expr() -> impl Strategy<Value = Expr> {
+            condition().prop_map(Expr::Condition).prop_recursive(4, 16, 2, |inner| {
+                prop_oneof![
+                    (inner.clone(), inner.clone()).prop_map(|(l, r)| Expr::And { left: Box::new(l), right: Box::new(r) }),
+                    (inner.clone(), inner.clone()).prop_map(|(l, r)| Expr::Or { left: Box::new(l), right: Box::new(r) }),
+                    inner.prop_map(|e| Expr::Not { expr: Box::new(e) }),
+                ]
+            })
+        }
+
+        proptest! {
+            #[test]
+            fn parse_of_display_is_identity(ast in expr()) {
+                let text = ast.to_string();
+                prop_assert_eq!(parse(&text).map(clear_list_positions).map_err(|e| format!("{}: {}", e, text)), Ok(ast));
+                prop_assert_eq!(format(&text).ok(), Some(text));
+            }
+        }
+    }
+
     #[test]
     fn test_compile_and_no_match() {
         let filter = compile("kind == 6").unwrap();
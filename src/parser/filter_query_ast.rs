@@ -7,6 +7,14 @@
 //! - `content contains "spam"`
 //! - `kind in [6, 7] AND content matches "(bot|spam)"`
 //! - `(kind == 6 OR kind == 7) AND NOT npub in ["npub1..."]`
+//! - `created_at > now() + 10m`
+//! - `created_at - referenced_created_at < 2`
+//! - `any tag[p].value in ["..."]`, `tag[e][3] == "mention"`
+//! - `npub in @known_bots`, `content contains_any @spam_words`
+//!
+//! `Expr`, `Condition`, `Field` and `Value` print back as canonical query text
+//! with only the parentheses the grammar needs, so `parse(&expr.to_string())`
+//! gives the same AST.
 
 use serde::{Deserialize, Serialize};
 
@@ -17,6 +25,10 @@ pub enum Token {
     Ident(String),
     String(String),
     Number(i64),
+    /// Duration literal (`10m`, `2h`, `7d`) in seconds
+    Duration(i64),
+    /// Named list reference (`@known_bots`)
+    ListRef(String),
     
     // Comparison operators
     Eq,         // ==
@@ -25,15 +37,26 @@ pub enum Token {
     Lt,         // <
     Ge,         // >=
     Le,         // <=
+
+    // Arithmetic operators
+    Plus,       // +
+    Minus,      // -
+    Star,       // *
+    Slash,      // /
     
     // String operators (keywords)
     Contains,
+    ContainsAny,
     StartsWith,
     EndsWith,
     Matches,
     In,
     NotIn,
     Exists,
+
+    // Quantifiers over every tag of a name
+    Any,
+    All,
     
     // Logical operators
     And,
@@ -58,19 +81,28 @@ impl std::fmt::Display for Token {
             Token::Ident(s) => write!(f, "{}", s),
             Token::String(s) => write!(f, "\"{}\"", s),
             Token::Number(n) => write!(f, "{}", n),
+            Token::Duration(secs) => write!(f, "{}s", secs),
+            Token::ListRef(name) => write!(f, "@{}", name),
             Token::Eq => write!(f, "=="),
             Token::Ne => write!(f, "!="),
             Token::Gt => write!(f, ">"),
             Token::Lt => write!(f, "<"),
             Token::Ge => write!(f, ">="),
             Token::Le => write!(f, "<="),
+            Token::Plus => write!(f, "+"),
+            Token::Minus => write!(f, "-"),
+            Token::Star => write!(f, "*"),
+            Token::Slash => write!(f, "/"),
             Token::Contains => write!(f, "contains"),
+            Token::ContainsAny => write!(f, "contains_any"),
             Token::StartsWith => write!(f, "starts_with"),
             Token::EndsWith => write!(f, "ends_with"),
             Token::Matches => write!(f, "matches"),
             Token::In => write!(f, "in"),
             Token::NotIn => write!(f, "not_in"),
             Token::Exists => write!(f, "exists"),
+            Token::Any => write!(f, "any"),
+            Token::All => write!(f, "all"),
             Token::And => write!(f, "AND"),
             Token::Or => write!(f, "OR"),
             Token::Not => write!(f, "NOT"),
@@ -115,14 +147,67 @@ pub enum Expr {
     Condition(Condition),
 }
 
-/// A single condition (field operator value)
+impl std::fmt::Display for Expr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // AND は OR より強く、どちらも左結合。NOT は条件か NOT にしか付かない
+        let grouped = |expr: &Expr, needs_parens: bool| {
+            if needs_parens { format!("({})", expr) } else { expr.to_string() }
+        };
+        match self {
+            Expr::Or { left, right } => {
+                write!(f, "{} OR {}", left, grouped(right, matches!(**right, Expr::Or { .. })))
+            }
+            Expr::And { left, right } => write!(
+                f,
+                "{} AND {}",
+                grouped(left, matches!(**left, Expr::Or { .. })),
+                grouped(right, matches!(**right, Expr::Or { .. } | Expr::And { .. }))
+            ),
+            Expr::Not { expr } => write!(f, "NOT {}", grouped(expr, matches!(**expr, Expr::Or { .. } | Expr::And { .. }))),
+            Expr::Condition(cond) => write!(f, "{}", cond),
+        }
+    }
+}
+
+/// A single condition ([any | all] field operator value)
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct Condition {
+    /// Test every tag of the name instead of the first one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quantifier: Option<Quantifier>,
     pub field: Field,
     pub op: Operator,
     pub value: Value,
 }
 
+impl std::fmt::Display for Condition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(quantifier) = self.quantifier {
+            write!(f, "{} ", quantifier)?;
+        }
+        write!(f, "{} {} {}", self.field, self.op, self.value)
+    }
+}
+
+/// How the values of every matching tag combine
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Quantifier {
+    /// At least one tag satisfies the condition
+    Any,
+    /// There is at least one tag and every one satisfies the condition
+    All,
+}
+
+impl std::fmt::Display for Quantifier {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Quantifier::Any => write!(f, "any"),
+            Quantifier::All => write!(f, "all"),
+        }
+    }
+}
+
 /// Field reference in a condition
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(tag = "type")]
@@ -137,8 +222,14 @@ pub enum Field {
     TagCount { tag_name: String },
     /// Tag value: tag[e].value
     TagValue { tag_name: String },
+    /// Tag element by position: tag[e][3] (tag[e].value is tag[e][1])
+    TagIndex { tag_name: String, index: usize },
     /// Referenced event's created_at (for bot detection)
     ReferencedCreatedAt,
+    /// Seconds since created_at (negative for future events)
+    Age,
+    /// Arithmetic over fields: `created_at - referenced_created_at`
+    Computed { expr: Box<Arith> },
 }
 
 impl Field {
@@ -147,12 +238,76 @@ impl Field {
         match self {
             Field::Simple { name } => name.clone(),
             Field::ContentLength => "content_length".to_string(),
-            Field::Tag { tag_name } => format!("tag[{}]", tag_name),
-            Field::TagCount { tag_name } => format!("tag[{}].count", tag_name),
-            Field::TagValue { tag_name } => format!("tag[{}].value", tag_name),
+            Field::Tag { tag_name } => format!("tag[{}]", tag_name_text(tag_name)),
+            Field::TagCount { tag_name } => format!("tag[{}].count", tag_name_text(tag_name)),
+            Field::TagValue { tag_name } => format!("tag[{}].value", tag_name_text(tag_name)),
+            Field::TagIndex { tag_name, index } => format!("tag[{}][{}]", tag_name_text(tag_name), index),
             Field::ReferencedCreatedAt => "referenced_created_at".to_string(),
+            Field::Age => "age".to_string(),
+            Field::Computed { expr } => expr.to_string(),
+        }
+    }
+
+    /// The event fields this field is made of (itself unless computed)
+    pub fn fields(&self) -> Vec<&Field> {
+        match self {
+            Field::Computed { expr } => expr.fields(),
+            field => vec![field],
+        }
+    }
+
+    /// Whether the field has one value per tag, so `any` / `all` apply
+    pub fn is_per_tag(&self) -> bool {
+        matches!(self, Field::TagValue { .. } | Field::TagIndex { .. })
+    }
+
+    /// Whether the field resolves to a number
+    pub fn is_numeric(&self) -> bool {
+        match self {
+            Field::Simple { name } => name == "kind" || name == "created_at",
+            Field::ContentLength | Field::TagCount { .. } | Field::ReferencedCreatedAt | Field::Age | Field::Computed { .. } => true,
+            Field::Tag { .. } | Field::TagValue { .. } | Field::TagIndex { .. } => false,
+        }
+    }
+}
+
+impl std::fmt::Display for Field {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Words the lexer reads as keywords (compared in lowercase)
+const KEYWORDS: [&str; 15] = [
+    "and", "or", "not", "contains", "contains_any", "starts_with", "ends_with", "matches",
+    "in", "not_in", "exists", "any", "all", "true", "false",
+];
+
+/// Tag name as written in `tag[...]`: bare when it lexes back as the same identifier
+fn tag_name_text(name: &str) -> String {
+    let mut chars = name.chars();
+    let bare = chars.next().is_some_and(|c| c.is_alphabetic() || c == '_')
+        && chars.all(|c| c.is_alphanumeric() || c == '_')
+        && !KEYWORDS.contains(&name.to_lowercase().as_str());
+    if bare { name.to_string() } else { quote(name) }
+}
+
+/// String literal with the escapes the lexer understands
+fn quote(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            '\r' => quoted.push_str("\\r"),
+            c => quoted.push(c),
         }
     }
+    quoted.push('"');
+    quoted
 }
 
 /// Comparison operator
@@ -173,6 +328,8 @@ pub enum Operator {
     Le,
     /// String contains: contains
     Contains,
+    /// String contains any item of a list: contains_any
+    ContainsAny,
     /// String starts with: starts_with
     StartsWith,
     /// String ends with: ends_with
@@ -197,6 +354,7 @@ impl std::fmt::Display for Operator {
             Operator::Ge => write!(f, ">="),
             Operator::Le => write!(f, "<="),
             Operator::Contains => write!(f, "contains"),
+            Operator::ContainsAny => write!(f, "contains_any"),
             Operator::StartsWith => write!(f, "starts_with"),
             Operator::EndsWith => write!(f, "ends_with"),
             Operator::Matches => write!(f, "matches"),
@@ -219,8 +377,66 @@ pub enum Value {
     Bool(bool),
     /// List of values (for in/not_in)
     List(Vec<Value>),
+    /// Named list from `filter_lists` (`@known_bots`), resolved when compiled
+    ListRef {
+        list: String,
+        /// Offset of the `@` in the query text, for unknown list errors
+        #[serde(skip)]
+        position: usize,
+    },
     /// Field reference (for comparing two fields)
     Field(Box<Field>),
+    /// `now()` or arithmetic (for relative time comparisons)
+    Arith(Box<Arith>),
+}
+
+/// Arithmetic operator
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ArithOp {
+    /// Addition: +
+    Add,
+    /// Subtraction: -
+    Sub,
+    /// Multiplication: *
+    Mul,
+    /// Integer division: / (no match when dividing by zero)
+    Div,
+}
+
+impl ArithOp {
+    /// Binding strength: `*` and `/` before `+` and `-`
+    pub fn precedence(&self) -> u8 {
+        match self {
+            ArithOp::Add | ArithOp::Sub => 1,
+            ArithOp::Mul | ArithOp::Div => 2,
+        }
+    }
+}
+
+impl std::fmt::Display for ArithOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ArithOp::Add => write!(f, "+"),
+            ArithOp::Sub => write!(f, "-"),
+            ArithOp::Mul => write!(f, "*"),
+            ArithOp::Div => write!(f, "/"),
+        }
+    }
+}
+
+/// Numeric value computed when the filter is evaluated
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type")]
+pub enum Arith {
+    /// Current Unix time: now()
+    Now,
+    /// left op right (numbers, durations, fields or nested arithmetic)
+    Binary {
+        op: ArithOp,
+        left: Value,
+        right: Value,
+    },
 }
 
 impl Value {
@@ -244,6 +460,70 @@ impl Value {
             _ => None,
         }
     }
+
+    /// Fields this value refers to, including those inside arithmetic
+    pub fn fields(&self) -> Vec<&Field> {
+        match self {
+            Value::Field(f) => f.fields(),
+            Value::List(items) => items.iter().flat_map(Value::fields).collect(),
+            Value::Arith(arith) => arith.fields(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Query text of the value
+    pub fn text(&self) -> String {
+        match self {
+            Value::String(s) => quote(s),
+            Value::Number(n) => n.to_string(),
+            Value::Bool(b) => b.to_string(),
+            Value::List(items) => format!("[{}]", items.iter().map(Value::text).collect::<Vec<_>>().join(", ")),
+            Value::ListRef { list, .. } => format!("@{}", list),
+            Value::Field(field) => field.name(),
+            Value::Arith(arith) => arith.to_string(),
+        }
+    }
+}
+
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.text())
+    }
+}
+
+impl Arith {
+    /// Fields used in the arithmetic
+    pub fn fields(&self) -> Vec<&Field> {
+        match self {
+            Arith::Now => Vec::new(),
+            Arith::Binary { left, right, .. } => {
+                let mut fields = left.fields();
+                fields.extend(right.fields());
+                fields
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for Arith {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Arith::Now => write!(f, "now()"),
+            Arith::Binary { op, left, right } => {
+                // 左結合なので、右側は同じ優先順位でも括弧が必要
+                let operand = |value: &Value, needs_parens: &dyn Fn(u8) -> bool| match value {
+                    Value::Arith(inner) => match &**inner {
+                        Arith::Binary { op: inner_op, .. } if needs_parens(inner_op.precedence()) => format!("({})", inner),
+                        _ => inner.to_string(),
+                    },
+                    other => other.text(),
+                };
+                let left = operand(left, &|p| p < op.precedence());
+                let right = operand(right, &|p| p <= op.precedence());
+                write!(f, "{} {} {}", left, op, right)
+            }
+        }
+    }
 }
 
 /// Parse error with position information
@@ -297,6 +577,24 @@ impl ValidationResult {
     }
 }
 
+/// One evaluated node of a filter expression, returned by `CompiledFilter::explain`
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TraceNode {
+    /// `and`, `or`, `not` or `condition`
+    pub node: String,
+    /// The condition as written (e.g. `kind == 1`), or the logical operator
+    pub text: String,
+    pub result: bool,
+    /// Resolved value of the condition's field (null if the event has none)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub field_value: Option<serde_json::Value>,
+    /// Resolved value of a field used as the right-hand side
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub compared_value: Option<serde_json::Value>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<TraceNode>,
+}
+
 /// Extract all field names used in an expression
 pub fn extract_fields(expr: &Expr) -> Vec<String> {
     let mut fields = Vec::new();
@@ -316,9 +614,39 @@ fn extract_fields_recursive(expr: &Expr, fields: &mut Vec<String>) {
             extract_fields_recursive(expr, fields);
         }
         Expr::Condition(cond) => {
-            fields.push(cond.field.name());
-            if let Value::Field(f) = &cond.value {
-                fields.push(f.name());
+            fields.extend(cond.field.fields().into_iter().map(Field::name));
+            fields.extend(cond.value.fields().into_iter().map(Field::name));
+        }
+    }
+}
+
+/// Extract the names of all lists an expression references (`@known_bots` -> `known_bots`)
+pub fn extract_list_refs(expr: &Expr) -> Vec<String> {
+    let mut names: Vec<String> = list_ref_positions(expr).into_iter().map(|(name, _)| name).collect();
+    names.sort();
+    names.dedup();
+    names
+}
+
+/// Every `@name` reference with its position, in source order
+pub fn list_ref_positions(expr: &Expr) -> Vec<(String, usize)> {
+    let mut refs = Vec::new();
+    list_ref_positions_recursive(expr, &mut refs);
+    refs
+}
+
+fn list_ref_positions_recursive(expr: &Expr, refs: &mut Vec<(String, usize)>) {
+    match expr {
+        Expr::And { left, right } | Expr::Or { left, right } => {
+            list_ref_positions_recursive(left, refs);
+            list_ref_positions_recursive(right, refs);
+        }
+        Expr::Not { expr } => {
+            list_ref_positions_recursive(expr, refs);
+        }
+        Expr::Condition(cond) => {
+            if let Value::ListRef { list, position } = &cond.value {
+                refs.push((list.clone(), *position));
             }
         }
     }
@@ -335,17 +663,21 @@ mod tests {
         assert_eq!(Field::Tag { tag_name: "e".to_string() }.name(), "tag[e]");
         assert_eq!(Field::TagCount { tag_name: "p".to_string() }.name(), "tag[p].count");
         assert_eq!(Field::TagValue { tag_name: "e".to_string() }.name(), "tag[e].value");
+        assert_eq!(Field::Age.name(), "age");
+        assert_eq!(Field::TagIndex { tag_name: "e".to_string(), index: 3 }.name(), "tag[e][3]");
     }
 
     #[test]
     fn test_extract_fields() {
         let expr = Expr::And {
             left: Box::new(Expr::Condition(Condition {
+                quantifier: None,
                 field: Field::Simple { name: "kind".to_string() },
                 op: Operator::Eq,
                 value: Value::Number(6),
             })),
             right: Box::new(Expr::Condition(Condition {
+                quantifier: None,
                 field: Field::Simple { name: "content".to_string() },
                 op: Operator::Contains,
                 value: Value::String("test".to_string()),
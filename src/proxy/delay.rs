@@ -0,0 +1,125 @@
+//! Per-connection queue for inbound EVENTs held back by `delay` rules.
+//!
+//! One task per connection publishes the held frames when they fall due, and
+//! the number of frames held at once is capped so a client cannot pile up
+//! timers. The task stops when the connection drops its [`DelayedPublisher`];
+//! frames still held at that point are not sent.
+
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, OwnedSemaphorePermit, Semaphore};
+use tokio::time::Instant;
+
+/// Upper bound on inbound EVENTs one connection may have held by `delay` rules
+pub const MAX_DELAYED_EVENTS: usize = 256;
+
+struct Delayed {
+    due: Instant,
+    seq: u64,
+    event_id: String,
+    text: String,
+    // 公開されるまでキューの枠を占有する
+    _permit: OwnedSemaphorePermit,
+}
+
+impl PartialEq for Delayed {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Delayed {}
+
+impl PartialOrd for Delayed {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Delayed {
+    // BinaryHeapは最大値を先に返すので、期限の早いものを大きいとみなす
+    fn cmp(&self, other: &Self) -> Ordering {
+        (other.due, other.seq).cmp(&(self.due, self.seq))
+    }
+}
+
+/// Handle for scheduling delayed EVENT frames on one connection
+pub struct DelayedPublisher {
+    tx: mpsc::UnboundedSender<Delayed>,
+    slots: Arc<Semaphore>,
+    next_seq: u64,
+}
+
+impl DelayedPublisher {
+    /// Spawn the publishing task; `publish` receives the event id and the frame
+    pub fn spawn<F>(capacity: usize, publish: F) -> Self
+    where
+        F: Fn(&str, String) + Send + 'static,
+    {
+        let (tx, mut rx) = mpsc::unbounded_channel::<Delayed>();
+        tokio::spawn(async move {
+            let mut pending = BinaryHeap::new();
+            loop {
+                let next_due = pending.peek().map(|d: &Delayed| d.due);
+                tokio::select! {
+                    received = rx.recv() => match received {
+                        Some(delayed) => pending.push(delayed),
+                        None => break,
+                    },
+                    _ = tokio::time::sleep_until(next_due.unwrap_or_else(Instant::now)), if next_due.is_some() => {
+                        if let Some(delayed) = pending.pop() {
+                            publish(&delayed.event_id, delayed.text);
+                        }
+                    }
+                }
+            }
+        });
+        Self {
+            tx,
+            slots: Arc::new(Semaphore::new(capacity)),
+            next_seq: 0,
+        }
+    }
+
+    /// Hold `text` for `delay`; false when the queue is full
+    pub fn schedule(&mut self, delay: Duration, event_id: &str, text: String) -> bool {
+        let Ok(permit) = Arc::clone(&self.slots).try_acquire_owned() else {
+            return false;
+        };
+        self.next_seq += 1;
+        self.tx
+            .send(Delayed {
+                due: Instant::now() + delay,
+                seq: self.next_seq,
+                event_id: event_id.to_string(),
+                text,
+                _permit: permit,
+            })
+            .is_ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[tokio::test]
+    async fn test_publishes_in_due_order_and_caps_queue() {
+        let published = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&published);
+        let mut delayed = DelayedPublisher::spawn(2, move |id, _| sink.lock().unwrap().push(id.to_string()));
+
+        assert!(delayed.schedule(Duration::from_millis(60), "late", "[]".to_string()));
+        assert!(delayed.schedule(Duration::from_millis(20), "early", "[]".to_string()));
+        // 枠が埋まっている間は受け付けない
+        assert!(!delayed.schedule(Duration::from_millis(10), "overflow", "[]".to_string()));
+
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        assert_eq!(*published.lock().unwrap(), vec!["early", "late"]);
+        // 公開済みの枠は再利用できる
+        assert!(delayed.schedule(Duration::from_millis(10), "again", "[]".to_string()));
+    }
+}
@@ -0,0 +1,335 @@
+//! Fan-out bookkeeping for a client session connected to several backend relays.
+//!
+//! Every REQ is sent to all backends, so the same event, EOSE, CLOSED and OK
+//! arrive once per backend. `Fanout` decides which of those copies the client sees.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+/// Maximum number of event ids remembered per subscription for de-duplication
+const SEEN_IDS_PER_SUB: usize = 10_000;
+/// Maximum number of published events waiting for an OK from the backends
+const MAX_PENDING_OKS: usize = 1_000;
+
+/// Insertion-ordered set that forgets the oldest ids once it is full
+#[derive(Default)]
+struct SeenIds {
+    set: HashSet<String>,
+    order: VecDeque<String>,
+}
+
+impl SeenIds {
+    /// Returns true if the id was not seen before
+    fn insert(&mut self, id: &str) -> bool {
+        if self.set.contains(id) {
+            return false;
+        }
+        if self.order.len() >= SEEN_IDS_PER_SUB {
+            if let Some(oldest) = self.order.pop_front() {
+                self.set.remove(&oldest);
+            }
+        }
+        self.set.insert(id.to_string());
+        self.order.push_back(id.to_string());
+        true
+    }
+}
+
+struct SubState {
+    generation: u64,
+    seen: SeenIds,
+    eose_from: HashSet<usize>,
+    eose_sent: bool,
+    closed_from: HashSet<usize>,
+}
+
+struct PendingOk {
+    waiting: HashSet<usize>,
+    forwarded: bool,
+}
+
+/// What a lost backend completed on the client's behalf
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct BackendLost {
+    /// Subscriptions whose EOSE is now complete
+    pub eose: Vec<String>,
+    /// Published events that will never get an OK from a backend
+    pub unanswered: Vec<String>,
+}
+
+/// Per-client fan-out state: event de-duplication, EOSE and OK aggregation
+pub struct Fanout {
+    live: HashSet<usize>,
+    subs: HashMap<String, SubState>,
+    pending_oks: HashMap<String, PendingOk>,
+    pending_order: VecDeque<String>,
+    next_generation: u64,
+}
+
+impl Fanout {
+    pub fn new(backends: usize) -> Self {
+        Self {
+            live: (0..backends).collect(),
+            subs: HashMap::new(),
+            pending_oks: HashMap::new(),
+            pending_order: VecDeque::new(),
+            next_generation: 0,
+        }
+    }
+
+    /// Register a (re)opened subscription. A REQ with an existing sub_id replaces it.
+    ///
+    /// Returns the generation to pass to `eose_timeout` for this REQ.
+    pub fn open(&mut self, sub_id: &str) -> u64 {
+        self.next_generation += 1;
+        self.subs.insert(
+            sub_id.to_string(),
+            SubState {
+                generation: self.next_generation,
+                seen: SeenIds::default(),
+                eose_from: HashSet::new(),
+                eose_sent: false,
+                closed_from: HashSet::new(),
+            },
+        );
+        self.next_generation
+    }
+
+    pub fn close(&mut self, sub_id: &str) {
+        self.subs.remove(sub_id);
+    }
+
+    pub fn is_open(&self, sub_id: &str) -> bool {
+        self.subs.contains_key(sub_id)
+    }
+
+    /// Number of subscriptions the client currently has open
+    pub fn open_subscriptions(&self) -> usize {
+        self.subs.len()
+    }
+
+    /// Returns true if the event should be forwarded (first copy for this subscription)
+    pub fn accept_event(&mut self, sub_id: &str, event_id: &str) -> bool {
+        match self.subs.get_mut(sub_id) {
+            Some(sub) => sub.seen.insert(event_id),
+            // Not a subscription we track (closed, or opened before we saw it): pass through
+            None => true,
+        }
+    }
+
+    /// Returns true once every live backend has sent EOSE for the subscription
+    pub fn accept_eose(&mut self, backend: usize, sub_id: &str) -> bool {
+        let Some(sub) = self.subs.get_mut(sub_id) else {
+            return false;
+        };
+        sub.eose_from.insert(backend);
+        Self::take_eose(&self.live, sub)
+    }
+
+    /// Returns true if the EOSE timeout fired before all backends answered
+    pub fn eose_timeout(&mut self, sub_id: &str, generation: u64) -> bool {
+        match self.subs.get_mut(sub_id) {
+            Some(sub) if sub.generation == generation && !sub.eose_sent => {
+                sub.eose_sent = true;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns true once every live backend has sent CLOSED for the subscription
+    pub fn accept_closed(&mut self, backend: usize, sub_id: &str) -> bool {
+        let Some(sub) = self.subs.get_mut(sub_id) else {
+            return false;
+        };
+        sub.closed_from.insert(backend);
+        if self.live.iter().all(|b| sub.closed_from.contains(b)) {
+            self.subs.remove(sub_id);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Record that an EVENT was sent to every live backend
+    pub fn publish(&mut self, event_id: &str) {
+        if self.pending_order.len() >= MAX_PENDING_OKS {
+            if let Some(oldest) = self.pending_order.pop_front() {
+                self.pending_oks.remove(&oldest);
+            }
+        }
+        self.pending_oks.insert(
+            event_id.to_string(),
+            PendingOk {
+                waiting: self.live.clone(),
+                forwarded: false,
+            },
+        );
+        self.pending_order.push_back(event_id.to_string());
+    }
+
+    /// Record an EVENT the proxy already answered itself (e.g. `OK true` for a
+    /// delayed event), so the backends' OKs for it are not forwarded.
+    pub fn publish_answered(&mut self, event_id: &str) {
+        self.publish(event_id);
+        if let Some(pending) = self.pending_oks.get_mut(event_id) {
+            pending.forwarded = true;
+        }
+    }
+
+    /// Returns true if this OK should be forwarded.
+    ///
+    /// The first accepting OK is forwarded immediately. A rejection is only
+    /// forwarded once every backend has rejected the event.
+    pub fn accept_ok(&mut self, backend: usize, event_id: &str, accepted: bool) -> bool {
+        let Some(pending) = self.pending_oks.get_mut(event_id) else {
+            return true;
+        };
+        pending.waiting.remove(&backend);
+        let forward = !pending.forwarded && (accepted || pending.waiting.is_empty());
+        if forward {
+            pending.forwarded = true;
+        }
+        if pending.waiting.is_empty() {
+            self.pending_oks.remove(event_id);
+            self.pending_order.retain(|id| id != event_id);
+        }
+        forward
+    }
+
+    /// Forget a backend that went away.
+    ///
+    /// Returns the subscriptions whose EOSE is now complete and the published
+    /// events that no backend is left to answer.
+    pub fn backend_lost(&mut self, backend: usize) -> BackendLost {
+        self.live.remove(&backend);
+        let mut answered = Vec::new();
+        let mut unanswered = Vec::new();
+        for (event_id, pending) in self.pending_oks.iter_mut() {
+            pending.waiting.remove(&backend);
+            if pending.waiting.is_empty() {
+                // 他のバックエンドの拒否は全員の応答待ちで保留されている
+                if !pending.forwarded {
+                    unanswered.push(event_id.clone());
+                }
+                answered.push(event_id.clone());
+            }
+        }
+        for event_id in &answered {
+            self.pending_oks.remove(event_id);
+        }
+        if !answered.is_empty() {
+            self.pending_order.retain(|id| self.pending_oks.contains_key(id));
+        }
+        let live = &self.live;
+        let eose = self
+            .subs
+            .iter_mut()
+            .filter_map(|(sub_id, sub)| Self::take_eose(live, sub).then(|| sub_id.clone()))
+            .collect();
+        BackendLost { eose, unanswered }
+    }
+
+    /// A backend came back and will answer the replayed subscriptions
+    pub fn backend_restored(&mut self, backend: usize) {
+        self.live.insert(backend);
+    }
+
+    pub fn live_backends(&self) -> usize {
+        self.live.len()
+    }
+
+    fn take_eose(live: &HashSet<usize>, sub: &mut SubState) -> bool {
+        if sub.eose_sent || !live.iter().all(|b| sub.eose_from.contains(b)) {
+            return false;
+        }
+        sub.eose_sent = true;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dedup_events_per_subscription() {
+        let mut fanout = Fanout::new(2);
+        fanout.open("a");
+        fanout.open("b");
+        assert!(fanout.accept_event("a", "ev1"));
+        assert!(!fanout.accept_event("a", "ev1"));
+        assert!(fanout.accept_event("b", "ev1"));
+    }
+
+    #[test]
+    fn test_eose_after_all_backends() {
+        let mut fanout = Fanout::new(2);
+        fanout.open("a");
+        assert!(!fanout.accept_eose(0, "a"));
+        assert!(!fanout.accept_eose(0, "a"));
+        assert!(fanout.accept_eose(1, "a"));
+        assert!(!fanout.accept_eose(1, "a"));
+    }
+
+    #[test]
+    fn test_eose_timeout_once_per_generation() {
+        let mut fanout = Fanout::new(2);
+        let old = fanout.open("a");
+        let current = fanout.open("a");
+        assert!(!fanout.eose_timeout("a", old));
+        assert!(fanout.eose_timeout("a", current));
+        assert!(!fanout.accept_eose(0, "a"));
+        assert!(!fanout.accept_eose(1, "a"));
+    }
+
+    #[test]
+    fn test_backend_lost_completes_eose() {
+        let mut fanout = Fanout::new(2);
+        fanout.open("a");
+        assert!(!fanout.accept_eose(0, "a"));
+        assert_eq!(fanout.backend_lost(1).eose, vec!["a".to_string()]);
+        assert_eq!(fanout.live_backends(), 1);
+
+        fanout.backend_restored(1);
+        assert_eq!(fanout.live_backends(), 2);
+        fanout.open("b");
+        assert!(!fanout.accept_eose(0, "b"));
+        assert!(fanout.accept_eose(1, "b"));
+    }
+
+    #[test]
+    fn test_backend_lost_answers_held_rejection() {
+        let mut fanout = Fanout::new(2);
+        fanout.publish("ev1");
+        fanout.publish("ev2");
+        assert!(!fanout.accept_ok(0, "ev1", false));
+        assert!(fanout.accept_ok(0, "ev2", true));
+
+        // ev1 was rejected by backend 0 and backend 1 will never answer
+        let lost = fanout.backend_lost(1);
+        assert_eq!(lost.unanswered, vec!["ev1".to_string()]);
+        assert_eq!(fanout.backend_lost(0).unanswered, Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_answered_publish_swallows_backend_oks() {
+        let mut fanout = Fanout::new(2);
+        fanout.publish_answered("ev1");
+        assert!(!fanout.accept_ok(0, "ev1", false));
+        assert!(!fanout.accept_ok(1, "ev1", true));
+        fanout.publish_answered("ev2");
+        assert_eq!(fanout.backend_lost(1).unanswered, Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_ok_aggregation() {
+        let mut fanout = Fanout::new(2);
+        fanout.publish("ev1");
+        assert!(!fanout.accept_ok(0, "ev1", false));
+        assert!(fanout.accept_ok(1, "ev1", false));
+
+        fanout.publish("ev2");
+        assert!(fanout.accept_ok(0, "ev2", true));
+        assert!(!fanout.accept_ok(1, "ev2", true));
+    }
+}
@@ -0,0 +1,206 @@
+//! NIP-11 `limitation` fields from `relay_info`, enforced on client messages.
+
+use sqlx::SqlitePool;
+
+use crate::nostr::event::Event;
+
+/// Limits advertised in the NIP-11 document; None means unlimited
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Limitations {
+    pub max_message_length: Option<usize>,
+    pub max_subscriptions: Option<usize>,
+    pub max_filters: Option<usize>,
+    pub max_event_tags: Option<usize>,
+    pub max_content_length: Option<usize>,
+}
+
+/// Which limitation a client message exceeded
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum LimitExceeded {
+    #[error("message length exceeds {0} bytes")]
+    MessageLength(usize),
+    #[error("too many subscriptions (max {0})")]
+    Subscriptions(usize),
+    #[error("too many filters (max {0})")]
+    Filters(usize),
+    #[error("too many tags (max {0})")]
+    EventTags(usize),
+    #[error("content length exceeds {0} characters")]
+    ContentLength(usize),
+}
+
+impl LimitExceeded {
+    /// Reason recorded in `event_rejection_logs`
+    pub fn reason(&self) -> &'static str {
+        match self {
+            LimitExceeded::MessageLength(_) => "message_too_long",
+            LimitExceeded::Subscriptions(_) => "too_many_subscriptions",
+            LimitExceeded::Filters(_) => "too_many_filters",
+            LimitExceeded::EventTags(_) => "too_many_tags",
+            LimitExceeded::ContentLength(_) => "content_too_long",
+        }
+    }
+}
+
+impl Limitations {
+    pub async fn load(pool: &SqlitePool) -> anyhow::Result<Self> {
+        let row = sqlx::query_as::<_, (Option<i64>, Option<i64>, Option<i64>, Option<i64>, Option<i64>)>(
+            "SELECT limitation_max_message_length, limitation_max_subscriptions, limitation_max_filters,
+             limitation_max_event_tags, limitation_max_content_length
+             FROM relay_info WHERE id = 1",
+        )
+        .fetch_optional(pool)
+        .await?;
+        // 0以下は未設定として扱う
+        let limit = |v: Option<i64>| v.and_then(|v| usize::try_from(v).ok()).filter(|v| *v > 0);
+        Ok(row
+            .map(|(msg, subs, filters, tags, content)| Self {
+                max_message_length: limit(msg),
+                max_subscriptions: limit(subs),
+                max_filters: limit(filters),
+                max_event_tags: limit(tags),
+                max_content_length: limit(content),
+            })
+            .unwrap_or_default())
+    }
+
+    /// Raw frame size in bytes
+    pub fn check_message(&self, text: &str) -> Result<(), LimitExceeded> {
+        match self.max_message_length {
+            Some(max) if text.len() > max => Err(LimitExceeded::MessageLength(max)),
+            _ => Ok(()),
+        }
+    }
+
+    /// `open` is the number of subscriptions the connection already has,
+    /// not counting `replaces_existing` (a REQ reusing an open sub_id)
+    pub fn check_req(&self, filters: usize, open: usize, replaces_existing: bool) -> Result<(), LimitExceeded> {
+        if let Some(max) = self.max_filters {
+            if filters > max {
+                return Err(LimitExceeded::Filters(max));
+            }
+        }
+        if let Some(max) = self.max_subscriptions {
+            if !replaces_existing && open >= max {
+                return Err(LimitExceeded::Subscriptions(max));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn check_event(&self, event: &Event) -> Result<(), LimitExceeded> {
+        if let Some(max) = self.max_event_tags {
+            if event.tags.len() > max {
+                return Err(LimitExceeded::EventTags(max));
+            }
+        }
+        if let Some(max) = self.max_content_length {
+            // NIP-11: max_content_length はUnicode文字数
+            if event.content.chars().count() > max {
+                return Err(LimitExceeded::ContentLength(max));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Bytes of an oversized frame searched for the event id
+const EVENT_ID_SCAN_BYTES: usize = 1024;
+
+/// Event id of an `["EVENT", {...}]` frame, found by scanning only its first bytes.
+///
+/// Used for frames too large to parse, so the client still gets an OK for its
+/// EVENT. Returns None when the frame is not an EVENT or the id is not near the start.
+pub fn scan_event_id(text: &str) -> Option<&str> {
+    let head = &text.as_bytes()[..text.len().min(EVENT_ID_SCAN_BYTES)];
+    let skip_ws = |mut i: usize| {
+        while head.get(i).is_some_and(|b| b.is_ascii_whitespace()) {
+            i += 1;
+        }
+        i
+    };
+    let mut i = skip_ws(0);
+    if head.get(i) != Some(&b'[') {
+        return None;
+    }
+    i = skip_ws(i + 1);
+    if !head[i..].starts_with(br#""EVENT""#) {
+        return None;
+    }
+    let mut from = i;
+    while let Some(pos) = head[from..].windows(4).position(|w| w == br#""id""#) {
+        let key = from + pos;
+        from = key + 4;
+        let mut j = skip_ws(from);
+        if head.get(j) != Some(&b':') {
+            continue;
+        }
+        j = skip_ws(j + 1);
+        let value = head.get(j + 1..j + 66)?;
+        if head[j] == b'"' && value[64] == b'"' && value[..64].iter().all(u8::is_ascii_hexdigit) {
+            return Some(&text[j + 1..j + 65]);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(tags: usize, content: &str) -> Event {
+        Event {
+            id: "id".to_string(),
+            pubkey: "pubkey".to_string(),
+            created_at: 0,
+            kind: 1,
+            tags: vec![vec!["t".to_string(), "x".to_string()]; tags],
+            content: content.to_string(),
+            sig: "sig".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_unlimited_by_default() {
+        let limits = Limitations::default();
+        assert_eq!(limits.check_message(&"x".repeat(1 << 20)), Ok(()));
+        assert_eq!(limits.check_req(100, 100, false), Ok(()));
+        assert_eq!(limits.check_event(&event(100, "hello")), Ok(()));
+    }
+
+    #[test]
+    fn test_limits() {
+        let limits = Limitations {
+            max_message_length: Some(10),
+            max_subscriptions: Some(2),
+            max_filters: Some(3),
+            max_event_tags: Some(2),
+            max_content_length: Some(3),
+        };
+        assert_eq!(limits.check_message("0123456789"), Ok(()));
+        assert_eq!(limits.check_message("0123456789a"), Err(LimitExceeded::MessageLength(10)));
+
+        assert_eq!(limits.check_req(4, 0, false), Err(LimitExceeded::Filters(3)));
+        assert_eq!(limits.check_req(1, 2, false), Err(LimitExceeded::Subscriptions(2)));
+        assert_eq!(limits.check_req(1, 2, true), Ok(()));
+
+        assert_eq!(limits.check_event(&event(3, "")), Err(LimitExceeded::EventTags(2)));
+        // 文字数で数える（バイト数ではない）
+        assert_eq!(limits.check_event(&event(0, "あいう")), Ok(()));
+        assert_eq!(limits.check_event(&event(0, "あいうえ")), Err(LimitExceeded::ContentLength(3)));
+    }
+
+    #[test]
+    fn test_scan_event_id() {
+        let id = "ab".repeat(32);
+        let frame = format!(r#"[ "EVENT", {{"id" : "{id}", "content": "{}"}}]"#, "x".repeat(1 << 16));
+        assert_eq!(scan_event_id(&frame), Some(id.as_str()));
+        // an "id" key quoted inside the content is not the event id
+        let frame = format!(r#"["EVENT",{{"content":"\"id\":\"{}\"","id":"{id}"}}]"#, "00".repeat(32));
+        assert_eq!(scan_event_id(&frame), Some(id.as_str()));
+        assert_eq!(scan_event_id(&format!(r#"["REQ","sub",{{"id":"{id}"}}]"#)), None);
+        assert_eq!(scan_event_id(r#"["EVENT",{"id":"short"}]"#), None);
+        let late = format!(r#"["EVENT",{{"content":"{}","id":"{id}"}}]"#, "x".repeat(2048));
+        assert_eq!(scan_event_id(&late), None);
+    }
+}
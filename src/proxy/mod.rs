@@ -1,2 +1,7 @@
+pub mod delay;
+pub mod fanout;
+pub mod limits;
+pub mod pool;
+pub mod rate_limit;
+pub mod upstream;
 pub mod ws_proxy;
-
@@ -0,0 +1,456 @@
+//! Shared upstream connection pool.
+//!
+//! All client sessions share one supervised connection per backend relay.
+//! Client subscription ids are rewritten to pool-unique ids on the way up, and
+//! EVENT/EOSE/CLOSED/OK frames are routed back to the owning client with the
+//! original id restored.
+
+use serde_json::Value;
+use sqlx::SqlitePool;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::protocol::Message as TungMessage;
+
+use crate::nostr::event::Event;
+use super::upstream::{self, load_enabled_urls, SlotUrls, Subscriptions, Upstream, UpstreamEvent};
+
+/// Maximum number of published event ids remembered for OK routing
+const MAX_PUBLISHED: usize = 10_000;
+
+pub type ClientId = u64;
+
+#[derive(Default)]
+struct Routes {
+    clients: HashMap<ClientId, mpsc::UnboundedSender<UpstreamEvent>>,
+    // backend sub_id -> (client, client sub_id)
+    subs: HashMap<String, (ClientId, String)>,
+    // client -> client sub_id -> backend sub_id
+    client_subs: HashMap<ClientId, HashMap<String, String>>,
+    // event id -> clients that published it
+    publishers: HashMap<String, Vec<ClientId>>,
+    publish_order: VecDeque<String>,
+    next_client_id: ClientId,
+    next_sub_id: u64,
+}
+
+struct Inner {
+    db: Option<SqlitePool>,
+    static_urls: Vec<String>,
+    slots: Mutex<Vec<Upstream>>,
+    slot_urls: SlotUrls,
+    live: Mutex<Vec<bool>>,
+    subs: Subscriptions,
+    routes: Mutex<Routes>,
+    events_tx: mpsc::UnboundedSender<UpstreamEvent>,
+}
+
+/// Process-wide pool of backend relay connections shared by every client
+#[derive(Clone)]
+pub struct UpstreamPool {
+    inner: Arc<Inner>,
+}
+
+impl UpstreamPool {
+    /// Pool backed by the enabled rows of `relay_config`.
+    ///
+    /// Slots are opened lazily when the first client registers, and a slot is
+    /// added whenever a newly enabled relay shows up.
+    pub fn new(db: SqlitePool) -> Self {
+        Self::build(Some(db), Vec::new())
+    }
+
+    /// Pool with a fixed set of backend relays (no database)
+    pub fn with_urls(urls: Vec<String>) -> Self {
+        Self::build(None, urls)
+    }
+
+    fn build(db: Option<SqlitePool>, static_urls: Vec<String>) -> Self {
+        let (events_tx, events_rx) = mpsc::unbounded_channel();
+        let inner = Arc::new(Inner {
+            db,
+            static_urls,
+            slots: Mutex::new(Vec::new()),
+            slot_urls: SlotUrls::default(),
+            live: Mutex::new(Vec::new()),
+            subs: Subscriptions::default(),
+            routes: Mutex::new(Routes::default()),
+            events_tx,
+        });
+        tokio::spawn(route_events(Arc::downgrade(&inner), events_rx));
+        Self { inner }
+    }
+
+    /// Open slots for enabled relays that do not have one yet and close the
+    /// slots of relays that are no longer enabled. Returns the active slot count.
+    async fn ensure_slots(&self) -> usize {
+        let urls = match &self.inner.db {
+            Some(db) => load_enabled_urls(db).await,
+            None => self.inner.static_urls.clone(),
+        };
+        let mut slots = self.inner.slots.lock().unwrap();
+        // 無効化・削除されたリレーのスロットは閉じる（インデックスは詰めずに欠番にする）
+        for index in self.inner.slot_urls.retire_missing(&urls) {
+            tracing::info!(slot = index, "Closing backend slot for disabled relay");
+            if let Some(slot) = slots.get(index) {
+                let _ = slot.send(TungMessage::Close(None));
+            }
+            self.inner.dispatch(UpstreamEvent::Disconnected(index));
+        }
+        for url in &urls {
+            // フェイルオーバー先も含め、既にどこかのスロットが使っているURLは開かない
+            if self.inner.slot_urls.contains(url) {
+                continue;
+            }
+            let index = self.inner.slot_urls.push(url);
+            tracing::info!(backend_url = %url, slot = index, "Opening shared backend slot");
+            self.inner.live.lock().unwrap().push(true);
+            // 接続中のクライアントにも新しいスロットの応答を待たせる（監視タスクの通知より先に届ける）
+            self.inner.dispatch(UpstreamEvent::Added(index));
+            slots.push(upstream::spawn(
+                index,
+                url,
+                &urls,
+                self.inner.db.clone(),
+                self.inner.subs.clone(),
+                self.inner.slot_urls.clone(),
+                self.inner.events_tx.clone(),
+            ));
+        }
+        self.inner.slot_urls.active()
+    }
+
+    /// Register a client session.
+    ///
+    /// Returns None if no backend relay is configured. Otherwise returns the
+    /// handle used to talk upstream, the channel of frames routed to this
+    /// client, and which slots are currently connected.
+    pub async fn register(&self) -> Option<(ClientHandle, mpsc::UnboundedReceiver<UpstreamEvent>, Vec<bool>)> {
+        if self.ensure_slots().await == 0 {
+            return None;
+        }
+        let (tx, rx) = mpsc::unbounded_channel();
+        let id = {
+            let mut routes = self.inner.routes.lock().unwrap();
+            routes.next_client_id += 1;
+            let id = routes.next_client_id;
+            routes.clients.insert(id, tx);
+            id
+        };
+        let live = self.inner.live.lock().unwrap().clone();
+        Some((ClientHandle { id, inner: Arc::clone(&self.inner) }, rx, live))
+    }
+
+    /// One-shot REQ for a single event.
+    ///
+    /// Returns the first event any backend sends for `filter`, or None once
+    /// every connected backend has sent EOSE or `timeout` passes.
+    pub async fn fetch_event(&self, filter: Value, timeout: Duration) -> Option<Event> {
+        const SUB_ID: &str = "fetch";
+        let (handle, mut rx, live) = self.register().await?;
+        let mut pending_eose = live.iter().filter(|l| **l).count();
+        if pending_eose == 0 {
+            return None;
+        }
+        handle.req(SUB_ID, vec![filter]);
+        let wait = async {
+            while let Some(frame) = rx.recv().await {
+                let UpstreamEvent::Message(_, TungMessage::Text(text)) = frame else {
+                    continue;
+                };
+                let Ok(Value::Array(arr)) = serde_json::from_str::<Value>(&text) else {
+                    continue;
+                };
+                match arr.first().and_then(|v| v.as_str()) {
+                    Some("EVENT") => {
+                        if let Some(event) = arr.get(2).and_then(|v| serde_json::from_value(v.clone()).ok()) {
+                            return Some(event);
+                        }
+                    }
+                    Some("EOSE") | Some("CLOSED") => {
+                        pending_eose = pending_eose.saturating_sub(1);
+                        if pending_eose == 0 {
+                            return None;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            None
+        };
+        // dropping the handle closes the subscription
+        tokio::time::timeout(timeout, wait).await.ok().flatten()
+    }
+}
+
+/// A client session's view of the pool. Dropping it closes the client's subscriptions.
+pub struct ClientHandle {
+    id: ClientId,
+    inner: Arc<Inner>,
+}
+
+impl ClientHandle {
+    pub fn id(&self) -> ClientId {
+        self.id
+    }
+
+    /// Open (or replace) a subscription on every backend
+    pub fn req(&self, sub_id: &str, filters: Vec<Value>) {
+        let backend_sub_id = {
+            let mut routes = self.inner.routes.lock().unwrap();
+            let existing = routes
+                .client_subs
+                .get(&self.id)
+                .and_then(|subs| subs.get(sub_id))
+                .cloned();
+            match existing {
+                Some(id) => id,
+                None => {
+                    routes.next_sub_id += 1;
+                    let id = format!("p{}", routes.next_sub_id);
+                    routes.subs.insert(id.clone(), (self.id, sub_id.to_string()));
+                    routes
+                        .client_subs
+                        .entry(self.id)
+                        .or_default()
+                        .insert(sub_id.to_string(), id.clone());
+                    id
+                }
+            }
+        };
+        let mut req = vec![Value::from("REQ"), Value::from(backend_sub_id.as_str())];
+        req.extend(filters.iter().cloned());
+        self.inner.subs.open(&backend_sub_id, filters);
+        self.inner.broadcast(Value::Array(req).to_string());
+    }
+
+    /// Close a subscription on every backend
+    pub fn close(&self, sub_id: &str) {
+        let backend_sub_id = {
+            let mut routes = self.inner.routes.lock().unwrap();
+            let Some(id) = routes
+                .client_subs
+                .get_mut(&self.id)
+                .and_then(|subs| subs.remove(sub_id))
+            else {
+                return;
+            };
+            routes.subs.remove(&id);
+            id
+        };
+        self.inner.subs.close(&backend_sub_id);
+        self.inner
+            .broadcast(serde_json::json!(["CLOSE", backend_sub_id]).to_string());
+    }
+
+    /// Send an EVENT frame to every backend and route the OKs back to this client
+    pub fn publish(&self, event_id: &str, text: String) {
+        {
+            let mut routes = self.inner.routes.lock().unwrap();
+            if !routes.publishers.contains_key(event_id) {
+                if routes.publish_order.len() >= MAX_PUBLISHED {
+                    if let Some(oldest) = routes.publish_order.pop_front() {
+                        routes.publishers.remove(&oldest);
+                    }
+                }
+                routes.publish_order.push_back(event_id.to_string());
+            }
+            let publishers = routes.publishers.entry(event_id.to_string()).or_default();
+            if !publishers.contains(&self.id) {
+                publishers.push(self.id);
+            }
+        }
+        self.inner.broadcast(text);
+    }
+}
+
+impl Drop for ClientHandle {
+    fn drop(&mut self) {
+        let backend_sub_ids: Vec<String> = {
+            let mut routes = self.inner.routes.lock().unwrap();
+            routes.clients.remove(&self.id);
+            let ids: Vec<String> = routes
+                .client_subs
+                .remove(&self.id)
+                .map(|subs| subs.into_values().collect())
+                .unwrap_or_default();
+            for id in &ids {
+                routes.subs.remove(id);
+            }
+            ids
+        };
+        for id in backend_sub_ids {
+            self.inner.subs.close(&id);
+            self.inner.broadcast(serde_json::json!(["CLOSE", id]).to_string());
+        }
+    }
+}
+
+impl Inner {
+    fn broadcast(&self, text: String) {
+        for slot in self.slots.lock().unwrap().iter() {
+            let _ = slot.send(TungMessage::Text(text.clone()));
+        }
+    }
+
+    /// Route one frame from a backend slot to the client(s) it belongs to
+    fn dispatch(&self, event: UpstreamEvent) {
+        let (slot, text) = match event {
+            UpstreamEvent::Added(slot) => {
+                for tx in self.routes.lock().unwrap().clients.values() {
+                    let _ = tx.send(UpstreamEvent::Added(slot));
+                }
+                return;
+            }
+            UpstreamEvent::Connected(slot) | UpstreamEvent::Disconnected(slot) => {
+                let connected = matches!(event, UpstreamEvent::Connected(_));
+                // 閉じる直前に接続できたスロットの通知は無視する
+                if connected && self.slot_urls.is_retired(slot) {
+                    return;
+                }
+                if let Some(live) = self.live.lock().unwrap().get_mut(slot) {
+                    *live = connected;
+                }
+                let routes = self.routes.lock().unwrap();
+                for tx in routes.clients.values() {
+                    let ev = if connected {
+                        UpstreamEvent::Connected(slot)
+                    } else {
+                        UpstreamEvent::Disconnected(slot)
+                    };
+                    let _ = tx.send(ev);
+                }
+                return;
+            }
+            UpstreamEvent::Message(slot, TungMessage::Text(text)) => (slot, text),
+            // Binary/ping/pong frames are not client-specific
+            UpstreamEvent::Message(..) => return,
+        };
+
+        let Ok(Value::Array(mut arr)) = serde_json::from_str::<Value>(&text) else {
+            return;
+        };
+        let cmd = arr.first().and_then(|v| v.as_str()).unwrap_or("").to_string();
+        let arg = arr.get(1).and_then(|v| v.as_str()).unwrap_or("").to_string();
+        let routes = self.routes.lock().unwrap();
+        match cmd.as_str() {
+            "EVENT" | "EOSE" | "CLOSED" => {
+                let Some((client_id, client_sub_id)) = routes.subs.get(&arg) else {
+                    return; // subscription already closed
+                };
+                let Some(tx) = routes.clients.get(client_id) else {
+                    return;
+                };
+                arr[1] = Value::from(client_sub_id.as_str());
+                let rewritten = Value::Array(arr).to_string();
+                let _ = tx.send(UpstreamEvent::Message(slot, TungMessage::Text(rewritten)));
+            }
+            "OK" => {
+                let Some(publishers) = routes.publishers.get(&arg) else {
+                    return;
+                };
+                for client_id in publishers {
+                    if let Some(tx) = routes.clients.get(client_id) {
+                        let _ = tx.send(UpstreamEvent::Message(slot, TungMessage::Text(text.clone())));
+                    }
+                }
+            }
+            "NOTICE" => {
+                tracing::info!(slot, notice = %arg, "Backend NOTICE");
+            }
+            _ => {
+                tracing::debug!(slot, command = %cmd, "Ignoring backend message");
+            }
+        }
+    }
+}
+
+async fn route_events(inner: Weak<Inner>, mut events_rx: mpsc::UnboundedReceiver<UpstreamEvent>) {
+    while let Some(event) = events_rx.recv().await {
+        let Some(inner) = inner.upgrade() else {
+            break;
+        };
+        inner.dispatch(event);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_rewrites_and_routes_subscriptions() {
+        // No slots: frames are only routed, nothing is sent upstream
+        let pool = UpstreamPool::with_urls(Vec::new());
+        let (tx_a, mut rx_a) = mpsc::unbounded_channel();
+        let (tx_b, mut rx_b) = mpsc::unbounded_channel();
+        {
+            let mut routes = pool.inner.routes.lock().unwrap();
+            routes.clients.insert(1, tx_a);
+            routes.clients.insert(2, tx_b);
+        }
+        let a = ClientHandle { id: 1, inner: Arc::clone(&pool.inner) };
+        let b = ClientHandle { id: 2, inner: Arc::clone(&pool.inner) };
+        a.req("feed", vec![]);
+        b.req("feed", vec![]);
+
+        let (backend_a, backend_b) = {
+            let routes = pool.inner.routes.lock().unwrap();
+            (
+                routes.client_subs[&1]["feed"].clone(),
+                routes.client_subs[&2]["feed"].clone(),
+            )
+        };
+        assert_ne!(backend_a, backend_b);
+
+        let eose = serde_json::json!(["EOSE", backend_b]).to_string();
+        pool.inner.dispatch(UpstreamEvent::Message(0, TungMessage::Text(eose)));
+        assert!(rx_a.try_recv().is_err());
+        match rx_b.try_recv() {
+            Ok(UpstreamEvent::Message(0, TungMessage::Text(text))) => {
+                assert_eq!(text, r#"["EOSE","feed"]"#);
+            }
+            other => panic!("unexpected: {:?}", other),
+        }
+
+        drop(b);
+        let eose = serde_json::json!(["EOSE", backend_b]).to_string();
+        pool.inner.dispatch(UpstreamEvent::Message(0, TungMessage::Text(eose)));
+        assert!(rx_a.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_routes_ok_to_publisher() {
+        let pool = UpstreamPool::with_urls(Vec::new());
+        let (tx_a, mut rx_a) = mpsc::unbounded_channel();
+        let (tx_b, mut rx_b) = mpsc::unbounded_channel();
+        {
+            let mut routes = pool.inner.routes.lock().unwrap();
+            routes.clients.insert(1, tx_a);
+            routes.clients.insert(2, tx_b);
+        }
+        let a = ClientHandle { id: 1, inner: Arc::clone(&pool.inner) };
+        a.publish("ev1", String::new());
+
+        let ok = r#"["OK","ev1",true,""]"#.to_string();
+        pool.inner.dispatch(UpstreamEvent::Message(0, TungMessage::Text(ok)));
+        assert!(matches!(rx_a.try_recv(), Ok(UpstreamEvent::Message(0, _))));
+        assert!(rx_b.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_existing_clients_learn_about_new_slots() {
+        let pool = UpstreamPool::with_urls(vec!["ws://127.0.0.1:1".to_string()]);
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        pool.inner.routes.lock().unwrap().clients.insert(1, tx);
+
+        assert_eq!(pool.ensure_slots().await, 1);
+        assert!(matches!(rx.try_recv(), Ok(UpstreamEvent::Added(0))));
+        // 既に開いているスロットは通知しない
+        assert_eq!(pool.ensure_slots().await, 1);
+        while let Ok(ev) = rx.try_recv() {
+            assert!(!matches!(ev, UpstreamEvent::Added(_)));
+        }
+    }
+}
@@ -0,0 +1,265 @@
+//! Token-bucket rate limits per client IP and per event pubkey.
+//!
+//! Limits live in the `rate_limits` table and are re-read at most every
+//! `RELOAD_INTERVAL`. Buckets are kept in memory and shared by all connections.
+
+use sqlx::SqlitePool;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How long loaded limits are reused before reading `rate_limits` again
+const RELOAD_INTERVAL: Duration = Duration::from_secs(10);
+/// Idle buckets are pruned once a map grows past this size
+const MAX_BUCKETS: usize = 10_000;
+/// A bucket untouched for this long has refilled completely and can be forgotten
+const BUCKET_IDLE: Duration = Duration::from_secs(60);
+
+/// Limits from `rate_limits`; None means unlimited
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RateLimits {
+    pub events_per_minute_per_ip: Option<u32>,
+    pub events_per_minute_per_pubkey: Option<u32>,
+    pub reqs_per_minute_per_ip: Option<u32>,
+    pub max_connections_per_ip: Option<u32>,
+}
+
+impl RateLimits {
+    pub async fn load(pool: &SqlitePool) -> anyhow::Result<Self> {
+        let row = sqlx::query_as::<_, (Option<i64>, Option<i64>, Option<i64>, Option<i64>)>(
+            "SELECT events_per_minute_per_ip, events_per_minute_per_pubkey, reqs_per_minute_per_ip, max_connections_per_ip
+             FROM rate_limits WHERE id = 1",
+        )
+        .fetch_optional(pool)
+        .await?;
+        let limit = |v: Option<i64>| v.and_then(|v| u32::try_from(v).ok());
+        Ok(row
+            .map(|(ev_ip, ev_pubkey, req_ip, conns)| Self {
+                events_per_minute_per_ip: limit(ev_ip),
+                events_per_minute_per_pubkey: limit(ev_pubkey),
+                reqs_per_minute_per_ip: limit(req_ip),
+                max_connections_per_ip: limit(conns),
+            })
+            .unwrap_or_default())
+    }
+}
+
+/// Which limit was hit
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum RateLimited {
+    #[error("too many connections from this IP")]
+    Connections,
+    #[error("too many events from this IP")]
+    EventsPerIp,
+    #[error("too many events from this pubkey")]
+    EventsPerPubkey,
+    #[error("too many subscriptions from this IP")]
+    ReqsPerIp,
+}
+
+/// Bucket holding up to `per_minute` tokens, refilled continuously
+#[derive(Debug, Clone)]
+struct TokenBucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl TokenBucket {
+    fn full(per_minute: u32, now: Instant) -> Self {
+        Self { tokens: per_minute as f64, updated_at: now }
+    }
+
+    fn try_take(&mut self, per_minute: u32, now: Instant) -> bool {
+        let capacity = per_minute as f64;
+        let elapsed = now.saturating_duration_since(self.updated_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * capacity / 60.0).min(capacity);
+        self.updated_at = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[derive(Default)]
+struct Buckets(HashMap<String, TokenBucket>);
+
+impl Buckets {
+    fn try_take(&mut self, key: &str, per_minute: Option<u32>, now: Instant) -> bool {
+        let Some(per_minute) = per_minute else {
+            return true;
+        };
+        if self.0.len() >= MAX_BUCKETS && !self.0.contains_key(key) {
+            self.0.retain(|_, b| now.saturating_duration_since(b.updated_at) < BUCKET_IDLE);
+        }
+        self.0
+            .entry(key.to_string())
+            .or_insert_with(|| TokenBucket::full(per_minute, now))
+            .try_take(per_minute, now)
+    }
+}
+
+#[derive(Default)]
+struct State {
+    limits: RateLimits,
+    loaded_at: Option<Instant>,
+    events_by_ip: Buckets,
+    events_by_pubkey: Buckets,
+    reqs_by_ip: Buckets,
+    connections: HashMap<String, u32>,
+}
+
+struct Inner {
+    db: Option<SqlitePool>,
+    state: Mutex<State>,
+}
+
+/// Process-wide rate limiter shared by every client connection
+#[derive(Clone)]
+pub struct RateLimiter {
+    inner: Arc<Inner>,
+}
+
+impl RateLimiter {
+    /// Limiter reading its limits from the `rate_limits` table
+    pub fn new(db: SqlitePool) -> Self {
+        Self::build(Some(db), RateLimits::default())
+    }
+
+    /// Limiter with fixed limits (no database)
+    pub fn with_limits(limits: RateLimits) -> Self {
+        Self::build(None, limits)
+    }
+
+    fn build(db: Option<SqlitePool>, limits: RateLimits) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                db,
+                state: Mutex::new(State { limits, ..State::default() }),
+            }),
+        }
+    }
+
+    async fn refresh(&self) {
+        let Some(db) = &self.inner.db else {
+            return;
+        };
+        let stale = {
+            let state = self.inner.state.lock().unwrap();
+            state.loaded_at.map(|t| t.elapsed() > RELOAD_INTERVAL).unwrap_or(true)
+        };
+        if !stale {
+            return;
+        }
+        match RateLimits::load(db).await {
+            Ok(limits) => {
+                let mut state = self.inner.state.lock().unwrap();
+                state.limits = limits;
+                state.loaded_at = Some(Instant::now());
+            }
+            Err(e) => tracing::error!(error = %e, "Failed to load rate limits"),
+        }
+    }
+
+    /// Count a new connection from `ip`; the slot is released when the guard drops
+    pub async fn connect(&self, ip: &str) -> Result<ConnectionGuard, RateLimited> {
+        self.refresh().await;
+        let mut state = self.inner.state.lock().unwrap();
+        let max = state.limits.max_connections_per_ip;
+        let count = state.connections.entry(ip.to_string()).or_insert(0);
+        if max.is_some_and(|max| *count >= max) {
+            return Err(RateLimited::Connections);
+        }
+        *count += 1;
+        Ok(ConnectionGuard {
+            limiter: self.clone(),
+            ip: ip.to_string(),
+        })
+    }
+
+    /// Take a token for an EVENT from `ip` signed by (or authenticated as) `pubkey`
+    pub async fn check_event(&self, ip: &str, pubkey: &str) -> Result<(), RateLimited> {
+        self.refresh().await;
+        let now = Instant::now();
+        let state = &mut *self.inner.state.lock().unwrap();
+        if !state.events_by_ip.try_take(ip, state.limits.events_per_minute_per_ip, now) {
+            return Err(RateLimited::EventsPerIp);
+        }
+        if !state.events_by_pubkey.try_take(pubkey, state.limits.events_per_minute_per_pubkey, now) {
+            return Err(RateLimited::EventsPerPubkey);
+        }
+        Ok(())
+    }
+
+    /// Take a token for a REQ from `ip`
+    pub async fn check_req(&self, ip: &str) -> Result<(), RateLimited> {
+        self.refresh().await;
+        let now = Instant::now();
+        let state = &mut *self.inner.state.lock().unwrap();
+        if !state.reqs_by_ip.try_take(ip, state.limits.reqs_per_minute_per_ip, now) {
+            return Err(RateLimited::ReqsPerIp);
+        }
+        Ok(())
+    }
+
+    fn release(&self, ip: &str) {
+        let mut state = self.inner.state.lock().unwrap();
+        if let Some(count) = state.connections.get_mut(ip) {
+            *count = count.saturating_sub(1);
+            if *count == 0 {
+                state.connections.remove(ip);
+            }
+        }
+    }
+}
+
+/// Holds one of an IP's concurrent connection slots
+pub struct ConnectionGuard {
+    limiter: RateLimiter,
+    ip: String,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.limiter.release(&self.ip);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_bucket_refill() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::full(2, start);
+        assert!(bucket.try_take(2, start));
+        assert!(bucket.try_take(2, start));
+        assert!(!bucket.try_take(2, start));
+        // 2/分 = 30秒で1トークン
+        assert!(bucket.try_take(2, start + Duration::from_secs(30)));
+        assert!(!bucket.try_take(2, start + Duration::from_secs(31)));
+    }
+
+    #[tokio::test]
+    async fn test_limits_per_ip_and_pubkey() {
+        let limiter = RateLimiter::with_limits(RateLimits {
+            events_per_minute_per_ip: Some(2),
+            events_per_minute_per_pubkey: Some(1),
+            reqs_per_minute_per_ip: None,
+            max_connections_per_ip: Some(1),
+        });
+        assert_eq!(limiter.check_event("1.1.1.1", "alice").await, Ok(()));
+        assert_eq!(limiter.check_event("1.1.1.1", "alice").await, Err(RateLimited::EventsPerPubkey));
+        assert_eq!(limiter.check_event("1.1.1.1", "bob").await, Err(RateLimited::EventsPerIp));
+        assert_eq!(limiter.check_event("2.2.2.2", "bob").await, Ok(()));
+        assert_eq!(limiter.check_req("1.1.1.1").await, Ok(()));
+
+        let guard = limiter.connect("1.1.1.1").await.unwrap();
+        assert_eq!(limiter.connect("1.1.1.1").await.err(), Some(RateLimited::Connections));
+        drop(guard);
+        assert!(limiter.connect("1.1.1.1").await.is_ok());
+    }
+}
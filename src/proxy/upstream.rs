@@ -0,0 +1,429 @@
+//! Supervised backend relay connections.
+//!
+//! Each backend slot is driven by a supervisor task that reconnects with
+//! exponential backoff, fails over to the next enabled `relay_config` entry and
+//! replays the open subscriptions on the new connection.
+
+use futures_util::{sink::SinkExt, stream::StreamExt};
+use serde_json::Value;
+use sqlx::SqlitePool;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio_tungstenite::{connect_async, tungstenite::protocol::Message as TungMessage};
+
+use crate::nostr::message::{parse_client_msg, ClientMsg};
+
+/// First reconnect delay; doubled after every failed attempt
+const BACKOFF_BASE: Duration = Duration::from_millis(500);
+/// Upper bound for the reconnect delay
+const BACKOFF_MAX: Duration = Duration::from_secs(60);
+/// A connection that lived this long resets the backoff
+const STABLE_AFTER: Duration = Duration::from_secs(30);
+/// Maximum number of EVENT frames buffered while a backend is unavailable
+const MAX_PENDING_FRAMES: usize = 256;
+
+/// Frames received from backend relays, tagged with the index of the backend slot they came from.
+#[derive(Debug)]
+pub enum UpstreamEvent {
+    /// The pool opened backend slot `index` for a newly enabled relay
+    Added(usize),
+    /// Backend slot `index` (re)connected and replayed the open subscriptions
+    Connected(usize),
+    /// A frame from backend slot `index`
+    Message(usize, TungMessage),
+    /// Backend slot `index` lost its connection and is reconnecting
+    Disconnected(usize),
+}
+
+/// Open subscriptions (by backend-side sub_id), replayed whenever a backend reconnects
+#[derive(Clone, Default)]
+pub struct Subscriptions {
+    inner: Arc<Mutex<HashMap<String, Vec<Value>>>>,
+}
+
+impl Subscriptions {
+    pub fn open(&self, sub_id: &str, filters: Vec<Value>) {
+        self.inner.lock().unwrap().insert(sub_id.to_string(), filters);
+    }
+
+    pub fn close(&self, sub_id: &str) {
+        self.inner.lock().unwrap().remove(sub_id);
+    }
+
+    /// REQ frames for every open subscription
+    fn req_frames(&self) -> Vec<String> {
+        self.inner
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(sub_id, filters)| {
+                let mut req = vec![Value::from("REQ"), Value::from(sub_id.as_str())];
+                req.extend(filters.iter().cloned());
+                Value::Array(req).to_string()
+            })
+            .collect()
+    }
+}
+
+/// Relay URL each slot is currently using, shared by the pool and the supervisors.
+///
+/// Supervisors update their entry on failover so the pool does not open a
+/// second slot for a relay that is already served, and so failover skips them.
+/// Slots whose relay was disabled are retired (None) and keep their index.
+#[derive(Clone, Default)]
+pub struct SlotUrls {
+    inner: Arc<Mutex<Vec<Option<String>>>>,
+}
+
+impl SlotUrls {
+    pub fn contains(&self, url: &str) -> bool {
+        self.inner.lock().unwrap().iter().any(|u| u.as_deref() == Some(url))
+    }
+
+    /// Record the URL of a new slot and return the slot index
+    pub fn push(&self, url: &str) -> usize {
+        let mut urls = self.inner.lock().unwrap();
+        urls.push(Some(url.to_string()));
+        urls.len() - 1
+    }
+
+    /// Number of slots that are not retired
+    pub fn active(&self) -> usize {
+        self.inner.lock().unwrap().iter().flatten().count()
+    }
+
+    pub fn is_retired(&self, index: usize) -> bool {
+        matches!(self.inner.lock().unwrap().get(index), Some(None))
+    }
+
+    /// Retire every slot whose current relay is not in `enabled` and return their indexes
+    pub fn retire_missing(&self, enabled: &[String]) -> Vec<usize> {
+        let mut urls = self.inner.lock().unwrap();
+        let mut retired = Vec::new();
+        for (index, slot) in urls.iter_mut().enumerate() {
+            if slot.as_ref().is_some_and(|url| !enabled.contains(url)) {
+                *slot = None;
+                retired.push(index);
+            }
+        }
+        retired
+    }
+
+    fn set(&self, index: usize, url: &str) {
+        // 退役したスロットは復活させない
+        if let Some(Some(slot)) = self.inner.lock().unwrap().get_mut(index) {
+            *slot = url.to_string();
+        }
+    }
+
+    /// URLs held by every slot except `index`
+    fn held_by_others(&self, index: usize) -> Vec<String> {
+        self.inner
+            .lock()
+            .unwrap()
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| *i != index)
+            .filter_map(|(_, u)| u.clone())
+            .collect()
+    }
+}
+
+/// Write half of a supervised backend slot
+pub struct Upstream {
+    tx: mpsc::UnboundedSender<TungMessage>,
+}
+
+impl Upstream {
+    /// Queue a frame for the backend. Returns false once the supervisor has stopped.
+    pub fn send(&self, msg: TungMessage) -> bool {
+        self.tx.send(msg).is_ok()
+    }
+}
+
+/// DBから有効なバックエンドリレーURLを全て取得
+pub async fn load_enabled_urls(pool: &SqlitePool) -> Vec<String> {
+    let rows: Vec<(String,)> = sqlx::query_as(
+        "SELECT url FROM relay_config WHERE enabled = 1 ORDER BY id ASC"
+    )
+    .fetch_all(pool)
+    .await
+    .unwrap_or_default();
+
+    rows.into_iter().map(|(url,)| url).collect()
+}
+
+/// Start a supervised backend slot.
+///
+/// The slot starts connecting immediately and tags everything it receives with
+/// `index`. Failover candidates are re-read from `relay_config` when a pool is
+/// available, otherwise `fallback_urls` is used; relays held by other slots in
+/// `slot_urls` are skipped.
+pub fn spawn(
+    index: usize,
+    url: &str,
+    fallback_urls: &[String],
+    pool: Option<SqlitePool>,
+    subs: Subscriptions,
+    slot_urls: SlotUrls,
+    events_tx: mpsc::UnboundedSender<UpstreamEvent>,
+) -> Upstream {
+    let (tx, rx) = mpsc::unbounded_channel::<TungMessage>();
+    let supervisor = Supervisor {
+        index,
+        url: url.to_string(),
+        fallback_urls: fallback_urls.to_vec(),
+        pool,
+        subs,
+        slot_urls,
+        events_tx,
+        cmd_rx: rx,
+        pending: Vec::new(),
+    };
+    tokio::spawn(supervisor.run());
+    Upstream { tx }
+}
+
+/// The enabled relay after `current` that no other slot holds, wrapping around.
+///
+/// Falls back to `current` when every other candidate is already served.
+fn pick_candidate(current: &str, candidates: &[String], held: &[String]) -> String {
+    let start = candidates
+        .iter()
+        .position(|u| u == current)
+        .map_or(0, |pos| pos + 1);
+    (0..candidates.len())
+        .map(|i| &candidates[(start + i) % candidates.len()])
+        .find(|u| *u == current || !held.contains(u))
+        .cloned()
+        .unwrap_or_else(|| current.to_string())
+}
+
+fn backoff_delay(attempt: u32) -> Duration {
+    BACKOFF_BASE
+        .saturating_mul(1u32 << attempt.min(16))
+        .min(BACKOFF_MAX)
+}
+
+struct Supervisor {
+    index: usize,
+    url: String,
+    fallback_urls: Vec<String>,
+    pool: Option<SqlitePool>,
+    subs: Subscriptions,
+    slot_urls: SlotUrls,
+    events_tx: mpsc::UnboundedSender<UpstreamEvent>,
+    cmd_rx: mpsc::UnboundedReceiver<TungMessage>,
+    // Frames queued while disconnected (REQ/CLOSE are covered by the replay)
+    pending: Vec<TungMessage>,
+}
+
+impl Supervisor {
+    async fn run(mut self) {
+        // Slots are treated as live until told otherwise
+        let mut live = true;
+        let mut attempt: u32 = 0;
+        loop {
+            let Some(result) = self.while_disconnected(connect_async(self.url.clone())).await else {
+                return; // pool dropped
+            };
+            match result {
+                Ok((ws, resp)) => {
+                    tracing::info!(backend_url = %self.url, status = ?resp.status(), slot = self.index, "Backend relay connected successfully");
+                    if self.events_tx.send(UpstreamEvent::Connected(self.index)).is_err() {
+                        return;
+                    }
+                    let connected_at = std::time::Instant::now();
+                    if !self.serve(ws).await {
+                        return;
+                    }
+                    tracing::warn!(backend_url = %self.url, slot = self.index, "Backend relay disconnected, reconnecting");
+                    live = false;
+                    if self.events_tx.send(UpstreamEvent::Disconnected(self.index)).is_err() {
+                        return;
+                    }
+                    if connected_at.elapsed() >= STABLE_AFTER {
+                        attempt = 0;
+                    }
+                    // Retry the same relay first: it may just have restarted
+                    if self.while_disconnected(tokio::time::sleep(backoff_delay(attempt))).await.is_none() {
+                        return;
+                    }
+                    attempt = attempt.saturating_add(1);
+                    continue;
+                }
+                Err(e) => {
+                    tracing::error!(backend_url = %self.url, error = %e, slot = self.index, attempt, "Failed to connect to backend relay");
+                    if live {
+                        live = false;
+                        if self.events_tx.send(UpstreamEvent::Disconnected(self.index)).is_err() {
+                            return;
+                        }
+                    }
+                }
+            }
+
+            let delay = backoff_delay(attempt);
+            attempt = attempt.saturating_add(1);
+            if self.while_disconnected(tokio::time::sleep(delay)).await.is_none() {
+                return;
+            }
+            let next = self.next_candidate().await;
+            if next != self.url {
+                tracing::info!(from = %self.url, to = %next, slot = self.index, "Failing over to another backend relay");
+                self.slot_urls.set(self.index, &next);
+                self.url = next;
+            }
+        }
+    }
+
+    /// Pick the enabled relay after the current one that no other slot serves
+    async fn next_candidate(&self) -> String {
+        let candidates = match &self.pool {
+            Some(pool) => load_enabled_urls(pool).await,
+            None => self.fallback_urls.clone(),
+        };
+        pick_candidate(&self.url, &candidates, &self.slot_urls.held_by_others(self.index))
+    }
+
+    /// Drive `fut` while buffering outgoing frames. Returns None if the pool dropped this slot.
+    async fn while_disconnected<F: Future>(&mut self, fut: F) -> Option<F::Output> {
+        tokio::pin!(fut);
+        loop {
+            tokio::select! {
+                out = &mut fut => return Some(out),
+                cmd = self.cmd_rx.recv() => match cmd {
+                    Some(TungMessage::Close(_)) | None => return None,
+                    Some(TungMessage::Text(text)) if is_subscription_frame(&text) => {}
+                    Some(msg) => {
+                        if self.pending.len() < MAX_PENDING_FRAMES {
+                            self.pending.push(msg);
+                        } else {
+                            tracing::warn!(backend_url = %self.url, "Backend unavailable, dropping queued frame");
+                        }
+                    }
+                },
+            }
+        }
+    }
+
+    /// Pump frames until the backend goes away. Returns false if the pool dropped this slot.
+    async fn serve(
+        &mut self,
+        ws: tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>,
+    ) -> bool {
+        let (mut sink, mut stream) = ws.split();
+
+        let replay = self.subs.req_frames();
+        if !replay.is_empty() {
+            tracing::info!(backend_url = %self.url, subscriptions = replay.len(), "Replaying subscriptions on backend relay");
+        }
+        let queued = std::mem::take(&mut self.pending);
+        for msg in replay.into_iter().map(TungMessage::Text).chain(queued) {
+            if sink.send(msg).await.is_err() {
+                return true;
+            }
+        }
+
+        loop {
+            tokio::select! {
+                cmd = self.cmd_rx.recv() => match cmd {
+                    Some(msg) => {
+                        let is_close = matches!(msg, TungMessage::Close(_));
+                        if sink.send(msg).await.is_err() {
+                            return true;
+                        }
+                        if is_close {
+                            return false;
+                        }
+                    }
+                    None => {
+                        let _ = sink.send(TungMessage::Close(None)).await;
+                        return false;
+                    }
+                },
+                msg = stream.next() => match msg {
+                    Some(Ok(TungMessage::Close(frame))) => {
+                        tracing::info!(backend_url = %self.url, close_frame = ?frame, "Backend closed connection");
+                        return true;
+                    }
+                    Some(Ok(msg)) => {
+                        if self.events_tx.send(UpstreamEvent::Message(self.index, msg)).is_err() {
+                            return false;
+                        }
+                    }
+                    Some(Err(e)) => {
+                        tracing::warn!(backend_url = %self.url, error = %e, "Backend relay read error");
+                        return true;
+                    }
+                    None => return true,
+                },
+            }
+        }
+    }
+}
+
+/// REQ and CLOSE frames are not queued while disconnected: the replay covers them
+fn is_subscription_frame(text: &str) -> bool {
+    matches!(
+        parse_client_msg(text),
+        Ok(ClientMsg::Req { .. }) | Ok(ClientMsg::Close { .. })
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_delay() {
+        assert_eq!(backoff_delay(0), Duration::from_millis(500));
+        assert_eq!(backoff_delay(1), Duration::from_secs(1));
+        assert_eq!(backoff_delay(3), Duration::from_secs(4));
+        assert_eq!(backoff_delay(30), BACKOFF_MAX);
+    }
+
+    #[test]
+    fn test_pick_candidate_skips_held_relays() {
+        let urls = |list: &[&str]| list.iter().map(|u| u.to_string()).collect::<Vec<_>>();
+        let candidates = urls(&["a", "b", "c"]);
+        assert_eq!(pick_candidate("a", &candidates, &[]), "b");
+        assert_eq!(pick_candidate("c", &candidates, &[]), "a");
+        // b is already served by another slot
+        assert_eq!(pick_candidate("a", &candidates, &urls(&["b"])), "c");
+        // everything else is served: keep retrying our own relay
+        assert_eq!(pick_candidate("a", &candidates, &urls(&["b", "c"])), "a");
+        // our relay was disabled
+        assert_eq!(pick_candidate("x", &candidates, &urls(&["a"])), "b");
+        assert_eq!(pick_candidate("x", &[], &[]), "x");
+    }
+
+    #[test]
+    fn test_retire_disabled_slots() {
+        let slots = SlotUrls::default();
+        assert_eq!(slots.push("a"), 0);
+        assert_eq!(slots.push("b"), 1);
+        assert_eq!(slots.retire_missing(&["b".to_string()]), vec![0]);
+        assert!(slots.is_retired(0));
+        assert!(!slots.contains("a"));
+        assert_eq!(slots.active(), 1);
+        // a retired slot stays retired even if its supervisor fails over
+        slots.set(0, "c");
+        assert!(!slots.contains("c"));
+        assert_eq!(slots.held_by_others(1), Vec::<String>::new());
+        // re-enabling the relay opens a new slot
+        assert_eq!(slots.push("a"), 2);
+    }
+
+    #[test]
+    fn test_replay_frames() {
+        let subs = Subscriptions::default();
+        subs.open("a", vec![serde_json::json!({"kinds": [1]})]);
+        subs.open("b", vec![]);
+        subs.close("b");
+        assert_eq!(subs.req_frames(), vec![r#"["REQ","a",{"kinds":[1]}]"#.to_string()]);
+    }
+}
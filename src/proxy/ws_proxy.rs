@@ -2,26 +2,69 @@ use anyhow::Context;
 use axum::extract::ws::{Message, WebSocket};
 use futures_util::{sink::SinkExt, stream::StreamExt};
 use sqlx::SqlitePool;
-use tokio_tungstenite::{connect_async, tungstenite::protocol::Message as TungMessage};
-use std::sync::Arc;
+use tokio_tungstenite::tungstenite::protocol::Message as TungMessage;
+use std::sync::{Arc, Mutex};
 
-use crate::nostr::message::{parse_client_msg, ClientMsg};
-use crate::filter::engine::FilterEngine;
+use crate::nostr::message::{parse_client_msg, ClientMsg, RelayMsg};
+use crate::filter::engine::{BackendOutcome, Direction, FilterEngine, RuleAction};
+use crate::filter::req_kinds::KindBlacklist;
 use crate::nostr::event::Event;
+use crate::nostr::nip42;
+use super::delay::{DelayedPublisher, MAX_DELAYED_EVENTS};
+use super::fanout::Fanout;
+use super::limits::{scan_event_id, Limitations};
+use super::pool::UpstreamPool;
+use super::rate_limit::{RateLimiter, RateLimits};
+use super::upstream::UpstreamEvent;
 
-/// One backend relay connection per client websocket connection (initial implementation).
+/// How long to wait for every backend's EOSE before sending EOSE to the client anyway
+const EOSE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Proxy settings that come from the environment rather than the database
+#[derive(Debug, Clone, Default)]
+pub struct ProxyConfig {
+    /// Public URL of this relay, checked against the `relay` tag of NIP-42 AUTH events
+    pub relay_url: Option<String>,
+    /// Kinds injected into REQ filters without `kinds` while the kind blacklist is in use.
+    /// When unset such filters are rejected with CLOSED.
+    pub req_default_kinds: Option<Vec<i64>>,
+}
+
+/// NIP-42 settings from `relay_info`, read once per connection
+#[derive(Debug, Clone, Copy, Default)]
+struct AuthPolicy {
+    /// `limitation_auth_required`: EVENT and REQ need an authenticated connection
+    required: bool,
+    /// REQ is only served to authenticated pubkeys in the safelist
+    restricted_reads: bool,
+}
+
+/// Proxy a client through a dedicated upstream pool for a single backend relay.
 pub async fn proxy_ws(client_ws: WebSocket, backend_url: String) -> anyhow::Result<()> {
-    proxy_ws_with_pool(client_ws, backend_url, None, None).await
+    proxy_ws_with_pool(
+        client_ws,
+        UpstreamPool::with_urls(vec![backend_url]),
+        RateLimiter::with_limits(RateLimits::default()),
+        FilterEngine::new(),
+        ProxyConfig::default(),
+        None,
+        None,
+    )
+    .await
 }
 
+/// Proxy a client through the shared upstream pool.
 pub async fn proxy_ws_with_pool(
-    client_ws: WebSocket,
-    backend_url: String,
+    mut client_ws: WebSocket,
+    upstreams: UpstreamPool,
+    limiter: RateLimiter,
+    filter_engine: FilterEngine,
+    config: ProxyConfig,
     pool: Option<SqlitePool>,
     client_ip: Option<String>,
 ) -> anyhow::Result<()> {
     let ip_str = client_ip.as_deref().unwrap_or("unknown");
-    tracing::info!(ip = %ip_str, backend_url = %backend_url, "WebSocket connection established");
+    tracing::info!(ip = %ip_str, "WebSocket connection established");
     
     // IP BANチェック
     if let (Some(pool), Some(ip)) = (&pool, &client_ip) {
@@ -31,6 +74,32 @@ pub async fn proxy_ws_with_pool(
         }
     }
 
+    // レート制限（ホワイトリストのIPは対象外）
+    let rate_limited_ip = match (&pool, &client_ip) {
+        (Some(pool), Some(ip)) if is_ip_whitelisted(pool, ip).await.unwrap_or(false) => None,
+        (_, ip) => ip.clone(),
+    };
+    let _connection_slot = match &rate_limited_ip {
+        Some(ip) => match limiter.connect(ip).await {
+            Ok(guard) => Some(guard),
+            Err(e) => {
+                tracing::warn!(ip = %ip, error = %e, "Connection rejected by rate limit");
+                let notice = RelayMsg::Notice(format!("rate-limited: {}", e));
+                let _ = client_ws.send(Message::Text(notice.to_json())).await;
+                return Ok(());
+            }
+        },
+        None => None,
+    };
+
+    // 共有アップストリームプールに登録（全ての有効なバックエンドへfan-out）
+    // サブスクリプションIDはプール内で一意なIDに書き換えられ、応答はこのクライアントにだけ届く
+    let Some((upstream, mut upstream_rx, live)) = upstreams.register().await else {
+        return Err(anyhow::anyhow!("No backend relay configured"));
+    };
+    let upstream = Arc::new(upstream);
+    tracing::info!(ip = %ip_str, client_id = upstream.id(), backends = live.len(), "Registered with upstream pool");
+
     // 接続ログ記録
     let connection_log_id = Arc::new(if let (Some(pool), Some(ip)) = (&pool, &client_ip) {
         let result = sqlx::query(
@@ -57,40 +126,71 @@ pub async fn proxy_ws_with_pool(
     });
     let connection_log_id_c2b = Arc::clone(&connection_log_id);
     let connection_log_id_b2c = Arc::clone(&connection_log_id);
-    
-    tracing::info!(backend_url = %backend_url, "Connecting to backend relay");
-    let (backend_ws, resp) = match connect_async(&backend_url).await {
-        Ok((ws, resp)) => {
-            tracing::info!(backend_url = %backend_url, status = ?resp.status(), "Backend relay connected successfully");
-            (ws, resp)
-        }
-        Err(e) => {
-            tracing::error!(backend_url = %backend_url, error = %e, "Failed to connect to backend relay");
-            return Err(anyhow::anyhow!("Failed to connect to backend relay {}: {}", backend_url, e));
-        }
-    };
 
+    let auth_policy = match &pool {
+        Some(pool) => load_auth_policy(pool).await.unwrap_or_else(|e| {
+            tracing::error!(error = %e, "Failed to load auth policy");
+            AuthPolicy::default()
+        }),
+        None => AuthPolicy::default(),
+    };
+    // NIP-11で公開しているlimitationをそのまま適用する
+    let limitations = match &pool {
+        Some(pool) => Limitations::load(pool).await.unwrap_or_else(|e| {
+            tracing::error!(error = %e, "Failed to load relay limitations");
+            Limitations::default()
+        }),
+        None => Limitations::default(),
+    };
+    
     let (mut client_tx, mut client_rx) = client_ws.split();
-    let (mut backend_tx, mut backend_rx) = backend_ws.split();
 
-    let mut filter_engine = FilterEngine::new();
+    let fanout = Arc::new(Mutex::new(Fanout::new(live.len())));
+    for (backend, connected) in live.iter().enumerate() {
+        if !connected {
+            fanout.lock().unwrap().backend_lost(backend);
+        }
+    }
+    let (eose_timeout_tx, mut eose_timeout_rx) = tokio::sync::mpsc::unbounded_channel::<(String, u64)>();
 
-    async fn is_post_allowed(pool: &SqlitePool, pubkey_hex: &str) -> anyhow::Result<bool> {
+    /// 投稿権限の判定結果
+    enum PostPermission {
+        Allowed,
+        NotInSafelist,
+        Banned,
+    }
+
+    async fn post_permission(pool: &SqlitePool, pubkey_hex: &str) -> anyhow::Result<PostPermission> {
         let npub = match pubkey_hex_to_npub(pubkey_hex) {
             Ok(n) => n,
             Err(e) => {
                 tracing::warn!(pubkey_hex = %pubkey_hex, error = %e, "Failed to convert pubkey_hex to npub");
-                return Ok(false);
+                return Ok(PostPermission::NotInSafelist);
             }
         };
-        let row: Option<(i64,)> =
-            sqlx::query_as("SELECT flags FROM safelist WHERE npub = ?")
+        let row: Option<(i64, i64)> =
+            sqlx::query_as("SELECT flags, banned FROM safelist WHERE npub = ?")
                 .bind(&npub)
                 .fetch_optional(pool)
                 .await?;
-        let allowed = row.map(|(flags,)| (flags & 1) == 1).unwrap_or(false);
         // ログ削除: 通常のチェックは静かに行う
-        Ok(allowed)
+        Ok(match row {
+            Some((_, banned)) if banned != 0 => PostPermission::Banned,
+            Some((flags, _)) if (flags & 1) == 1 => PostPermission::Allowed,
+            _ => PostPermission::NotInSafelist,
+        })
+    }
+
+    async fn is_read_allowed(pool: &SqlitePool, pubkey_hex: &str) -> anyhow::Result<bool> {
+        let Ok(npub) = pubkey_hex_to_npub(pubkey_hex) else {
+            return Ok(false);
+        };
+        let row: Option<(i64,)> =
+            sqlx::query_as("SELECT banned FROM safelist WHERE npub = ?")
+                .bind(&npub)
+                .fetch_optional(pool)
+                .await?;
+        Ok(matches!(row, Some((0,))))
     }
 
     fn pubkey_hex_to_npub(pubkey_hex: &str) -> anyhow::Result<String> {
@@ -104,6 +204,7 @@ pub async fn proxy_ws_with_pool(
         event: &Event,
         reason: &str,
         ip_address: Option<&str>,
+        action: &RuleAction,
     ) -> anyhow::Result<()> {
         let npub = match pubkey_hex_to_npub(&event.pubkey) {
             Ok(n) => n,
@@ -113,7 +214,7 @@ pub async fn proxy_ws_with_pool(
             }
         };
         match sqlx::query(
-            "INSERT INTO event_rejection_logs (event_id, pubkey_hex, npub, ip_address, kind, reason) VALUES (?, ?, ?, ?, ?, ?)"
+            "INSERT INTO event_rejection_logs (event_id, pubkey_hex, npub, ip_address, kind, reason, direction, action) VALUES (?, ?, ?, ?, ?, ?, ?, ?)"
         )
         .bind(&event.id)
         .bind(&event.pubkey)
@@ -121,6 +222,8 @@ pub async fn proxy_ws_with_pool(
         .bind(ip_address)
         .bind(event.kind)
         .bind(reason)
+        .bind(Direction::Inbound.as_str())
+        .bind(action.as_str())
         .execute(pool)
         .await {
             Ok(_) => {
@@ -134,6 +237,35 @@ pub async fn proxy_ws_with_pool(
         }
     }
 
+    /// 拒否ログと統計を記録し、クライアントに ["OK", <id>, false, "<prefix>: <message>"] を返す
+    #[allow(clippy::too_many_arguments)]
+    async fn reject_event(
+        pool: Option<&SqlitePool>,
+        connection_log_id: Option<i64>,
+        client_ip: Option<&str>,
+        client_out_tx: &tokio::sync::mpsc::UnboundedSender<Message>,
+        event: &Event,
+        reason: &str,
+        prefix: &str,
+        message: &str,
+    ) {
+        if let Some(pool) = pool {
+            if let Err(e) = log_rejection(pool, event, reason, client_ip, &RuleAction::Drop).await {
+                tracing::error!(error = %e, "Failed to log rejection");
+            }
+            if let Some(log_id) = connection_log_id {
+                let _ = sqlx::query(
+                    "UPDATE connection_logs SET rejected_event_count = rejected_event_count + 1 WHERE id = ?"
+                )
+                .bind(log_id)
+                .execute(pool)
+                .await;
+            }
+        }
+        let ok = RelayMsg::rejected(&event.id, prefix, message);
+        let _ = client_out_tx.send(Message::Text(ok.to_json()));
+    }
+
     // multiplex all outbound-to-client messages through a single sender task
     let (client_out_tx, mut client_out_rx) = tokio::sync::mpsc::unbounded_channel::<Message>();
     let client_sender = tokio::spawn(async move {
@@ -144,85 +276,261 @@ pub async fn proxy_ws_with_pool(
         }
     });
 
+    // NIP-42: 接続ごとのチャレンジを送る
+    let challenge = nip42::new_challenge();
+    let _ = client_out_tx.send(Message::Text(serde_json::json!(["AUTH", challenge]).to_string()));
+
     // client -> backend
     let pool_c2b = pool.clone();
     let client_ip_c2b = client_ip.clone();
     let connection_log_id_c2b_clone = Arc::clone(&connection_log_id_c2b);
     let client_out_tx_c2b = client_out_tx.clone();
+    let fanout_c2b = Arc::clone(&fanout);
+    let upstream_c2b = Arc::clone(&upstream);
+    let relay_url = config.relay_url.clone();
+    // クライアントからのEVENTにも同じルールを適用する（direction = inbound / both）
+    let filter_engine_c2b = filter_engine.clone();
+    let rate_limited_ip_c2b = rate_limited_ip.clone();
+    let limiter_c2b = limiter.clone();
+    let req_default_kinds = config.req_default_kinds.clone();
+    // delayルールで保留したEVENTは接続ごとの上限付きキューから転送する（切断後は送らない）
+    let delayed_upstream = Arc::downgrade(&upstream);
+    let mut delayed = DelayedPublisher::spawn(MAX_DELAYED_EVENTS, move |event_id, text| {
+        if let Some(handle) = delayed_upstream.upgrade() {
+            handle.publish(event_id, text);
+        }
+    });
     let c2b = async move {
+        // AUTHで認証されたpubkey（未認証ならNone）
+        let mut authed_pubkey: Option<String> = None;
+        let send_closed = |sub_id: &str, message: &str| {
+            let closed = serde_json::json!(["CLOSED", sub_id, message]);
+            let _ = client_out_tx_c2b.send(Message::Text(closed.to_string()));
+        };
         while let Some(msg) = client_rx.next().await {
             let msg = msg?;
             match msg {
                 Message::Text(text) => {
+                    if let Err(e) = limitations.check_message(&text) {
+                        tracing::warn!(len = text.len(), "Client message too large");
+                        // EVENTならOKで拒否を返す（全体はパースせずidだけを拾う）
+                        let reply = match scan_event_id(&text) {
+                            Some(event_id) => RelayMsg::rejected(event_id, "invalid", "message too large"),
+                            None => RelayMsg::Notice(format!("invalid: {}", e)),
+                        };
+                        let _ = client_out_tx_c2b.send(Message::Text(reply.to_json()));
+                        continue;
+                    }
                     // If it's an EVENT, enforce safelist when pool is available.
                     match parse_client_msg(&text) {
                         Ok(ClientMsg::Event { event }) => {
                             // ログ削除: 通常のイベント受信ログを削除
+                            // NIP-01: IDの再計算とBIP-340署名の検証
+                            let reject = |reason: String, prefix: &'static str, message: String| {
+                                let event = &event;
+                                let pool = pool_c2b.as_ref();
+                                let client_ip = client_ip_c2b.as_deref();
+                                let connection_log_id = *connection_log_id_c2b_clone;
+                                let client_out_tx = &client_out_tx_c2b;
+                                async move {
+                                    reject_event(pool, connection_log_id, client_ip, client_out_tx, event, &reason, prefix, &message).await
+                                }
+                            };
+                            if let Err(e) = limitations.check_event(&event) {
+                                tracing::warn!(event_id = %event.id, error = %e, "EVENT blocked: exceeds relay limitation");
+                                reject(e.reason().to_string(), "invalid", e.to_string()).await;
+                                continue;
+                            }
+                            if let Err(e) = event.verify() {
+                                tracing::warn!(event_id = %event.id, pubkey_hex = %event.pubkey, error = %e, "EVENT blocked: invalid id or signature");
+                                reject(e.reason().to_string(), "invalid", e.to_string()).await;
+                                continue;
+                            }
+                            if auth_policy.required && authed_pubkey.is_none() {
+                                tracing::warn!(event_id = %event.id, pubkey_hex = %event.pubkey, "EVENT blocked: not authenticated");
+                                reject("auth_required".to_string(), "auth-required", "authenticate to publish".to_string()).await;
+                                continue;
+                            }
+                            if let Some(ip) = &rate_limited_ip_c2b {
+                                let pubkey = authed_pubkey.as_deref().unwrap_or(&event.pubkey);
+                                if let Err(e) = limiter_c2b.check_event(ip, pubkey).await {
+                                    tracing::warn!(event_id = %event.id, ip = %ip, error = %e, "EVENT blocked: rate limited");
+                                    reject("rate_limited".to_string(), "rate-limited", e.to_string()).await;
+                                    continue;
+                                }
+                            }
                             if let Some(pool) = &pool_c2b {
-                                let allowed = match is_post_allowed(pool, &event.pubkey).await {
-                                    Ok(a) => a,
+                                // 認証済みなら著者ではなく認証されたpubkeyで投稿権限を判定する
+                                let poster = authed_pubkey.as_deref().unwrap_or(&event.pubkey);
+                                let permission = match post_permission(pool, poster).await {
+                                    Ok(p) => p,
                                     Err(e) => {
                                         tracing::error!(error = %e, "Failed to check post_allowed");
-                                        false
+                                        PostPermission::NotInSafelist
                                     }
                                 };
-                                if !allowed {
-                                    tracing::warn!(event_id = %event.id, pubkey_hex = %event.pubkey, "EVENT blocked: not in safelist or post_allowed flag not set");
-                                    // 拒否ログを記録
-                                    if let Err(e) = log_rejection(pool, &event, "not_in_safelist", client_ip_c2b.as_deref()).await {
-                                        tracing::error!(error = %e, "Failed to log rejection");
+                                match permission {
+                                    PostPermission::Allowed => {
+                                        // ログ削除: 許可されたイベントのログを削除
                                     }
-                                    // 統計情報を更新
-                                    if let Some(log_id) = *connection_log_id_c2b_clone {
-                                        let _ = sqlx::query(
-                                            "UPDATE connection_logs SET rejected_event_count = rejected_event_count + 1 WHERE id = ?"
-                                        )
-                                        .bind(log_id)
-                                        .execute(pool)
-                                        .await;
+                                    PostPermission::Banned => {
+                                        tracing::warn!(event_id = %event.id, pubkey_hex = %poster, "EVENT blocked: banned npub");
+                                        reject("banned_npub".to_string(), "blocked", "pubkey is banned".to_string()).await;
+                                        continue;
+                                    }
+                                    PostPermission::NotInSafelist => {
+                                        tracing::warn!(event_id = %event.id, pubkey_hex = %poster, "EVENT blocked: not in safelist or post_allowed flag not set");
+                                        reject("not_in_safelist".to_string(), "blocked", "not in safelist".to_string()).await;
+                                        continue;
+                                    }
+                                }
+                                match filter_engine_c2b.check_client_event(pool, &event).await {
+                                    Ok(Some(verdict)) if verdict.action == RuleAction::Drop => {
+                                        tracing::info!(event_id = %event.id, reason = %verdict.reason, "Client EVENT dropped by filter");
+                                        let message = match verdict.reason.as_str() {
+                                            "banned_npub" => "pubkey is banned",
+                                            "kind_blacklist" => "kind is not allowed",
+                                            _ => "rejected by filter rule",
+                                        };
+                                        reject(verdict.reason, "blocked", message.to_string()).await;
+                                        continue;
+                                    }
+                                    Ok(Some(verdict)) => {
+                                        tracing::info!(event_id = %event.id, reason = %verdict.reason, action = verdict.action.as_str(), "Client EVENT matched filter rule");
+                                        if let Err(e) = log_rejection(pool, &event, &verdict.reason, client_ip_c2b.as_deref(), &verdict.action).await {
+                                            tracing::error!(error = %e, "Failed to log filter action");
+                                        }
+                                        match verdict.action {
+                                            // flagはログを残してそのまま転送する
+                                            RuleAction::Drop | RuleAction::Flag => {}
+                                            RuleAction::Shadow => {
+                                                // 転送せずに受理したように見せる
+                                                let ok = RelayMsg::Ok(event.id.clone(), true, String::new());
+                                                let _ = client_out_tx_c2b.send(Message::Text(ok.to_json()));
+                                                continue;
+                                            }
+                                            RuleAction::Delay(delay) => {
+                                                // 保留枠が空いていれば受理を返し、期限が来たらこの接続のハンドルで転送する
+                                                if !delayed.schedule(delay, &event.id, text) {
+                                                    tracing::warn!(event_id = %event.id, "EVENT blocked: too many delayed events");
+                                                    let ok = RelayMsg::rejected(&event.id, "rate-limited", "too many delayed events");
+                                                    let _ = client_out_tx_c2b.send(Message::Text(ok.to_json()));
+                                                    continue;
+                                                }
+                                                // 受理はここで返すので、後で届くバックエンドのOKは転送しない
+                                                fanout_c2b.lock().unwrap().publish_answered(&event.id);
+                                                let ok = RelayMsg::Ok(event.id.clone(), true, String::new());
+                                                let _ = client_out_tx_c2b.send(Message::Text(ok.to_json()));
+                                                continue;
+                                            }
+                                        }
+                                    }
+                                    Ok(None) => {}
+                                    Err(e) => {
+                                        tracing::error!(error = %e, "Error in inbound filter check, passing through");
                                     }
-                                    let notice = serde_json::json!(["NOTICE", "blocked: not in safelist"]);
-                                    let _ = client_out_tx_c2b.send(Message::Text(notice.to_string()));
-                                    continue;
                                 }
-                                // ログ削除: 許可されたイベントのログを削除
                             } else {
                                 tracing::warn!("No pool available, forwarding EVENT without safelist check");
                             }
+                            fanout_c2b.lock().unwrap().publish(&event.id);
+                            upstream_c2b.publish(&event.id, text);
+                        }
+                        Ok(ClientMsg::Req { sub_id, mut filters }) => {
+                            if let Some(ip) = &rate_limited_ip_c2b {
+                                if let Err(e) = limiter_c2b.check_req(ip).await {
+                                    tracing::warn!(sub_id = %sub_id, ip = %ip, error = %e, "REQ blocked: rate limited");
+                                    send_closed(&sub_id, &format!("rate-limited: {}", e));
+                                    continue;
+                                }
+                            }
+                            if (auth_policy.required || auth_policy.restricted_reads) && authed_pubkey.is_none() {
+                                send_closed(&sub_id, "auth-required: authenticate to subscribe");
+                                continue;
+                            }
+                            if auth_policy.restricted_reads {
+                                let allowed = match (&pool_c2b, &authed_pubkey) {
+                                    (Some(pool), Some(pubkey)) => is_read_allowed(pool, pubkey).await.unwrap_or_else(|e| {
+                                        tracing::error!(error = %e, "Failed to check read access");
+                                        false
+                                    }),
+                                    _ => false,
+                                };
+                                if !allowed {
+                                    send_closed(&sub_id, "restricted: not allowed to read from this relay");
+                                    continue;
+                                }
+                            }
+                            let limit_check = {
+                                let fanout = fanout_c2b.lock().unwrap();
+                                limitations.check_req(filters.len(), fanout.open_subscriptions(), fanout.is_open(&sub_id))
+                            };
+                            if let Err(e) = limit_check {
+                                tracing::info!(sub_id = %sub_id, error = %e, "REQ rejected by relay limitation");
+                                send_closed(&sub_id, &format!("invalid: {}", e));
+                                continue;
+                            }
+                            // Kindブラックリストに該当するkindはREQの段階で取り除く
+                            if let Some(pool) = &pool_c2b {
+                                match KindBlacklist::load(pool).await {
+                                    Ok(blacklist) => match blacklist.rewrite_filters(filters, req_default_kinds.as_deref()) {
+                                        Ok(rewritten) => filters = rewritten,
+                                        Err(e) => {
+                                            tracing::info!(sub_id = %sub_id, reason = %e, "REQ rejected by kind blacklist");
+                                            send_closed(&sub_id, &format!("blocked: {}", e));
+                                            continue;
+                                        }
+                                    },
+                                    Err(e) => {
+                                        tracing::error!(error = %e, "Failed to load kind blacklist, forwarding REQ as is");
+                                    }
+                                }
+                            }
+                            // EOSEが揃わない場合に備えてタイムアウトを仕掛ける
+                            let generation = fanout_c2b.lock().unwrap().open(&sub_id);
+                            upstream_c2b.req(&sub_id, filters);
+                            let eose_timeout_tx = eose_timeout_tx.clone();
+                            tokio::spawn(async move {
+                                tokio::time::sleep(EOSE_TIMEOUT).await;
+                                let _ = eose_timeout_tx.send((sub_id, generation));
+                            });
                         }
-                        Ok(ClientMsg::Req { sub_id: _, filters: _ }) => {
-                            // ログ削除: REQメッセージのログを削除
+                        Ok(ClientMsg::Auth { event }) => {
+                            let now = chrono::Utc::now().timestamp();
+                            let reply = match nip42::verify_auth_event(&event, &challenge, relay_url.as_deref(), now) {
+                                Ok(()) => {
+                                    tracing::info!(pubkey_hex = %event.pubkey, "Client authenticated");
+                                    authed_pubkey = Some(event.pubkey.clone());
+                                    RelayMsg::Ok(event.id.clone(), true, String::new())
+                                }
+                                Err(e) => {
+                                    tracing::warn!(pubkey_hex = %event.pubkey, error = %e, "AUTH rejected");
+                                    RelayMsg::rejected(&event.id, "invalid", &e.to_string())
+                                }
+                            };
+                            let _ = client_out_tx_c2b.send(Message::Text(reply.to_json()));
                         }
-                        Ok(ClientMsg::Close { sub_id: _ }) => {
-                            // ログ削除: CLOSEメッセージのログを削除
+                        Ok(ClientMsg::Close { sub_id }) => {
+                            fanout_c2b.lock().unwrap().close(&sub_id);
+                            upstream_c2b.close(&sub_id);
                         }
-                        Err(_e) => {
-                            // ログ削除: パースエラーのログを削除（Nostrメッセージでない可能性が高い）
+                        Err(e) => {
+                            // 共有接続ではサブスクリプションIDを書き換えられないメッセージは転送しない
+                            let notice = serde_json::json!(["NOTICE", format!("error: {}", e)]);
+                            let _ = client_out_tx_c2b.send(Message::Text(notice.to_string()));
                         }
                     }
-                    // ログ削除: 通常のメッセージ転送ログを削除
-                    backend_tx.send(TungMessage::Text(text)).await?
                 }
-                Message::Binary(bin) => {
+                Message::Binary(_) => {
                     // ログ削除: バイナリメッセージのログを削除
-                    backend_tx.send(TungMessage::Binary(bin)).await?
                 }
-                Message::Ping(p) => {
-                    // ログ削除: PINGメッセージのログを削除
-                    backend_tx.send(TungMessage::Ping(p)).await?
-                }
-                Message::Pong(p) => {
-                    // ログ削除: PONGメッセージのログを削除
-                    backend_tx.send(TungMessage::Pong(p)).await?
+                Message::Ping(_) | Message::Pong(_) => {
+                    // PING/PONGは各WebSocket層で自動応答されるため転送しない
                 }
                 Message::Close(frame) => {
                     let close_info = frame.as_ref().map(|f| (f.code, f.reason.clone()));
                     tracing::info!(close_code = ?close_info.as_ref().map(|(c, _)| c), close_reason = ?close_info.as_ref().map(|(_, r)| r.as_ref()), "Client closed connection");
-                    let close = frame.map(|f| tokio_tungstenite::tungstenite::protocol::CloseFrame {
-                        code: f.code.into(),
-                        reason: f.reason,
-                    });
-                    backend_tx.send(TungMessage::Close(close)).await?;
+                    // サブスクリプションはClientHandleのdrop時にバックエンド側でCLOSEされる
                     break;
                 }
             }
@@ -235,41 +543,105 @@ pub async fn proxy_ws_with_pool(
     let client_ip_b2c = client_ip.clone();
     let connection_log_id_b2c_clone = Arc::clone(&connection_log_id_b2c);
     let client_out_tx_b2c = client_out_tx.clone();
+    let fanout_b2c = Arc::clone(&fanout);
+    let upstream_b2c = Arc::clone(&upstream);
     let b2c = async move {
-        while let Some(msg) = backend_rx.next().await {
-            let msg = msg?;
+        let send_eose = |sub_id: &str| {
+            let eose = serde_json::json!(["EOSE", sub_id]);
+            let _ = client_out_tx_b2c.send(Message::Text(eose.to_string()));
+        };
+        loop {
+            let (backend, msg) = tokio::select! {
+                ev = upstream_rx.recv() => match ev {
+                    Some(UpstreamEvent::Message(backend, msg)) => (backend, msg),
+                    Some(UpstreamEvent::Added(backend)) | Some(UpstreamEvent::Connected(backend)) => {
+                        fanout_b2c.lock().unwrap().backend_restored(backend);
+                        continue;
+                    }
+                    Some(UpstreamEvent::Disconnected(backend)) => {
+                        // 再接続まで、このバックエンドのEOSE/OKは待たない
+                        let lost = fanout_b2c.lock().unwrap().backend_lost(backend);
+                        for sub_id in lost.eose {
+                            send_eose(&sub_id);
+                        }
+                        // 応答できるバックエンドが残っていないEVENTにはここでOKを返す
+                        for event_id in lost.unanswered {
+                            let ok = RelayMsg::rejected(&event_id, "error", "backend relay disconnected before accepting the event");
+                            let _ = client_out_tx_b2c.send(Message::Text(ok.to_json()));
+                        }
+                        continue;
+                    }
+                    None => break,
+                },
+                Some((sub_id, generation)) = eose_timeout_rx.recv() => {
+                    if fanout_b2c.lock().unwrap().eose_timeout(&sub_id, generation) {
+                        tracing::debug!(sub_id = %sub_id, "EOSE timeout, sending EOSE to client");
+                        send_eose(&sub_id);
+                    }
+                    continue;
+                }
+            };
             match msg {
                 TungMessage::Text(text) => {
+                    // 複数バックエンドからの重複を除去し、EOSE/CLOSED/OKを集約する
+                    if let Ok(serde_json::Value::Array(arr)) = serde_json::from_str::<serde_json::Value>(&text) {
+                        let cmd = arr.first().and_then(|v| v.as_str());
+                        let arg = arr.get(1).and_then(|v| v.as_str()).unwrap_or("");
+                        let forward = {
+                            let mut fanout = fanout_b2c.lock().unwrap();
+                            match cmd {
+                                Some("EVENT") => {
+                                    let event_id = arr.get(2).and_then(|ev| ev.get("id")).and_then(|v| v.as_str()).unwrap_or("");
+                                    fanout.accept_event(arg, event_id)
+                                }
+                                Some("EOSE") => fanout.accept_eose(backend, arg),
+                                Some("CLOSED") => {
+                                    let closed = fanout.accept_closed(backend, arg);
+                                    if closed {
+                                        upstream_b2c.close(arg);
+                                    }
+                                    closed
+                                }
+                                Some("OK") => {
+                                    let accepted = arr.get(2).and_then(|v| v.as_bool()).unwrap_or(false);
+                                    fanout.accept_ok(backend, arg, accepted)
+                                }
+                                _ => true,
+                            }
+                        };
+                        if !forward {
+                            continue;
+                        }
+                    }
                     if let Some(pool) = &pool_b2c {
-                        match filter_engine.should_drop_backend_text_with_ip(pool, &text, client_ip_b2c.as_deref()).await {
-                            Ok(true) => {
+                        match filter_engine.filter_backend_text_with_ip(pool, &text, client_ip_b2c.as_deref()).await {
+                            Ok(BackendOutcome::Forward) => {
+                                // Event passed filter - ログ削除
+                            }
+                            Ok(BackendOutcome::Drop) => {
                                 // ブロック時のみログ出力（重要）
                                 tracing::info!("Backend EVENT dropped by filter");
                                 continue;
                             }
-                            Ok(false) => {
-                                // Event passed filter - ログ削除
+                            Ok(BackendOutcome::Delay(delay)) => {
+                                // 切断後は送信先がないので、弱い参照で保持する
+                                let client_out_tx = client_out_tx_b2c.downgrade();
+                                tokio::spawn(async move {
+                                    tokio::time::sleep(delay).await;
+                                    if let Some(tx) = client_out_tx.upgrade() {
+                                        let _ = tx.send(Message::Text(text));
+                                    }
+                                });
+                                continue;
                             }
                             Err(e) => {
                                 tracing::error!(error = %e, "Error in filter check, passing through");
                             }
                         }
                     }
-                    // Check if this is an EVENT response from backend
+                    // Check if this is an OK response from backend
                     if let Ok(serde_json::Value::Array(arr)) = serde_json::from_str::<serde_json::Value>(&text) {
-                        if arr.first().and_then(|v| v.as_str()) == Some("EVENT") {
-                            // ログ削除: 通常のイベント転送ログを削除
-                            if let Some(_sub_id) = arr.get(1).and_then(|v| v.as_str()) {
-                                if let Some(_ev_v) = arr.get(2) {
-                                    // イベント処理は続行
-                                }
-                            }
-                        } else if arr.first().and_then(|v| v.as_str()) == Some("EOSE") {
-                            // ログ削除: EOSEメッセージのログを削除
-                            if let Some(_sub_id) = arr.get(1).and_then(|v| v.as_str()) {
-                                // EOSE処理は続行
-                            }
-                        } else if arr.first().and_then(|v| v.as_str()) == Some("OK") {
+                        if arr.first().and_then(|v| v.as_str()) == Some("OK") {
                             if let Some(_event_id) = arr.get(1).and_then(|v| v.as_str()) {
                                 // OKメッセージの形式: ["OK", <event_id>, <accepted>, <message>]
                                 let accepted = arr.get(2).and_then(|v| v.as_bool()).unwrap_or(false);
@@ -295,13 +667,6 @@ pub async fn proxy_ws_with_pool(
                                     }
                                 }
                             }
-                        } else if arr.first().and_then(|v| v.as_str()) == Some("NOTICE") {
-                            // ログ削除: NOTICEメッセージのログを削除
-                            if let Some(_notice_msg) = arr.get(1).and_then(|v| v.as_str()) {
-                                // NOTICE処理は続行
-                            }
-                        } else {
-                            // ログ削除: 未知のメッセージタイプのログを削除
                         }
                     }
                     // ログ削除: 通常のメッセージ転送ログを削除
@@ -311,25 +676,7 @@ pub async fn proxy_ws_with_pool(
                     // ログ削除: バイナリメッセージのログを削除
                     let _ = client_out_tx_b2c.send(Message::Binary(bin));
                 }
-                TungMessage::Ping(p) => {
-                    // ログ削除: PINGメッセージのログを削除
-                    let _ = client_out_tx_b2c.send(Message::Ping(p));
-                }
-                TungMessage::Pong(p) => {
-                    // ログ削除: PONGメッセージのログを削除
-                    let _ = client_out_tx_b2c.send(Message::Pong(p));
-                }
-                TungMessage::Close(frame) => {
-                    let close_info = frame.as_ref().map(|f| (f.code, f.reason.clone()));
-                    tracing::info!(close_code = ?close_info.as_ref().map(|(c, _)| c), close_reason = ?close_info.as_ref().map(|(_, r)| r.as_ref()), "Backend closed connection");
-                    let close = frame.map(|f| axum::extract::ws::CloseFrame {
-                        code: f.code.into(),
-                        reason: f.reason,
-                    });
-                    let _ = client_out_tx_b2c.send(Message::Close(close));
-                    break;
-                }
-                // ignore frames we don't map yet
+                // PING/PONG/Close are handled by the upstream supervisor
                 _ => {}
             }
         }
@@ -357,6 +704,32 @@ pub async fn proxy_ws_with_pool(
     Ok(())
 }
 
+/// relay_infoからNIP-42の設定を読み込む
+async fn load_auth_policy(pool: &SqlitePool) -> anyhow::Result<AuthPolicy> {
+    let row: Option<(i64, i64)> = sqlx::query_as(
+        "SELECT COALESCE(limitation_auth_required, 0), auth_restricted_reads FROM relay_info WHERE id = 1"
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(row
+        .map(|(required, restricted_reads)| AuthPolicy {
+            required: required != 0,
+            restricted_reads: restricted_reads != 0,
+        })
+        .unwrap_or_default())
+}
+
+/// IPアドレスがホワイトリストに登録されているか確認
+async fn is_ip_whitelisted(pool: &SqlitePool, ip: &str) -> anyhow::Result<bool> {
+    let row: Option<(i64,)> = sqlx::query_as(
+        "SELECT whitelisted FROM ip_access_control WHERE ip_address = ?"
+    )
+    .bind(ip)
+    .fetch_optional(pool)
+    .await?;
+    Ok(row.map(|(whitelisted,)| whitelisted == 1).unwrap_or(false))
+}
+
 /// IPアドレスがBANされているか確認
 async fn is_ip_banned(pool: &SqlitePool, ip: &str) -> anyhow::Result<bool> {
     let row: Option<(i64,)> = sqlx::query_as(
@@ -22,7 +22,10 @@ async fn setup_pool() -> SqlitePool {
 async fn api_requires_basic_auth() {
     let pool = setup_pool().await;
     auth::ensure_admin_user(&pool, "admin", "admin").await.unwrap();
-    let app = api::routes::router(pool.clone());
+    let app = api::routes::router(api::routes::AppState {
+        pool: pool.clone(),
+        filter_engine: FilterEngine::new(),
+    });
 
     // without auth
     let resp = app
@@ -60,7 +63,7 @@ async fn filter_drops_kind7_same_created_at_as_cached_kind1() {
         .await
         .unwrap();
 
-    let mut engine = FilterEngine::new();
+    let engine = FilterEngine::new();
 
     // cache kind1
     let kind1 = serde_json::json!(["EVENT", "sub", {
@@ -103,3 +106,906 @@ async fn filter_drops_kind7_same_created_at_as_cached_kind1() {
     assert!(engine.should_drop_backend_text(&pool, &kind7_drop).await.unwrap());
 }
 
+
+#[tokio::test]
+async fn filter_drops_forged_backend_event_when_verification_enabled() {
+    let pool = setup_pool().await;
+    let engine = FilterEngine::new().with_signature_verification(true);
+
+    let forged = serde_json::json!(["EVENT", "sub", {
+        "id": "0000000000000000000000000000000000000000000000000000000000000000",
+        "pubkey": "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f",
+        "created_at": 123,
+        "kind": 1,
+        "tags": [],
+        "content": "hello",
+        "sig": "sig"
+    }])
+    .to_string();
+    assert!(engine.should_drop_backend_text(&pool, &forged).await.unwrap());
+
+    let (reason,): (String,) = sqlx::query_as("SELECT reason FROM event_rejection_logs")
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(reason, "invalid_id");
+}
+
+#[tokio::test]
+async fn filter_rules_apply_by_direction() {
+    let pool = setup_pool().await;
+    sqlx::query(
+        "INSERT INTO filter_rules (name, nl_text, parsed_json, direction) VALUES ('no spam in', 'content contains \"spam\"', 'content contains \"spam\"', 'inbound')",
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    let event: proxy_nostr_relay::nostr::event::Event = serde_json::from_value(serde_json::json!({
+        "id": "spamid",
+        "pubkey": "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f",
+        "created_at": 123,
+        "kind": 1,
+        "tags": [],
+        "content": "buy spam now",
+        "sig": "sig"
+    }))
+    .unwrap();
+
+    let engine = FilterEngine::new();
+    let reason = engine.check_client_event(&pool, &event).await.unwrap();
+    assert!(reason.is_some_and(|v| v.reason.starts_with("filter_rule:")));
+
+    // inbound-only rule does not touch backend output
+    let text = serde_json::json!(["EVENT", "sub", event]).to_string();
+    assert!(!engine.should_drop_backend_text(&pool, &text).await.unwrap());
+}
+
+#[tokio::test]
+async fn filter_api_reloads_shared_engine() {
+    let pool = setup_pool().await;
+    auth::ensure_admin_user(&pool, "admin", "admin").await.unwrap();
+    let engine = FilterEngine::new();
+    let app = api::routes::router(api::routes::AppState {
+        pool: pool.clone(),
+        filter_engine: engine.clone(),
+    });
+
+    let event: proxy_nostr_relay::nostr::event::Event = serde_json::from_value(serde_json::json!({
+        "id": "ev",
+        "pubkey": "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f",
+        "created_at": 123,
+        "kind": 1,
+        "tags": [],
+        "content": "buy spam now",
+        "sig": "sig"
+    }))
+    .unwrap();
+    // loads the (empty) rule set and starts the 30 second cache
+    assert_eq!(engine.check_client_event(&pool, &event).await.unwrap(), None);
+
+    let resp = app
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/filters")
+                .header("authorization", basic_header("admin", "admin"))
+                .header("content-type", "application/json")
+                .body(Body::from(r#"{"name":"spam","nl_text":"content contains \"spam\""}"#))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    // a clone handed to a connection sees the new rule without waiting for the cache
+    let connection_engine = engine.clone();
+    let reason = connection_engine.check_client_event(&pool, &event).await.unwrap();
+    assert!(reason.is_some_and(|v| v.reason.starts_with("filter_rule:")));
+}
+
+#[tokio::test]
+async fn kind1_cache_fetches_missing_reference() {
+    let pool = setup_pool().await;
+    auth::ensure_admin_user(&pool, "admin", "admin").await.unwrap();
+    let fetcher: proxy_nostr_relay::filter::engine::Kind1Fetcher = std::sync::Arc::new(|id: String| {
+        Box::pin(async move {
+            serde_json::from_value(serde_json::json!({
+                "id": id,
+                "pubkey": "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f",
+                "created_at": 123,
+                "kind": 1,
+                "tags": [],
+                "content": "hello",
+                "sig": "sig"
+            }))
+            .ok()
+        })
+    });
+    let engine = FilterEngine::new().with_kind1_fetcher(fetcher);
+
+    // the referenced kind1 was never seen by the proxy
+    let kind7 = serde_json::json!(["EVENT", "sub", {
+        "id": "kind7id",
+        "pubkey": "ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff",
+        "created_at": 123,
+        "kind": 7,
+        "tags": [["e", "unseen"]],
+        "content": "+",
+        "sig": "sig"
+    }])
+    .to_string();
+    assert!(engine.should_drop_backend_text(&pool, &kind7).await.unwrap());
+
+    let app = api::routes::router(api::routes::AppState {
+        pool: pool.clone(),
+        filter_engine: engine.clone(),
+    });
+    let resp = app
+        .oneshot(
+            Request::builder()
+                .uri("/cache/kind1")
+                .header("authorization", basic_header("admin", "admin"))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body = axum::body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+    let stats: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(stats["entries"], 1);
+    assert_eq!(stats["fetches"], 1);
+    assert_eq!(stats["fetch_hits"], 1);
+    assert_eq!(stats["hits"], 1);
+}
+
+#[tokio::test]
+async fn filter_rule_actions_on_backend_events() {
+    use proxy_nostr_relay::filter::engine::BackendOutcome;
+
+    let pool = setup_pool().await;
+    for (name, query, action, delay_secs) in [
+        ("flag", r#"content contains "flagme""#, "flag", None),
+        ("delay", r#"content contains "later""#, "delay", Some(30)),
+        ("shadow", r#"content contains "hide""#, "shadow", None),
+    ] {
+        sqlx::query("INSERT INTO filter_rules (name, nl_text, parsed_json, action, delay_secs) VALUES (?, ?, ?, ?, ?)")
+            .bind(name)
+            .bind(query)
+            .bind(query)
+            .bind(action)
+            .bind(delay_secs)
+            .execute(&pool)
+            .await
+            .unwrap();
+    }
+    let engine = FilterEngine::new();
+    let frame = |content: &str, tags: serde_json::Value| {
+        serde_json::json!(["EVENT", "sub", {
+            "id": format!("id-{content}"),
+            "pubkey": "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f",
+            "created_at": 123,
+            "kind": 1,
+            "tags": tags,
+            "content": content,
+            "sig": "sig"
+        }])
+        .to_string()
+    };
+
+    let outcome = |text: String| {
+        let engine = engine.clone();
+        let pool = pool.clone();
+        async move { engine.filter_backend_text_with_ip(&pool, &text, None).await.unwrap() }
+    };
+    assert_eq!(outcome(frame("flagme", serde_json::json!([]))).await, BackendOutcome::Forward);
+    assert_eq!(
+        outcome(frame("later", serde_json::json!([]))).await,
+        BackendOutcome::Delay(std::time::Duration::from_secs(30))
+    );
+    assert_eq!(outcome(frame("hide", serde_json::json!([]))).await, BackendOutcome::Drop);
+
+    let actions: Vec<(String,)> = sqlx::query_as("SELECT action FROM event_rejection_logs ORDER BY id")
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+    let actions: Vec<&str> = actions.iter().map(|(a,)| a.as_str()).collect();
+    assert_eq!(actions, vec!["flag", "delay", "shadow"]);
+}
+
+#[tokio::test]
+async fn filter_allow_rules_first_match_wins() {
+    let pool = setup_pool().await;
+    let trusted = "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+    for (name, query, rule_order, rule_type) in [
+        ("trusted", format!(r#"pubkey == "{trusted}""#), 0, "allow"),
+        ("spam", r#"content contains "spam""#.to_string(), 1, "block"),
+        ("too late", r#"content contains "ok""#.to_string(), 2, "allow"),
+    ] {
+        sqlx::query("INSERT INTO filter_rules (name, nl_text, parsed_json, rule_order, rule_type) VALUES (?, ?, ?, ?, ?)")
+            .bind(name)
+            .bind(&query)
+            .bind(&query)
+            .bind(rule_order)
+            .bind(rule_type)
+            .execute(&pool)
+            .await
+            .unwrap();
+    }
+    let engine = FilterEngine::new();
+    let event = |pubkey: &str, content: &str| -> proxy_nostr_relay::nostr::event::Event {
+        serde_json::from_value(serde_json::json!({
+            "id": "ev",
+            "pubkey": pubkey,
+            "created_at": 123,
+            "kind": 1,
+            "tags": [],
+            "content": content,
+            "sig": "sig"
+        }))
+        .unwrap()
+    };
+
+    // the allow rule matches first
+    let verdict = engine.check_client_event(&pool, &event(trusted, "spam but ok")).await.unwrap();
+    assert_eq!(verdict, None);
+
+    // the block rule comes before the second allow rule
+    let other = "ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff";
+    let verdict = engine.check_client_event(&pool, &event(other, "spam but ok")).await.unwrap();
+    assert!(verdict.is_some_and(|v| v.reason.starts_with("filter_rule:")));
+}
+
+#[tokio::test]
+async fn filter_rule_hit_counters_reach_api() {
+    let pool = setup_pool().await;
+    auth::ensure_admin_user(&pool, "admin", "admin").await.unwrap();
+    for (name, query) in [("spam", r#"content contains "spam""#), ("never", r#"content == "never""#)] {
+        sqlx::query("INSERT INTO filter_rules (name, nl_text, parsed_json) VALUES (?, ?, ?)")
+            .bind(name)
+            .bind(query)
+            .bind(query)
+            .execute(&pool)
+            .await
+            .unwrap();
+    }
+    let engine = FilterEngine::new();
+    for content in ["spam", "spam", "hello"] {
+        let event: proxy_nostr_relay::nostr::event::Event = serde_json::from_value(serde_json::json!({
+            "id": "ev",
+            "pubkey": "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f",
+            "created_at": 123,
+            "kind": 1,
+            "tags": [],
+            "content": content,
+            "sig": "sig"
+        }))
+        .unwrap();
+        engine.check_client_event(&pool, &event).await.unwrap();
+    }
+
+    let app = api::routes::router(api::routes::AppState {
+        pool: pool.clone(),
+        filter_engine: engine.clone(),
+    });
+    let resp = app
+        .oneshot(
+            Request::builder()
+                .uri("/filters")
+                .header("authorization", basic_header("admin", "admin"))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let body = axum::body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+    let rules: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(rules[0]["name"], "spam");
+    assert_eq!(rules[0]["hit_count"], 2);
+    assert_eq!(rules[0]["eval_count"], 3);
+    assert!(rules[0]["last_matched_at"].is_string());
+    // only evaluated when the first rule did not match
+    assert_eq!(rules[1]["hit_count"], 0);
+    assert_eq!(rules[1]["eval_count"], 1);
+    assert!(rules[1]["last_matched_at"].is_null());
+}
+
+#[tokio::test]
+async fn monitor_rules_log_without_blocking() {
+    let pool = setup_pool().await;
+    auth::ensure_admin_user(&pool, "admin", "admin").await.unwrap();
+    let engine = FilterEngine::new();
+    let app = api::routes::router(api::routes::AppState {
+        pool: pool.clone(),
+        filter_engine: engine.clone(),
+    });
+
+    let resp = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/filters")
+                .header("authorization", basic_header("admin", "admin"))
+                .header("content-type", "application/json")
+                .body(Body::from(r#"{"name":"spam","nl_text":"content contains \"spam\"","mode":"monitor"}"#))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let body = axum::body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+    let created: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    let rule_id = created["id"].as_i64().unwrap();
+
+    let event: proxy_nostr_relay::nostr::event::Event = serde_json::from_value(serde_json::json!({
+        "id": "ev",
+        "pubkey": "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f",
+        "created_at": 123,
+        "kind": 1,
+        "tags": [],
+        "content": "buy spam now",
+        "sig": "sig"
+    }))
+    .unwrap();
+    assert_eq!(engine.check_client_event(&pool, &event).await.unwrap(), None);
+
+    let resp = app
+        .oneshot(
+            Request::builder()
+                .uri(format!("/filters/{rule_id}/would-block?hours=1"))
+                .header("authorization", basic_header("admin", "admin"))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let body = axum::body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+    let would_block: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(would_block["total"], 1);
+    assert_eq!(would_block["events"][0]["event_id"], "ev");
+    assert_eq!(would_block["events"][0]["reason"], format!("would_block:{rule_id}"));
+    assert_eq!(would_block["events"][0]["direction"], "inbound");
+}
+
+#[tokio::test]
+async fn backtest_runs_query_over_sampled_events() {
+    let pool = setup_pool().await;
+    auth::ensure_admin_user(&pool, "admin", "admin").await.unwrap();
+    let trusted = "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+    let other = "ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff";
+    let trusted_npub = bech32::encode::<bech32::Bech32>(
+        bech32::Hrp::parse("npub").unwrap(),
+        &hex::decode(trusted).unwrap(),
+    )
+    .unwrap();
+    sqlx::query("INSERT INTO safelist (npub, flags) VALUES (?, 1)")
+        .bind(&trusted_npub)
+        .execute(&pool)
+        .await
+        .unwrap();
+
+    let engine = FilterEngine::new().with_event_sampling(100, 1);
+    for (id, pubkey, content) in [("a", other, "gm spam"), ("b", trusted, "gm friends"), ("c", other, "hello")] {
+        let event: proxy_nostr_relay::nostr::event::Event = serde_json::from_value(serde_json::json!({
+            "id": id,
+            "pubkey": pubkey,
+            "created_at": 123,
+            "kind": 1,
+            "tags": [],
+            "content": content,
+            "sig": "sig"
+        }))
+        .unwrap();
+        engine.check_client_event(&pool, &event).await.unwrap();
+    }
+
+    let app = api::routes::router(api::routes::AppState {
+        pool: pool.clone(),
+        filter_engine: engine,
+    });
+    let backtest = |body: &'static str| {
+        let app = app.clone();
+        async move {
+            let resp = app
+                .oneshot(
+                    Request::builder()
+                        .method("POST")
+                        .uri("/filters/backtest")
+                        .header("authorization", basic_header("admin", "admin"))
+                        .header("content-type", "application/json")
+                        .body(Body::from(body))
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            let body = axum::body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap()
+        }
+    };
+
+    let result = backtest(r#"{"query":"content starts_with \"gm\"","limit":1}"#).await;
+    assert_eq!(result["valid"], true);
+    assert_eq!(result["scanned"], 3);
+    assert_eq!(result["match_count"], 2);
+    assert_eq!(result["samples"].as_array().unwrap().len(), 1);
+    assert_eq!(result["safelisted_match_count"], 1);
+    assert_eq!(result["safelisted_samples"][0]["event"]["id"], "b");
+    assert_eq!(result["safelisted_samples"][0]["npub"], trusted_npub);
+
+    let result = backtest(r#"{"query":"content starts_with \"gm\"","direction":"outbound"}"#).await;
+    assert_eq!(result["scanned"], 0);
+
+    let result = backtest(r#"{"query":"content contains"}"#).await;
+    assert_eq!(result["valid"], false);
+    assert!(result["error"].is_string());
+}
+
+#[tokio::test]
+async fn explain_reports_every_check_and_rule() {
+    let pool = setup_pool().await;
+    auth::ensure_admin_user(&pool, "admin", "admin").await.unwrap();
+    for (name, query, rule_order, mode) in [
+        ("watch gm", r#"content contains "gm""#, 0, "monitor"),
+        ("spam", r#"kind == 1 AND (content contains "spam" OR tag[t].value == "ad")"#, 1, "enforce"),
+        ("never reached", r#"kind == 1"#, 2, "enforce"),
+    ] {
+        sqlx::query("INSERT INTO filter_rules (name, nl_text, parsed_json, rule_order, mode) VALUES (?, ?, ?, ?, ?)")
+            .bind(name)
+            .bind(query)
+            .bind(query)
+            .bind(rule_order)
+            .bind(mode)
+            .execute(&pool)
+            .await
+            .unwrap();
+    }
+    let app = api::routes::router(api::routes::AppState {
+        pool: pool.clone(),
+        filter_engine: FilterEngine::new(),
+    });
+    let explain = |direction: &str| {
+        let app = app.clone();
+        let body = serde_json::json!({
+            "direction": direction,
+            "event": {
+                "id": "ev",
+                "pubkey": "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f",
+                "created_at": 123,
+                "kind": 1,
+                "tags": [["t", "ad"]],
+                "content": "gm",
+                "sig": "sig"
+            }
+        });
+        async move {
+            let resp = app
+                .oneshot(
+                    Request::builder()
+                        .method("POST")
+                        .uri("/filters/explain")
+                        .header("authorization", basic_header("admin", "admin"))
+                        .header("content-type", "application/json")
+                        .body(Body::from(body.to_string()))
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            let body = axum::body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap()
+        }
+    };
+
+    // backend events are not signature-checked by default, so the rules decide
+    let result = explain("outbound").await;
+    assert_eq!(result["success"], true);
+    assert_eq!(result["decision"], "drop");
+    let rules = result["rules"].as_array().unwrap();
+    let rule_id = rules[1]["id"].as_i64().unwrap();
+    assert_eq!(result["reason"], format!("filter_rule:{rule_id}"));
+    assert_eq!(rules[0]["matched"], true);
+    assert_eq!(rules[0]["decisive"], false);
+    assert_eq!(rules[1]["decisive"], true);
+    let or = &rules[1]["trace"]["children"][1];
+    assert_eq!(or["children"][0]["result"], false);
+    assert_eq!(or["children"][1]["text"], r#"tag[t].value == "ad""#);
+    assert_eq!(or["children"][1]["field_value"], "ad");
+    assert_eq!(rules[2]["matched"], true);
+    assert_eq!(rules[2]["reached"], false);
+
+    // client events must be signed and from a safelisted npub first
+    let result = explain("inbound").await;
+    assert_eq!(result["decision"], "drop");
+    assert!(result["signature_error"].is_string());
+    assert_eq!(result["post_allowed"], false);
+    assert_eq!(result["rules"][1]["reached"], false);
+}
+
+#[tokio::test]
+async fn filter_lists_resolve_and_recompile_rules() {
+    let pool = setup_pool().await;
+    auth::ensure_admin_user(&pool, "admin", "admin").await.unwrap();
+    let engine = FilterEngine::new();
+    let app = api::routes::router(api::routes::AppState {
+        pool: pool.clone(),
+        filter_engine: engine.clone(),
+    });
+    let call = |method: &str, uri: &str, body: serde_json::Value| {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("authorization", basic_header("admin", "admin"))
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let app = app.clone();
+        async move {
+            let resp = app.oneshot(request).await.unwrap();
+            let body = axum::body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap()
+        }
+    };
+
+    // unknown lists are rejected like any other invalid query
+    let rule = serde_json::json!({"name": "blocked kinds", "nl_text": "kind in @blocked_kinds"});
+    let result = call("POST", "/filters", rule.clone()).await;
+    assert_eq!(result["success"], false);
+    assert_eq!(result["error"], "Unknown list '@blocked_kinds'");
+
+    let result = call(
+        "POST",
+        "/lists",
+        serde_json::json!({"name": "blocked_kinds", "list_type": "kinds", "items": [7, "6"]}),
+    )
+    .await;
+    assert_eq!(result["success"], true);
+    let list_id = result["id"].as_i64().unwrap();
+    assert_eq!(call("POST", "/filters", rule).await["success"], true);
+    assert_eq!(call("GET", "/lists", serde_json::Value::Null).await[0]["items"], serde_json::json!([7, 6]));
+
+    let event: proxy_nostr_relay::nostr::event::Event = serde_json::from_value(serde_json::json!({
+        "id": "ev",
+        "pubkey": "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f",
+        "created_at": 123,
+        "kind": 1,
+        "tags": [],
+        "content": "gm",
+        "sig": "sig"
+    }))
+    .unwrap();
+    assert_eq!(engine.check_client_event(&pool, &event).await.unwrap(), None);
+
+    // editing the list recompiles the rule right away
+    let result = call(
+        "PUT",
+        &format!("/lists/{list_id}"),
+        serde_json::json!({"name": "blocked_kinds", "list_type": "kinds", "items": [1]}),
+    )
+    .await;
+    assert_eq!(result["success"], true);
+    let verdict = engine.check_client_event(&pool, &event).await.unwrap();
+    assert!(verdict.is_some_and(|v| v.reason.starts_with("filter_rule:")));
+
+    // a list in use can be neither renamed nor deleted
+    let result = call(
+        "PUT",
+        &format!("/lists/{list_id}"),
+        serde_json::json!({"name": "renamed", "list_type": "kinds", "items": [1]}),
+    )
+    .await;
+    assert_eq!(result["success"], false);
+    let result = call("DELETE", &format!("/lists/{list_id}"), serde_json::Value::Null).await;
+    assert_eq!(result["success"], false);
+    assert_eq!(result["error"], "List @blocked_kinds is used by filter rules: blocked kinds");
+}
+
+#[tokio::test]
+async fn kind_index_skips_rules_for_other_kinds() {
+    let pool = setup_pool().await;
+    for (name, query, rule_order) in [
+        ("reactions", r#"kind == 7 AND content contains "x""#, 0),
+        ("anything", r#"content contains "x" AND kind != 3"#, 1),
+        ("notes", "kind in [1, 7]", 2),
+    ] {
+        sqlx::query("INSERT INTO filter_rules (name, nl_text, parsed_json, rule_order) VALUES (?, ?, ?, ?)")
+            .bind(name)
+            .bind(query)
+            .bind(query)
+            .bind(rule_order)
+            .execute(&pool)
+            .await
+            .unwrap();
+    }
+    let engine = FilterEngine::new();
+    let check = |kind: i64, content: &str| {
+        let event: proxy_nostr_relay::nostr::event::Event = serde_json::from_value(serde_json::json!({
+            "id": format!("ev{kind}{content}"),
+            "pubkey": "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f",
+            "created_at": 123,
+            "kind": kind,
+            "tags": [],
+            "content": content,
+            "sig": "sig"
+        }))
+        .unwrap();
+        let engine = engine.clone();
+        let pool = pool.clone();
+        async move { engine.check_client_event(&pool, &event).await.unwrap().map(|v| v.reason) }
+    };
+    let rule_id = |name: &'static str| {
+        let pool = pool.clone();
+        async move {
+            sqlx::query_as::<_, (i64,)>("SELECT id FROM filter_rules WHERE name = ?")
+                .bind(name)
+                .fetch_one(&pool)
+                .await
+                .unwrap()
+                .0
+        }
+    };
+
+    // rule order still decides among the rules that can match the kind
+    assert_eq!(check(7, "x").await, Some(format!("filter_rule:{}", rule_id("reactions").await)));
+    assert_eq!(check(1, "x").await, Some(format!("filter_rule:{}", rule_id("anything").await)));
+    assert_eq!(check(1, "y").await, Some(format!("filter_rule:{}", rule_id("notes").await)));
+    assert_eq!(check(3, "x").await, None);
+
+    engine.flush_rule_stats(&pool).await.unwrap();
+    let evaluations: Vec<(String, i64)> =
+        sqlx::query_as("SELECT name, eval_count FROM filter_rules ORDER BY rule_order")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+    // kind 7 のルールは kind 7 のイベントでしか評価されない
+    assert_eq!(
+        evaluations,
+        vec![("reactions".to_string(), 1), ("anything".to_string(), 3), ("notes".to_string(), 1)]
+    );
+}
+
+#[tokio::test]
+async fn format_endpoint_returns_canonical_query() {
+    let pool = setup_pool().await;
+    auth::ensure_admin_user(&pool, "admin", "admin").await.unwrap();
+    let app = api::routes::router(api::routes::AppState {
+        pool: pool.clone(),
+        filter_engine: FilterEngine::new(),
+    });
+    let format = |query: &str| {
+        let app = app.clone();
+        let body = serde_json::json!({ "query": query });
+        async move {
+            let resp = app
+                .oneshot(
+                    Request::builder()
+                        .method("POST")
+                        .uri("/filters/format")
+                        .header("authorization", basic_header("admin", "admin"))
+                        .header("content-type", "application/json")
+                        .body(Body::from(body.to_string()))
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            let body = axum::body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap()
+        }
+    };
+
+    let result = format("((kind==6 or kind==7)) and not (content contains \"bot\")  # Bot対策").await;
+    assert_eq!(result["valid"], true);
+    assert_eq!(result["formatted"], "(kind == 6 OR kind == 7) AND NOT content contains \"bot\"");
+
+    // 未登録のリストでも整形はできる
+    let result = format("npub IN @unknown").await;
+    assert_eq!(result["formatted"], "npub in @unknown");
+
+    let result = format("kind ==").await;
+    assert_eq!(result["valid"], false);
+    assert_eq!(result["position"], 7);
+    assert!(result.get("formatted").is_none());
+}
+
+#[tokio::test]
+async fn safelist_filter_bypass_exempts_outbound_rules() {
+    let pool = setup_pool().await;
+    let query = r#"content contains "spam""#;
+    sqlx::query("INSERT INTO filter_rules (name, nl_text, parsed_json) VALUES ('spam', ?, ?)")
+        .bind(query)
+        .bind(query)
+        .execute(&pool)
+        .await
+        .unwrap();
+    let pubkey_hex = "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+    let hrp = bech32::Hrp::parse("npub").unwrap();
+    let npub = bech32::encode::<bech32::Bech32>(hrp, &hex::decode(pubkey_hex).unwrap()).unwrap();
+    sqlx::query("INSERT INTO safelist (npub, flags, memo) VALUES (?, 2, 'bypass')")
+        .bind(&npub)
+        .execute(&pool)
+        .await
+        .unwrap();
+    let engine = FilterEngine::new();
+    let event = serde_json::json!({
+        "id": "ev",
+        "pubkey": pubkey_hex,
+        "created_at": 123,
+        "kind": 1,
+        "tags": [],
+        "content": "spam",
+        "sig": "sig"
+    });
+
+    let backend = serde_json::json!(["EVENT", "sub", event]).to_string();
+    assert!(!engine.should_drop_backend_text(&pool, &backend).await.unwrap());
+
+    // the flag is the only source of the bypass: clearing it revokes it
+    sqlx::query("UPDATE safelist SET flags = 0 WHERE npub = ?")
+        .bind(&npub)
+        .execute(&pool)
+        .await
+        .unwrap();
+    assert!(engine.should_drop_backend_text(&pool, &backend).await.unwrap());
+    sqlx::query("UPDATE safelist SET flags = 2 WHERE npub = ?")
+        .bind(&npub)
+        .execute(&pool)
+        .await
+        .unwrap();
+
+    // inbound rules still apply, as before the bypass flag only covered backend events
+    let event = serde_json::from_value(event).unwrap();
+    let verdict = engine.check_client_event(&pool, &event).await.unwrap();
+    assert!(verdict.is_some_and(|v| v.reason.starts_with("filter_rule:")));
+}
+
+#[tokio::test]
+async fn delayed_client_event_gets_exactly_one_ok() {
+    use futures_util::{SinkExt, StreamExt};
+    use proxy_nostr_relay::nostr::event::Event;
+    use proxy_nostr_relay::proxy::{pool::UpstreamPool, rate_limit::{RateLimiter, RateLimits}, ws_proxy::{self, ProxyConfig}};
+    use tokio_tungstenite::tungstenite::Message;
+
+    // backend that rejects every EVENT it receives
+    let backend = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let backend_url = format!("ws://{}", backend.local_addr().unwrap());
+    let (received_tx, mut received_rx) = tokio::sync::mpsc::unbounded_channel::<String>();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = backend.accept().await {
+            let received_tx = received_tx.clone();
+            tokio::spawn(async move {
+                let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+                while let Some(Ok(Message::Text(text))) = ws.next().await {
+                    let v: serde_json::Value = serde_json::from_str(&text).unwrap();
+                    if v[0] == "EVENT" {
+                        let id = v[1]["id"].as_str().unwrap().to_string();
+                        let _ = received_tx.send(id.clone());
+                        let ok = serde_json::json!(["OK", id, false, "blocked: not here"]);
+                        let _ = ws.send(Message::Text(ok.to_string())).await;
+                    }
+                }
+            });
+        }
+    });
+
+    let keypair = secp256k1::Keypair::from_seckey_slice(secp256k1::SECP256K1, &[0x11; 32]).unwrap();
+    let (pubkey, _) = keypair.x_only_public_key();
+    let mut event = Event {
+        id: String::new(),
+        pubkey: hex::encode(pubkey.serialize()),
+        created_at: 1700000000,
+        kind: 1,
+        tags: vec![],
+        content: "see you later".to_string(),
+        sig: String::new(),
+    };
+    let id = event.compute_id();
+    event.id = hex::encode(id);
+    let sig = secp256k1::SECP256K1.sign_schnorr_no_aux_rand(&secp256k1::Message::from_digest(id), &keypair);
+    event.sig = hex::encode(sig.serialize());
+
+    let pool = setup_pool().await;
+    let hrp = bech32::Hrp::parse("npub").unwrap();
+    let npub = bech32::encode::<bech32::Bech32>(hrp, &pubkey.serialize()).unwrap();
+    sqlx::query("INSERT INTO safelist (npub, flags, memo) VALUES (?, 1, 'poster')")
+        .bind(&npub)
+        .execute(&pool)
+        .await
+        .unwrap();
+    let query = r#"content contains "later""#;
+    sqlx::query("INSERT INTO filter_rules (name, nl_text, parsed_json, action, delay_secs) VALUES ('hold', ?, ?, 'delay', 1)")
+        .bind(query)
+        .bind(query)
+        .execute(&pool)
+        .await
+        .unwrap();
+
+    let upstreams = UpstreamPool::with_urls(vec![backend_url]);
+    let app = axum::Router::new().route(
+        "/",
+        axum::routing::get(move |ws: axum::extract::WebSocketUpgrade| {
+            let (upstreams, pool) = (upstreams.clone(), pool.clone());
+            async move {
+                ws.on_upgrade(move |socket| async move {
+                    let limiter = RateLimiter::with_limits(RateLimits::default());
+                    let _ = ws_proxy::proxy_ws_with_pool(socket, upstreams, limiter, FilterEngine::new(), ProxyConfig::default(), Some(pool), None).await;
+                })
+            }
+        }),
+    );
+    let proxy = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let proxy_url = format!("ws://{}/", proxy.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(proxy, app).await.unwrap() });
+
+    let (mut client, _) = tokio_tungstenite::connect_async(proxy_url).await.unwrap();
+    client
+        .send(Message::Text(serde_json::json!(["EVENT", event]).to_string()))
+        .await
+        .unwrap();
+
+    // the held event reaches the backend after the delay, and its rejection is not forwarded
+    let mut oks = Vec::new();
+    let collect = async {
+        while let Some(Ok(Message::Text(text))) = client.next().await {
+            let v: serde_json::Value = serde_json::from_str(&text).unwrap();
+            if v[0] == "OK" {
+                oks.push(v);
+            }
+        }
+    };
+    let _ = tokio::time::timeout(std::time::Duration::from_secs(3), collect).await;
+    assert_eq!(received_rx.try_recv().unwrap(), event.id);
+    assert_eq!(oks, vec![serde_json::json!(["OK", event.id, true, ""])]);
+}
+
+#[tokio::test]
+async fn filter_update_keeps_omitted_action_fields() {
+    let pool = setup_pool().await;
+    auth::ensure_admin_user(&pool, "admin", "admin").await.unwrap();
+    let app = api::routes::router(api::routes::AppState {
+        pool: pool.clone(),
+        filter_engine: FilterEngine::new(),
+    });
+    let request = |method: &str, uri: &str, body: &str| {
+        Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("authorization", basic_header("admin", "admin"))
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    };
+
+    let resp = app
+        .clone()
+        .oneshot(request(
+            "POST",
+            "/filters",
+            r#"{"name":"hold","nl_text":"content contains \"http\"","direction":"outbound","action":"delay","delay_secs":60}"#,
+        ))
+        .await
+        .unwrap();
+    let body = axum::body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+    let created: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    let id = created["id"].as_i64().unwrap();
+
+    // a partial update that only renames the rule
+    let resp = app
+        .oneshot(request(
+            "PUT",
+            &format!("/filters/{id}"),
+            r#"{"name":"hold links","nl_text":"content contains \"http\"","enabled":true,"rule_order":0}"#,
+        ))
+        .await
+        .unwrap();
+    let body = axum::body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+    let updated: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(updated["success"], true);
+
+    let row: (String, String, String, Option<i64>, String) = sqlx::query_as(
+        "SELECT name, direction, action, delay_secs, rule_type FROM filter_rules WHERE id = ?",
+    )
+    .bind(id)
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    assert_eq!(row, ("hold links".to_string(), "outbound".to_string(), "delay".to_string(), Some(60), "block".to_string()));
+}
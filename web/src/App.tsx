@@ -32,8 +32,101 @@ interface FilterRule {
   parsed_json: string;
   enabled: boolean;
   rule_order: number;
+  direction: FilterDirection;
+  action: FilterAction;
+  delay_secs?: number;
+  rule_type: FilterRuleType;
+  mode: FilterMode;
+  hit_count: number;
+  eval_count: number;
+  avg_eval_us: number;
+  last_matched_at?: string;
 }
 
+type FilterRuleType = 'block' | 'allow';
+
+type FilterMode = 'off' | 'monitor' | 'enforce';
+
+interface BacktestMatch {
+  npub: string;
+  direction: string;
+  sampled_at: string;
+  event: { id: string; kind: number; content: string };
+}
+
+interface TraceNode {
+  node: string;
+  text: string;
+  result: boolean;
+  field_value?: unknown;
+  compared_value?: unknown;
+  children?: TraceNode[];
+}
+
+interface RuleExplanation {
+  id: number;
+  name: string;
+  rule_type: FilterRuleType;
+  mode: FilterMode;
+  action: FilterAction;
+  applies: boolean;
+  reached: boolean;
+  matched: boolean;
+  decisive: boolean;
+  trace?: TraceNode;
+}
+
+interface Explanation {
+  success: boolean;
+  error?: string;
+  decision: string;
+  reason?: string;
+  npub: string;
+  signature_error?: string;
+  signature_checked: boolean;
+  post_allowed?: boolean;
+  banned_npub: boolean;
+  kind_blacklisted: boolean;
+  rules: RuleExplanation[];
+  bot_filter: { applies: boolean; filter_bypass: boolean; referenced_created_at?: number; matched: boolean };
+}
+
+interface BacktestResult {
+  valid: boolean;
+  error?: string;
+  scanned: number;
+  match_count: number;
+  samples: BacktestMatch[];
+  safelisted_match_count: number;
+  safelisted_samples: BacktestMatch[];
+}
+
+interface WouldBlockEvent {
+  id: number;
+  event_id: string;
+  npub: string;
+  kind: number;
+  content_preview: string;
+  direction: string;
+  reason: string;
+  created_at: string;
+}
+
+type FilterListType = 'pubkeys' | 'words' | 'domains' | 'kinds';
+
+interface FilterList {
+  id: number;
+  name: string;
+  list_type: FilterListType;
+  description: string;
+  items: (string | number)[];
+  updated_at: string;
+}
+
+type FilterDirection = 'inbound' | 'outbound' | 'both';
+
+type FilterAction = 'drop' | 'shadow' | 'flag' | 'delay';
+
 interface RelayConfig {
   url: string;
   enabled: boolean;
@@ -57,6 +150,8 @@ interface EventRejectionLog {
   kind: number;
   reason: string;
   created_at: string;
+  direction?: 'inbound' | 'outbound';
+  action?: FilterAction;
 }
 
 interface Stats {
@@ -66,6 +161,20 @@ interface Stats {
   rejections_by_reason: { reason: string; count: number }[];
   top_npubs_by_rejections: { npub: string; count: number }[];
   top_ips_by_rejections: { ip_address: string; count: number }[];
+  filter_rule_hits: { id: number; name: string; enabled: boolean; hit_count: number; eval_count: number; avg_eval_us: number; last_matched_at?: string }[];
+}
+
+interface Kind1CacheStats {
+  entries: number;
+  max_entries: number;
+  ttl_secs: number;
+  hits: number;
+  misses: number;
+  hit_rate: number;
+  evictions: number;
+  expirations: number;
+  fetches: number;
+  fetch_hits: number;
 }
 
 interface RelayInfo {
@@ -84,9 +193,17 @@ interface RelayInfo {
   limitation_auth_required: boolean;
   limitation_payment_required: boolean;
   icon?: string;
+  auth_restricted_reads: boolean;
 }
 
-type Tab = 'dashboard' | 'relays' | 'relay-info' | 'safelist' | 'ip' | 'kind' | 'filters' | 'logs';
+interface RateLimits {
+  events_per_minute_per_ip?: number;
+  events_per_minute_per_pubkey?: number;
+  reqs_per_minute_per_ip?: number;
+  max_connections_per_ip?: number;
+}
+
+type Tab = 'dashboard' | 'relays' | 'relay-info' | 'safelist' | 'ip' | 'kind' | 'rate-limits' | 'filters' | 'logs';
 
 function App() {
   const [activeTab, setActiveTab] = useState<Tab>('dashboard');
@@ -115,6 +232,9 @@ function App() {
         <button className={activeTab === 'kind' ? 'active' : ''} onClick={() => setActiveTab('kind')}>
           Kind Blacklist
         </button>
+        <button className={activeTab === 'rate-limits' ? 'active' : ''} onClick={() => setActiveTab('rate-limits')}>
+          Rate Limits
+        </button>
         <button className={activeTab === 'filters' ? 'active' : ''} onClick={() => setActiveTab('filters')}>
           Filter Rules
         </button>
@@ -130,7 +250,8 @@ function App() {
           {activeTab === 'safelist' && <SafelistSection />}
           {activeTab === 'ip' && <IpSection />}
           {activeTab === 'kind' && <KindBlacklistSection />}
-          {activeTab === 'filters' && <FiltersSection />}
+          {activeTab === 'rate-limits' && <RateLimitsSection />}
+          {activeTab === 'filters' && <><FiltersSection /><FilterListsSection /><ExplainSection /></>}
           {activeTab === 'logs' && <LogsSection />}
         </div>
       </main>
@@ -141,6 +262,7 @@ function App() {
 // Dashboard Section
 function DashboardSection() {
   const [stats, setStats] = useState<Stats | null>(null);
+  const [cache, setCache] = useState<Kind1CacheStats | null>(null);
   const [loading, setLoading] = useState(true);
 
   useEffect(() => {
@@ -149,6 +271,10 @@ function DashboardSection() {
         .then(res => res.json())
         .then(data => { setStats(data); setLoading(false); })
         .catch(() => setLoading(false));
+      fetch('/api/cache/kind1')
+        .then(res => res.json())
+        .then(setCache)
+        .catch(() => setCache(null));
     };
     fetchStats();
     const interval = setInterval(fetchStats, 10000);
@@ -238,6 +364,54 @@ function DashboardSection() {
             )}
           </div>
         </div>
+
+        {/* Filter rule hits */}
+        <div className="mini-panel">
+          <div className="mini-panel-header">
+            <span className="icon purple"></span>
+            Filter Rule Hits
+          </div>
+          <div className="mini-list">
+            {stats.filter_rule_hits.length === 0 ? (
+              <div className="mini-list-item"><span className="label">No filter rules</span></div>
+            ) : (
+              stats.filter_rule_hits.slice(0, 8).map(r => (
+                <div className="mini-list-item" key={r.id}>
+                  <span className="label">{r.name}{r.hit_count === 0 ? ' (never matched)' : ''}</span>
+                  <span className="value">{r.hit_count.toLocaleString()}</span>
+                </div>
+              ))
+            )}
+          </div>
+        </div>
+
+        {/* Referenced kind1 cache */}
+        {cache && (
+          <div className="mini-panel">
+            <div className="mini-panel-header">
+              <span className="icon green"></span>
+              Kind1 Cache
+            </div>
+            <div className="mini-list">
+              <div className="mini-list-item">
+                <span className="label">Entries</span>
+                <span className="value">{cache.entries.toLocaleString()} / {cache.max_entries.toLocaleString()}</span>
+              </div>
+              <div className="mini-list-item">
+                <span className="label">Hit rate</span>
+                <span className="value">{(cache.hit_rate * 100).toFixed(1)}%</span>
+              </div>
+              <div className="mini-list-item">
+                <span className="label">Evicted / Expired</span>
+                <span className="value">{cache.evictions.toLocaleString()} / {cache.expirations.toLocaleString()}</span>
+              </div>
+              <div className="mini-list-item">
+                <span className="label">Backend fetches (found)</span>
+                <span className="value">{cache.fetches.toLocaleString()} ({cache.fetch_hits.toLocaleString()})</span>
+              </div>
+            </div>
+          </div>
+        )}
       </div>
     </>
   );
@@ -251,6 +425,12 @@ function formatReason(reason: string): string {
     'bot_filter': 'Bot Filter',
     'not_in_safelist': 'Not in Safelist',
     'filter_rule': 'Filter Rule',
+    'invalid_id': 'Invalid Event ID',
+    'invalid_signature': 'Invalid Signature',
+    'auth_required': 'Auth Required',
+    'rate_limited': 'Rate Limited',
+    'too_many_tags': 'Too Many Tags',
+    'content_too_long': 'Content Too Long',
   };
   return map[reason] || reason;
 }
@@ -391,6 +571,7 @@ function RelayInfoSection() {
     version: '0.1.0',
     limitation_auth_required: false,
     limitation_payment_required: false,
+    auth_restricted_reads: false,
   });
   const [loading, setLoading] = useState(true);
   const [saving, setSaving] = useState(false);
@@ -577,6 +758,14 @@ function RelayInfoSection() {
             />
             Payment Required
           </label>
+          <label>
+            <input 
+              type="checkbox"
+              checked={info.auth_restricted_reads} 
+              onChange={e => setInfo({ ...info, auth_restricted_reads: e.target.checked })}
+            />
+            Restrict Reads to Authenticated Safelist
+          </label>
         </div>
       </div>
 
@@ -596,6 +785,79 @@ function RelayInfoSection() {
   );
 }
 
+// Rate Limits Section
+function RateLimitsSection() {
+  const [limits, setLimits] = useState<RateLimits>({});
+  const [loading, setLoading] = useState(true);
+  const [saving, setSaving] = useState(false);
+  const [message, setMessage] = useState('');
+
+  useEffect(() => {
+    fetch('/api/rate-limits')
+      .then(res => res.json())
+      .then(data => { setLimits(data); setLoading(false); })
+      .catch(() => setLoading(false));
+  }, []);
+
+  const saveLimits = () => {
+    setSaving(true);
+    setMessage('');
+    fetch('/api/rate-limits', {
+      method: 'PUT',
+      headers: { 'Content-Type': 'application/json' },
+      body: JSON.stringify(limits)
+    })
+      .then(() => {
+        setMessage('Saved successfully!');
+        setSaving(false);
+        setTimeout(() => setMessage(''), 3000);
+      })
+      .catch(() => {
+        setMessage('Failed to save');
+        setSaving(false);
+      });
+  };
+
+  const fields: { key: keyof RateLimits; label: string; placeholder: string }[] = [
+    { key: 'events_per_minute_per_ip', label: 'EVENTs per Minute per IP', placeholder: '60' },
+    { key: 'events_per_minute_per_pubkey', label: 'EVENTs per Minute per Pubkey', placeholder: '30' },
+    { key: 'reqs_per_minute_per_ip', label: 'REQs per Minute per IP', placeholder: '120' },
+    { key: 'max_connections_per_ip', label: 'Concurrent Connections per IP', placeholder: '10' },
+  ];
+
+  if (loading) return <div className="loading">Loading...</div>;
+
+  return (
+    <div className="section">
+      <h2>Rate Limits</h2>
+      <p style={{ color: 'var(--text-muted)', marginBottom: '1.5rem' }}>
+        Token-bucket limits per client IP and per event pubkey. Leave empty for no limit. Whitelisted IPs are exempt.
+      </p>
+
+      <div className="form-grid">
+        {fields.map(f => (
+          <div className="form-group" key={f.key}>
+            <label>{f.label}</label>
+            <input 
+              type="number"
+              value={limits[f.key] || ''} 
+              onChange={e => setLimits({ ...limits, [f.key]: e.target.value ? parseInt(e.target.value) : undefined })}
+              placeholder={f.placeholder}
+            />
+          </div>
+        ))}
+      </div>
+
+      <div className="form-actions">
+        <button onClick={saveLimits} disabled={saving}>
+          {saving ? 'Saving...' : 'Save Changes'}
+        </button>
+        {message && <span className={message.includes('success') ? 'success-msg' : 'error-msg'}>{message}</span>}
+      </div>
+    </div>
+  );
+}
+
 // Safelist Section
 function SafelistSection() {
   const [safelist, setSafelist] = useState<SafelistEntry[]>([]);
@@ -658,7 +920,7 @@ function SafelistSection() {
             checked={(newEntry.flags & 2) === 2} 
             onChange={e => setNewEntry({ ...newEntry, flags: e.target.checked ? newEntry.flags | 2 : newEntry.flags & ~2 })} 
           />
-          Filter Bypass
+          Filter Bypass (outbound)
         </label>
         <input 
           placeholder="Memo" 
@@ -937,7 +1199,11 @@ function KindBlacklistSection() {
 // Filters Section
 function FiltersSection() {
   const [filters, setFilters] = useState<FilterRule[]>([]);
-  const [newFilter, setNewFilter] = useState({ name: '', nl_text: '' });
+  const emptyFilter = { name: '', nl_text: '', direction: 'both' as FilterDirection, rule_type: 'block' as FilterRuleType, mode: 'enforce' as FilterMode, action: 'drop' as FilterAction, delay_secs: 60 };
+  const [newFilter, setNewFilter] = useState(emptyFilter);
+  const [backtest, setBacktest] = useState<BacktestResult | null>(null);
+  const [wouldBlock, setWouldBlock] = useState<{ filter: FilterRule; total: number; events: WouldBlockEvent[] } | null>(null);
+  const [error, setError] = useState<string | null>(null);
   const [loading, setLoading] = useState(true);
 
   const fetchFilters = () => {
@@ -954,14 +1220,66 @@ function FiltersSection() {
       method: 'POST',
       headers: { 'Content-Type': 'application/json' },
       body: JSON.stringify(newFilter)
-    }).then(() => { fetchFilters(); setNewFilter({ name: '', nl_text: '' }); });
+    })
+      .then(res => res.json())
+      .then(data => {
+        if (!data.success) { setError(data.error || 'Failed to add filter'); return; }
+        setError(null);
+        fetchFilters();
+        setNewFilter(emptyFilter);
+      });
   };
 
-  const toggleEnabled = (filter: FilterRule) => {
+  const runBacktest = () => {
+    if (!newFilter.nl_text) return;
+    fetch('/api/filters/backtest', {
+      method: 'POST',
+      headers: { 'Content-Type': 'application/json' },
+      body: JSON.stringify({ query: newFilter.nl_text, direction: newFilter.direction })
+    })
+      .then(res => res.json())
+      .then(data => {
+        if (!data.valid) { setError(data.error || 'Invalid query'); setBacktest(null); return; }
+        setError(null);
+        setBacktest(data);
+      });
+  };
+
+  const formatQuery = () => {
+    if (!newFilter.nl_text) return;
+    fetch('/api/filters/format', {
+      method: 'POST',
+      headers: { 'Content-Type': 'application/json' },
+      body: JSON.stringify({ query: newFilter.nl_text })
+    })
+      .then(res => res.json())
+      .then(data => {
+        if (!data.valid) { setError(data.error || 'Invalid query'); return; }
+        setError(null);
+        setNewFilter({ ...newFilter, nl_text: data.formatted });
+      });
+  };
+
+  const updateMode = (filter: FilterRule, mode: FilterMode) => {
+    fetch(`/api/filters/${filter.id}`, {
+      method: 'PUT',
+      headers: { 'Content-Type': 'application/json' },
+      body: JSON.stringify({ ...filter, mode })
+    }).then(fetchFilters);
+  };
+
+  const showWouldBlock = (filter: FilterRule) => {
+    fetch(`/api/filters/${filter.id}/would-block?hours=24`)
+      .then(res => res.json())
+      .then(data => setWouldBlock({ filter, total: data.total, events: data.events }));
+  };
+
+  const updateOrder = (filter: FilterRule, rule_order: number) => {
+    if (rule_order === filter.rule_order) return;
     fetch(`/api/filters/${filter.id}`, {
       method: 'PUT',
       headers: { 'Content-Type': 'application/json' },
-      body: JSON.stringify({ ...filter, enabled: !filter.enabled })
+      body: JSON.stringify({ ...filter, rule_order })
     }).then(fetchFilters);
   };
 
@@ -987,29 +1305,121 @@ function FiltersSection() {
           onChange={e => setNewFilter({ ...newFilter, nl_text: e.target.value })} 
           className="wide"
         />
+        <select
+          value={newFilter.direction}
+          onChange={e => setNewFilter({ ...newFilter, direction: e.target.value as FilterDirection })}
+        >
+          <option value="both">Both</option>
+          <option value="inbound">Inbound (client → relay)</option>
+          <option value="outbound">Outbound (relay → client)</option>
+        </select>
+        <select
+          value={newFilter.rule_type}
+          onChange={e => setNewFilter({ ...newFilter, rule_type: e.target.value as FilterRuleType })}
+        >
+          <option value="block">Block</option>
+          <option value="allow">Allow (stop here)</option>
+        </select>
+        <select
+          value={newFilter.mode}
+          onChange={e => setNewFilter({ ...newFilter, mode: e.target.value as FilterMode })}
+        >
+          <option value="enforce">Enforce</option>
+          <option value="monitor">Monitor (log only)</option>
+          <option value="off">Off</option>
+        </select>
+        {newFilter.rule_type === 'block' && (
+          <select
+            value={newFilter.action}
+            onChange={e => setNewFilter({ ...newFilter, action: e.target.value as FilterAction })}
+          >
+            <option value="drop">Drop</option>
+            <option value="shadow">Shadow (OK but not forwarded)</option>
+            <option value="flag">Flag (forward and log)</option>
+            <option value="delay">Delay</option>
+          </select>
+        )}
+        {newFilter.rule_type === 'block' && newFilter.action === 'delay' && (
+          <input
+            type="number"
+            min={1}
+            max={3600}
+            placeholder="Seconds"
+            value={newFilter.delay_secs}
+            onChange={e => setNewFilter({ ...newFilter, delay_secs: Number(e.target.value) })}
+          />
+        )}
+        <button className="btn-secondary" onClick={formatQuery}>Format</button>
+        <button className="btn-secondary" onClick={runBacktest}>Backtest</button>
         <button onClick={addFilter}>Add Rule</button>
       </div>
+      {error && <div className="empty-state">{error}</div>}
+      {backtest && (
+        <div className="table-container">
+          <p>
+            Matched {backtest.match_count.toLocaleString()} of {backtest.scanned.toLocaleString()} sampled events
+            {backtest.safelisted_match_count > 0 && ` (${backtest.safelisted_match_count} from safelisted npubs)`}
+            {' '}<button className="btn-small btn-secondary" onClick={() => setBacktest(null)}>Close</button>
+          </p>
+          <table>
+            <thead>
+              <tr><th>Sampled</th><th>Direction</th><th>Kind</th><th>Npub</th><th>Content</th></tr>
+            </thead>
+            <tbody>
+              {[...backtest.safelisted_samples, ...backtest.samples.filter(m => !backtest.safelisted_samples.some(s => s.event.id === m.event.id))].map(m => (
+                <tr key={m.event.id}>
+                  <td style={{ whiteSpace: 'nowrap' }}>{new Date(m.sampled_at).toLocaleString()}</td>
+                  <td>{m.direction}</td>
+                  <td>{m.event.kind}</td>
+                  <td style={{ fontFamily: 'monospace' }}>
+                    {backtest.safelisted_samples.some(s => s.event.id === m.event.id) && <span className="badge badge-info">safelist</span>}{' '}
+                    {m.npub.slice(0, 16)}…
+                  </td>
+                  <td style={{ color: 'var(--text-muted)' }}>{m.event.content.slice(0, 200)}</td>
+                </tr>
+              ))}
+            </tbody>
+          </table>
+        </div>
+      )}
 
       <div className="table-container">
         <table>
           <thead>
-            <tr><th>Name</th><th>Condition</th><th>Status</th><th>Actions</th></tr>
+            <tr><th>Order</th><th>Name</th><th>Condition</th><th>Direction</th><th>On Match</th><th>Hits</th><th>Avg Eval</th><th>Last Match</th><th>Mode</th><th>Actions</th></tr>
           </thead>
           <tbody>
             {filters.length === 0 ? (
-              <tr><td colSpan={4} className="empty-state">No filters configured</td></tr>
+              <tr><td colSpan={10} className="empty-state">No filters configured</td></tr>
             ) : (
               filters.map(filter => (
                 <tr key={filter.id}>
+                  <td>
+                    <input
+                      type="number"
+                      defaultValue={filter.rule_order}
+                      style={{ width: '4em' }}
+                      onBlur={e => updateOrder(filter, Number(e.target.value))}
+                    />
+                  </td>
                   <td style={{ fontWeight: 500 }}>{filter.name}</td>
                   <td style={{ color: 'var(--text-muted)' }}>{filter.nl_text}</td>
+                  <td>{filter.direction}</td>
+                  <td>{filter.rule_type === 'allow' ? <span className="badge badge-info">allow</span> : filter.action === 'delay' ? `delay ${filter.delay_secs}s` : filter.action}</td>
+                  <td style={{ fontFamily: 'monospace' }}>{filter.hit_count.toLocaleString()} / {filter.eval_count.toLocaleString()}</td>
+                  <td style={{ fontFamily: 'monospace' }}>{filter.avg_eval_us.toFixed(1)} µs</td>
+                  <td style={{ whiteSpace: 'nowrap' }}>{filter.last_matched_at ? new Date(filter.last_matched_at).toLocaleString() : '—'}</td>
                   <td>
-                    <div 
-                      className={`toggle ${filter.enabled ? 'active' : ''}`} 
-                      onClick={() => toggleEnabled(filter)}
-                    ></div>
+                    <select value={filter.mode} onChange={e => updateMode(filter, e.target.value as FilterMode)}>
+                      <option value="enforce">Enforce</option>
+                      <option value="monitor">Monitor</option>
+                      <option value="off">Off</option>
+                    </select>
                   </td>
                   <td>
+                    {filter.mode === 'monitor' && (
+                      <button className="btn-small" onClick={() => showWouldBlock(filter)}>Would Block</button>
+                    )}
                     <button className="btn-small btn-secondary" onClick={() => deleteFilter(filter.id)}>Delete</button>
                   </td>
                 </tr>
@@ -1018,6 +1428,233 @@ function FiltersSection() {
           </tbody>
         </table>
       </div>
+
+      {wouldBlock && (
+        <>
+          <h3>
+            Would {wouldBlock.filter.rule_type === 'allow' ? 'allow' : 'block'}: {wouldBlock.filter.name} (last 24h, {wouldBlock.total} events)
+            {' '}<button className="btn-small btn-secondary" onClick={() => setWouldBlock(null)}>Close</button>
+          </h3>
+          <div className="table-container">
+            <table>
+              <thead>
+                <tr><th>Time</th><th>Direction</th><th>Kind</th><th>Npub</th><th>Content</th></tr>
+              </thead>
+              <tbody>
+                {wouldBlock.events.length === 0 ? (
+                  <tr><td colSpan={5} className="empty-state">No matching events</td></tr>
+                ) : (
+                  wouldBlock.events.map(ev => (
+                    <tr key={ev.id}>
+                      <td style={{ whiteSpace: 'nowrap' }}>{new Date(ev.created_at).toLocaleString()}</td>
+                      <td>{ev.direction}</td>
+                      <td>{ev.kind}</td>
+                      <td style={{ fontFamily: 'monospace' }}>{ev.npub.slice(0, 16)}…</td>
+                      <td style={{ color: 'var(--text-muted)' }}>{ev.content_preview}</td>
+                    </tr>
+                  ))
+                )}
+              </tbody>
+            </table>
+          </div>
+        </>
+      )}
+    </div>
+  );
+}
+
+function TraceView({ node }: { node: TraceNode }) {
+  return (
+    <li>
+      <span style={{ color: node.result ? 'var(--success, green)' : 'var(--text-muted)' }}>
+        {node.result ? '✓' : '✗'} <code>{node.text}</code>
+      </span>
+      {node.field_value !== undefined && <span style={{ color: 'var(--text-muted)' }}> (value: <code>{JSON.stringify(node.field_value)}</code>{node.compared_value !== undefined && <> vs <code>{JSON.stringify(node.compared_value)}</code></>})</span>}
+      {node.children && node.children.length > 0 && (
+        <ul>{node.children.map((c, i) => <TraceView key={i} node={c} />)}</ul>
+      )}
+    </li>
+  );
+}
+
+// Explain Section: 1件のイベントがどう判定されるかを確認する
+function FilterListsSection() {
+  const empty = { name: '', list_type: 'pubkeys' as FilterListType, description: '', items: '' };
+  const [lists, setLists] = useState<FilterList[]>([]);
+  const [form, setForm] = useState(empty);
+  const [editingId, setEditingId] = useState<number | null>(null);
+  const [error, setError] = useState<string | null>(null);
+
+  const fetchLists = () => {
+    fetch('/api/lists').then(res => res.json()).then(setLists);
+  };
+
+  useEffect(() => { fetchLists(); }, []);
+
+  const save = () => {
+    const body = { ...form, items: form.items.split('\n') };
+    fetch(editingId === null ? '/api/lists' : `/api/lists/${editingId}`, {
+      method: editingId === null ? 'POST' : 'PUT',
+      headers: { 'Content-Type': 'application/json' },
+      body: JSON.stringify(body)
+    })
+      .then(res => res.json())
+      .then(data => {
+        if (!data.success) { setError(data.error || 'Failed to save list'); return; }
+        setError(null);
+        setForm(empty);
+        setEditingId(null);
+        fetchLists();
+      });
+  };
+
+  const edit = (list: FilterList) => {
+    setEditingId(list.id);
+    setForm({ name: list.name, list_type: list.list_type, description: list.description, items: list.items.join('\n') });
+  };
+
+  const deleteList = (list: FilterList) => {
+    if (!confirm(`Delete @${list.name}?`)) return;
+    fetch(`/api/lists/${list.id}`, { method: 'DELETE' })
+      .then(res => res.json())
+      .then(data => {
+        setError(data.success ? null : data.error || 'Failed to delete list');
+        fetchLists();
+      });
+  };
+
+  return (
+    <div className="section">
+      <h2>Named Lists</h2>
+      <div className="form-row">
+        <input placeholder="Name (e.g., known_bots)" value={form.name} onChange={e => setForm({ ...form, name: e.target.value })} />
+        <select value={form.list_type} onChange={e => setForm({ ...form, list_type: e.target.value as FilterListType })}>
+          <option value="pubkeys">Pubkeys</option>
+          <option value="words">Words</option>
+          <option value="domains">Domains</option>
+          <option value="kinds">Kinds</option>
+        </select>
+        <input placeholder="Description" value={form.description} onChange={e => setForm({ ...form, description: e.target.value })} />
+      </div>
+      <div className="form-row">
+        <textarea
+          className="wide"
+          rows={4}
+          placeholder="One item per line"
+          value={form.items}
+          onChange={e => setForm({ ...form, items: e.target.value })}
+        />
+        <button onClick={save}>{editingId === null ? 'Add List' : 'Save List'}</button>
+        {editingId !== null && <button className="btn-secondary" onClick={() => { setEditingId(null); setForm(empty); }}>Cancel</button>}
+      </div>
+      {error && <div className="empty-state">{error}</div>}
+      <div className="table-container">
+        <table>
+          <thead>
+            <tr><th>Reference</th><th>Type</th><th>Items</th><th>Description</th><th>Updated</th><th>Actions</th></tr>
+          </thead>
+          <tbody>
+            {lists.length === 0 ? (
+              <tr><td colSpan={6} className="empty-state">No lists. Use them in queries as npub in @name</td></tr>
+            ) : (
+              lists.map(list => (
+                <tr key={list.id}>
+                  <td style={{ fontFamily: 'monospace', fontWeight: 500 }}>@{list.name}</td>
+                  <td><span className="badge badge-info">{list.list_type.toUpperCase()}</span></td>
+                  <td>{list.items.length}</td>
+                  <td>{list.description}</td>
+                  <td>{list.updated_at}</td>
+                  <td>
+                    <button className="btn-small btn-secondary" onClick={() => edit(list)}>Edit</button>
+                    <button className="btn-small btn-secondary" onClick={() => deleteList(list)}>Delete</button>
+                  </td>
+                </tr>
+              ))
+            )}
+          </tbody>
+        </table>
+      </div>
+    </div>
+  );
+}
+
+function ExplainSection() {
+  const [eventJson, setEventJson] = useState('');
+  const [direction, setDirection] = useState<'inbound' | 'outbound'>('inbound');
+  const [result, setResult] = useState<Explanation | null>(null);
+  const [error, setError] = useState<string | null>(null);
+
+  const explain = () => {
+    let event: unknown;
+    try {
+      event = JSON.parse(eventJson);
+    } catch {
+      setError('Event is not valid JSON');
+      return;
+    }
+    fetch('/api/filters/explain', {
+      method: 'POST',
+      headers: { 'Content-Type': 'application/json' },
+      body: JSON.stringify({ event, direction })
+    })
+      .then(res => res.ok ? res.json() : res.text().then(text => ({ success: false, error: text })))
+      .then(data => {
+        if (!data.success) { setError(data.error || 'Failed to explain event'); setResult(null); return; }
+        setError(null);
+        setResult(data);
+      });
+  };
+
+  return (
+    <div className="section">
+      <h2>Explain Event</h2>
+      <div className="form-row">
+        <textarea
+          className="wide"
+          rows={4}
+          placeholder='{"id": "...", "pubkey": "...", "kind": 1, ...}'
+          value={eventJson}
+          onChange={e => setEventJson(e.target.value)}
+        />
+        <select value={direction} onChange={e => setDirection(e.target.value as 'inbound' | 'outbound')}>
+          <option value="inbound">Inbound (client → relay)</option>
+          <option value="outbound">Outbound (relay → client)</option>
+        </select>
+        <button onClick={explain}>Explain</button>
+      </div>
+      {error && <div className="empty-state">{error}</div>}
+      {result && (
+        <>
+          <p>
+            Decision: <strong>{result.decision}</strong>{result.reason && ` (${result.reason})`}
+          </p>
+          <ul>
+            <li>Signature: {result.signature_error ? `${result.signature_error}${result.signature_checked ? '' : ' (not checked)'}` : 'valid'}</li>
+            {result.post_allowed !== undefined && result.post_allowed !== null && <li>Post allowed: {result.post_allowed ? 'yes' : 'no'}</li>}
+            <li>Banned npub: {result.banned_npub ? 'yes' : 'no'}</li>
+            <li>Kind blacklisted: {result.kind_blacklisted ? 'yes' : 'no'}</li>
+            {result.bot_filter.applies && <li>Bot filter: {result.bot_filter.filter_bypass ? 'bypassed' : result.bot_filter.matched ? 'matched' : 'no match'}</li>}
+          </ul>
+          <div className="table-container">
+            <table>
+              <thead>
+                <tr><th>Rule</th><th>Type</th><th>Mode</th><th>Result</th><th>Conditions</th></tr>
+              </thead>
+              <tbody>
+                {result.rules.map(rule => (
+                  <tr key={rule.id} style={{ opacity: rule.reached ? 1 : 0.6 }}>
+                    <td style={{ fontWeight: rule.decisive ? 700 : 500 }}>{rule.name}</td>
+                    <td>{rule.rule_type === 'allow' ? 'allow' : rule.action}</td>
+                    <td>{rule.mode}</td>
+                    <td>{!rule.applies ? 'other direction' : rule.decisive ? 'decided' : rule.matched ? (rule.reached ? 'matched (monitor)' : 'matched (not reached)') : 'no match'}</td>
+                    <td>{rule.trace && <ul style={{ margin: 0 }}><TraceView node={rule.trace} /></ul>}</td>
+                  </tr>
+                ))}
+              </tbody>
+            </table>
+          </div>
+        </>
+      )}
     </div>
   );
 }
@@ -1066,16 +1703,18 @@ function LogsSection() {
         <div className="table-container">
           <table>
             <thead>
-              <tr><th>Time</th><th>Reason</th><th>Kind</th><th>Npub</th><th>IP</th></tr>
+              <tr><th>Time</th><th>Reason</th><th>Action</th><th>Direction</th><th>Kind</th><th>Npub</th><th>IP</th></tr>
             </thead>
             <tbody>
               {rejectionLogs.length === 0 ? (
-                <tr><td colSpan={5} className="empty-state">No rejection logs</td></tr>
+                <tr><td colSpan={7} className="empty-state">No rejection logs</td></tr>
               ) : (
                 rejectionLogs.map(log => (
                   <tr key={log.id}>
                     <td style={{ whiteSpace: 'nowrap' }}>{new Date(log.created_at).toLocaleString()}</td>
                     <td><span className="badge badge-danger">{formatReason(log.reason)}</span></td>
+                    <td>{log.action || 'drop'}</td>
+                    <td>{log.direction || '—'}</td>
                     <td style={{ fontFamily: 'monospace' }}>{log.kind}</td>
                     <td className="truncate">{log.npub}</td>
                     <td style={{ fontFamily: 'monospace' }}>{log.ip_address || '—'}</td>